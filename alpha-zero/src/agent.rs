@@ -1,23 +1,48 @@
-use crate::{encode_nn_input, AgentModel, BoardState, EnvTurnMode};
+use crate::{
+    encode_nn_input, BoardState, ChildInfo, EnvTurnMode, InferenceBackend, InferenceError,
+    MCTSExecutor, SearchInfo, StopSignal,
+};
 use atomic_float::AtomicF32;
 use environment::{Environment, GameStatus};
-use mcts::{State, MCTS};
-use parking_lot::RwLock;
-use rand::{distributions::WeightedIndex, prelude::*};
-use std::{iter::once, sync::atomic::Ordering};
-use tensorflow::{Session, Status};
+use mcts::{DumpFormat, DumpOptions, State, MCTS};
+use parking_lot::{Mutex, RwLock};
+use rand::{distributions::WeightedIndex, prelude::*, rngs::StdRng};
+use std::{
+    cmp::Reverse,
+    io::{self, Write},
+    iter::once,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
 
 pub struct Agent {
     pub env: Environment,
     pub mcts: MCTS<BoardState>,
+    // Time spent and nodes expanded by the search since the root node has been set.
+    search_nanos: AtomicU64,
+    search_node_count: AtomicUsize,
+    // Source of randomness for both the search and the action sampling.
+    rng: Mutex<StdRng>,
 }
 
 impl Agent {
-    pub fn new(agent_model: &AgentModel, session: &Session) -> Result<Self, Status> {
+    pub fn new(backend: &dyn InferenceBackend) -> Result<Self, InferenceError> {
+        Self::with_rng(backend, StdRng::from_entropy())
+    }
+
+    /// Creates an agent whose search and action sampling are reproducible for the given seed.
+    /// Note that the search is only reproducible when the [MCTSExecutor] runs on a single thread;
+    /// the [ParallelMCTSExecutor](super::ParallelMCTSExecutor) is reproducible regardless of the number of threads.
+    pub fn with_seed(backend: &dyn InferenceBackend, seed: u64) -> Result<Self, InferenceError> {
+        Self::with_rng(backend, StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(backend: &dyn InferenceBackend, rng: StdRng) -> Result<Self, InferenceError> {
         let env = Environment::new();
 
-        let input = encode_nn_input(1, EnvTurnMode::Player, once(&env));
-        let p = agent_model.evaluate_p(session, input)?;
+        let input = encode_nn_input(backend.features(), 1, EnvTurnMode::Player, once(&env));
+        let p = backend.evaluate_p(1, &input)?;
         let policy = {
             let mut policy = [0f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE];
             policy.copy_from_slice(&p[..]);
@@ -31,7 +56,91 @@ impl Agent {
             z: AtomicF32::new(0f32),
         });
 
-        Ok(Self { env, mcts })
+        Ok(Self {
+            env,
+            mcts,
+            search_nanos: AtomicU64::new(0),
+            search_node_count: AtomicUsize::new(0),
+            rng: Mutex::new(rng),
+        })
+    }
+
+    /// Creates a new random number generator seeded from the agent's one.
+    /// This is used by the MCTS executors, so that each batch owns its generator.
+    pub(crate) fn fork_rng(&self) -> StdRng {
+        StdRng::seed_from_u64(self.rng.lock().gen())
+    }
+
+    /// Records a search performed on the tree. This is called by the MCTS executors.
+    pub(crate) fn record_search(&self, elapsed: Duration, node_count_before: usize) {
+        let expanded = self.mcts.node_count().saturating_sub(node_count_before);
+        self.search_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+        self.search_node_count
+            .fetch_add(expanded, Ordering::Relaxed);
+    }
+
+    /// Collects the statistics of the current tree.
+    /// The search statistics are accumulated until the root node changes by `play_action`.
+    pub fn search_info(&self) -> SearchInfo {
+        let root = self.mcts.root();
+
+        let mut children = root
+            .children
+            .read()
+            .iter()
+            .map(|child| {
+                let n = child.n.load(Ordering::Relaxed);
+                ChildInfo {
+                    action: child.action.unwrap(),
+                    n,
+                    q: if n == 0 {
+                        0f32
+                    } else {
+                        child.w.load(Ordering::Relaxed) / n as f32
+                    },
+                    p: child.p.load(Ordering::Relaxed),
+                }
+            })
+            .collect::<Vec<_>>();
+        children.sort_by_key(|child| Reverse(child.n));
+
+        let mut principal_variation = Vec::new();
+        let mut node = root;
+
+        loop {
+            // The first of the most visited children, as in `children`, on ties.
+            let best = node
+                .children
+                .read()
+                .iter()
+                .copied()
+                .min_by_key(|child| Reverse(child.n.load(Ordering::Relaxed)));
+
+            match best {
+                Some(child) if child.n.load(Ordering::Relaxed) != 0 => {
+                    principal_variation.push(child.action.unwrap());
+                    node = unsafe { &*child.ptr };
+                }
+                _ => break,
+            }
+        }
+
+        let search_secs = self.search_nanos.load(Ordering::Relaxed) as f32 * 1e-9;
+        let nodes_per_second = if search_secs < f32::EPSILON {
+            0f32
+        } else {
+            self.search_node_count.load(Ordering::Relaxed) as f32 / search_secs
+        };
+
+        SearchInfo {
+            children,
+            principal_variation,
+            root_n: root.n.load(Ordering::Relaxed),
+            node_count: self.mcts.node_count(),
+            depth: self.mcts.statistics().max_depth,
+            nodes_per_second,
+        }
     }
 
     /// Computes the policy from MCTS tree.
@@ -76,6 +185,18 @@ impl Agent {
         Some(policy)
     }
 
+    /// Writes the current tree for debugging, with actions in board notation.
+    /// See [MCTS::dump](mcts::MCTS::dump).
+    pub fn dump_tree(
+        &self,
+        dst: impl Write,
+        format: DumpFormat,
+        options: DumpOptions,
+    ) -> io::Result<()> {
+        self.mcts
+            .dump(dst, format, options, Environment::action_to_notation)
+    }
+
     /// Samples a single action from the computed policy and returns the action and the policy.
     /// Returns `None` if the policy is empty.
     /// Note that the policy returned by this function is not affected by the temperature;
@@ -129,7 +250,7 @@ impl Agent {
 
                     let dist = WeightedIndex::new(&heated_policy).unwrap();
 
-                    dist.sample(&mut rand::thread_rng())
+                    dist.sample(&mut *self.rng.lock())
                 }
             },
             policy,
@@ -144,18 +265,29 @@ impl Agent {
     pub fn ensure_action_exists(
         &mut self,
         action: usize,
-        agent_model: &AgentModel,
-        session: &Session,
-    ) -> Result<(), Status> {
+        backend: &dyn InferenceBackend,
+    ) -> Result<(), InferenceError> {
         if Environment::BOARD_SIZE * Environment::BOARD_SIZE <= action {
             return Ok(());
         }
 
+        // Keep the existing subtree, which may have been explored by pondering.
+        if self
+            .mcts
+            .root()
+            .children
+            .read()
+            .iter()
+            .any(|child| child.action == Some(action))
+        {
+            return Ok(());
+        }
+
         let mut env = self.env.clone();
         env.place_stone(action);
 
-        let input = encode_nn_input(1, EnvTurnMode::Opponent, once(&env));
-        let p = agent_model.evaluate_p(session, input)?;
+        let input = encode_nn_input(backend.features(), 1, EnvTurnMode::Opponent, once(&env));
+        let p = backend.evaluate_p(1, &input)?;
         let mut policy = {
             let mut policy = [0f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE];
             policy.copy_from_slice(&p[..]);
@@ -196,6 +328,38 @@ impl Agent {
         Ok(())
     }
 
+    /// Keeps searching the current tree in the background while `wait` blocks,
+    /// e.g. while waiting for the opponent's move, and returns the value returned by `wait`.
+    /// The search is stopped as soon as `wait` returns, or after `max_count` simulations.
+    ///
+    /// The explored subtree is reused once the opponent's action is played with
+    /// `ensure_action_exists` and `play_action`.
+    /// To control the search manually, use [MCTSExecutor::run_until_stopped] with a [StopSignal].
+    pub fn ponder<T>(
+        &self,
+        mcts_executor: &MCTSExecutor,
+        max_count: usize,
+        batch_size: usize,
+        backend: &dyn InferenceBackend,
+        wait: impl FnOnce() -> T,
+    ) -> Result<T, InferenceError> {
+        let signal = StopSignal::new();
+
+        thread::scope(|scope| {
+            let search = scope.spawn(|| {
+                mcts_executor.run_until_stopped(max_count, batch_size, backend, self, &signal)
+            });
+
+            // Stops the search even if `wait` panics, as the scope joins the search thread before unwinding.
+            let guard = StopOnDrop(&signal);
+            let result = wait();
+            drop(guard);
+
+            search.join().unwrap()?;
+            Ok(result)
+        })
+    }
+
     /// Plays a single action and returns the game status.
     /// Returns `None` if:
     /// - The action is illegal.
@@ -228,10 +392,21 @@ impl Agent {
         };
 
         self.mcts.transition(children_index);
+        self.search_nanos.store(0, Ordering::Relaxed);
+        self.search_node_count.store(0, Ordering::Relaxed);
         Some(status)
     }
 }
 
+/// Stops the signal when dropped.
+struct StopOnDrop<'a>(&'a StopSignal);
+
+impl<'a> Drop for StopOnDrop<'a> {
+    fn drop(&mut self) {
+        self.0.stop();
+    }
+}
+
 /// A method to sample actions from the policy.
 pub enum ActionSamplingMode {
     /// Selects the action with the highest probability.
@@ -239,3 +414,107 @@ pub enum ActionSamplingMode {
     /// Selects the action using Boltzmann distribution with the given temperature.
     Boltzmann(f32),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        test_checkpoint::{test_networks, zero_checkpoint},
+        CpuBackend,
+    };
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[test]
+    fn test_search_info() {
+        let backend = CpuBackend::from_checkpoint(zero_checkpoint(test_networks()[1])).unwrap();
+        let agent = Agent::with_seed(&backend, 42).unwrap();
+
+        // Enough simulations to expand every root child and then to search below some of them.
+        let count = Environment::BOARD_SIZE * Environment::BOARD_SIZE + 31;
+        MCTSExecutor::with_num_threads(1)
+            .run(count, 8, 0f32, 1f32, &backend, &agent)
+            .unwrap();
+
+        let info = agent.search_info();
+        assert_eq!(info.root_n, count as u64);
+        assert_eq!(info.node_count, count + 1);
+        assert_eq!(
+            info.children.len(),
+            Environment::BOARD_SIZE * Environment::BOARD_SIZE
+        );
+        assert_eq!(
+            info.children.iter().map(|child| child.n).sum::<u64>(),
+            info.root_n
+        );
+        assert!(info
+            .children
+            .windows(2)
+            .all(|children| children[1].n <= children[0].n));
+
+        let best = info.best_child().unwrap();
+        assert!(2 <= best.n);
+        assert_eq!(info.principal_variation.first(), Some(&best.action));
+        assert!(2 <= info.principal_variation.len());
+        assert!(info.principal_variation.len() <= info.depth);
+
+        let mut env = agent.env.clone();
+
+        for &action in &info.principal_variation {
+            assert!(env.is_legal_move(action));
+            env.place_stone(action);
+        }
+    }
+
+    #[test]
+    fn test_ponder_stops_when_wait_panics() {
+        let backend = CpuBackend::from_checkpoint(zero_checkpoint(test_networks()[1])).unwrap();
+        let agent = Agent::with_seed(&backend, 42).unwrap();
+        let mcts_executor = MCTSExecutor::with_num_threads(1);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            agent.ponder(&mcts_executor, usize::MAX, 8, &backend, || {
+                panic!("the opponent disconnected")
+            })
+        }));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_with_seed() {
+        let backend = CpuBackend::from_checkpoint(zero_checkpoint(test_networks()[1])).unwrap();
+
+        // Searches with noise and samples a few moves, returning the visit counts and the actions.
+        let play = |seed| {
+            let mut agent = Agent::with_seed(&backend, seed).unwrap();
+            let mut visits = Vec::new();
+            let mut actions = Vec::new();
+
+            for _ in 0..3 {
+                MCTSExecutor::with_num_threads(1)
+                    .run(400, 8, 0.25f32, 0.03f32, &backend, &agent)
+                    .unwrap();
+                visits.push(
+                    agent
+                        .search_info()
+                        .children
+                        .iter()
+                        .map(|child| (child.action, child.n))
+                        .collect::<Vec<_>>(),
+                );
+
+                let (action, _) = agent
+                    .sample_action(ActionSamplingMode::Boltzmann(1f32))
+                    .unwrap();
+                agent.play_action(action).unwrap();
+                actions.push(action);
+            }
+
+            (visits, actions)
+        };
+
+        let (visits, actions) = play(42);
+        assert_eq!(play(42), (visits.clone(), actions.clone()));
+        assert_ne!(play(43), (visits, actions));
+    }
+}
@@ -1,9 +1,11 @@
-use super::{ModelIO, Network};
+use super::{ModelIO, Network, NetworkConfig, OptimizerConfig};
 use environment::Environment;
 use tensorflow::{
-    ops::{add, constant, mean, reshape, square, sub, Placeholder},
-    train::{AdadeltaOptimizer, MinimizeOptions, Optimizer},
-    DataType, Operation, Scope, Session, SessionRunArgs, Status, Tensor, Variable,
+    ops::{
+        constant, l2_loss, mean, mul, reshape, softmax_cross_entropy_with_logits, square, sub,
+        AddN, Placeholder,
+    },
+    DataType, Operation, Scope, Session, SessionRunArgs, Shape, Status, Tensor, Variable,
 };
 
 pub struct AgentModel {
@@ -14,16 +16,35 @@ pub struct AgentModel {
     pub op_v_loss: Operation,
     pub op_pi_input: Operation,
     pub op_p_loss: Operation,
+    /// L2 regularization of the network weights, already scaled by its coefficient.
+    pub op_l2_loss: Operation,
+    /// Target of the reply policy head, if the network has one.
+    pub op_reply_pi_input: Option<Operation>,
+    /// Loss of the reply policy head, already scaled by its weight.
+    pub op_reply_p_loss: Option<Operation>,
+    /// Target of the ownership head, if the network has one.
+    pub op_ownership_input: Option<Operation>,
+    /// Loss of the ownership head, already scaled by its weight.
+    pub op_ownership_loss: Option<Operation>,
     pub op_loss: Operation,
+    /// Float scalar holding the training step, which drives the learning rate schedule.
+    pub op_step: Operation,
+    pub op_learning_rate: Operation,
     pub op_minimize: Operation,
+    /// See [Network::op_training].
+    pub op_training: Operation,
+    /// See [Network::op_update_moving_averages].
+    pub op_update_moving_averages: Operation,
     pub variables: Vec<Variable>,
     pub io: ModelIO,
 }
 
 impl AgentModel {
-    pub const LEARNING_RATE: f32 = 0.01;
-
-    pub fn new(scope: &mut Scope) -> Result<Self, Status> {
+    pub fn new(
+        network_config: NetworkConfig,
+        optimizer_config: OptimizerConfig,
+        scope: &mut Scope,
+    ) -> Result<Self, Status> {
         let op_pi_input = Placeholder::new()
             .dtype(DataType::Float)
             .shape([
@@ -45,6 +66,7 @@ impl AgentModel {
         )?;
 
         let network = Network::new(
+            network_config,
             op_pi_input_flatten,
             scope,
             "input",
@@ -66,22 +88,118 @@ impl AgentModel {
             &mut scope.with_op_name("v_loss"),
         )?;
 
-        let op_loss = add(
+        // Only the weights are regularized; biases and batch normalization parameters are vectors.
+        let mut weight_l2_losses = Vec::new();
+
+        for variable in &network.trainable_variables {
+            if 1 < variable.shape().dims().unwrap_or(0) {
+                weight_l2_losses.push(l2_loss(variable.output().clone(), scope)?);
+            }
+        }
+
+        let op_l2_loss = mul(
+            constant(optimizer_config.l2_regularization, scope)?,
+            add_n(weight_l2_losses, scope)?,
+            &mut scope.with_op_name("l2_loss"),
+        )?;
+
+        let mut losses = vec![
             op_v_loss.clone(),
             network.op_p_loss.clone(),
-            &mut scope.with_op_name("loss"),
-        )?;
+            op_l2_loss.clone(),
+        ];
 
-        let mut optimizer = AdadeltaOptimizer::new();
-        optimizer.set_learning_rate(constant(Self::LEARNING_RATE, scope)?);
+        let (op_reply_pi_input, op_reply_p_loss) = match &network.op_reply_p_logits {
+            Some(reply_p_logits) => {
+                let op_reply_pi_input = Placeholder::new()
+                    .dtype(DataType::Float)
+                    .shape([
+                        -1,
+                        Environment::BOARD_SIZE as i64,
+                        Environment::BOARD_SIZE as i64,
+                    ])
+                    .build(&mut scope.with_op_name("reply_pi_input"))?;
+                let op_reply_pi_input_flatten = reshape(
+                    op_reply_pi_input.clone(),
+                    constant(
+                        &[
+                            -1,
+                            Environment::BOARD_SIZE as i64 * Environment::BOARD_SIZE as i64,
+                        ],
+                        scope,
+                    )?,
+                    scope,
+                )?;
+                let op_reply_p_loss = mul(
+                    constant(optimizer_config.reply_policy_loss_weight, scope)?,
+                    mean(
+                        softmax_cross_entropy_with_logits(
+                            reply_p_logits.clone(),
+                            op_reply_pi_input_flatten,
+                            scope,
+                        )?,
+                        constant(&[0], scope)?,
+                        scope,
+                    )?,
+                    &mut scope.with_op_name("reply_p_loss"),
+                )?;
+                losses.push(op_reply_p_loss.clone());
+
+                (Some(op_reply_pi_input), Some(op_reply_p_loss))
+            }
+            None => (None, None),
+        };
+
+        let (op_ownership_input, op_ownership_loss) = match &network.op_ownership_output {
+            Some(ownership_output) => {
+                let op_ownership_input = Placeholder::new()
+                    .dtype(DataType::Float)
+                    .shape([
+                        -1,
+                        Environment::BOARD_SIZE as i64,
+                        Environment::BOARD_SIZE as i64,
+                    ])
+                    .build(&mut scope.with_op_name("ownership_input"))?;
+                let op_ownership_loss = mul(
+                    constant(optimizer_config.ownership_loss_weight, scope)?,
+                    mean(
+                        square(
+                            sub(op_ownership_input.clone(), ownership_output.clone(), scope)?,
+                            scope,
+                        )?,
+                        constant(&[0, 1, 2], scope)?,
+                        scope,
+                    )?,
+                    &mut scope.with_op_name("ownership_loss"),
+                )?;
+                losses.push(op_ownership_loss.clone());
+
+                (Some(op_ownership_input), Some(op_ownership_loss))
+            }
+            None => (None, None),
+        };
+
+        let op_loss = add_n(losses, &mut scope.with_op_name("loss"))?;
+
+        let op_step = Placeholder::new()
+            .dtype(DataType::Float)
+            .shape(Shape::from(Some(vec![])))
+            .build(&mut scope.with_op_name("step"))?;
+        let op_learning_rate = optimizer_config.build_learning_rate(op_step.clone(), scope)?;
 
-        let (optimizer_vars, op_minimize) = optimizer.minimize(
+        let (optimizer_vars, op_minimize) = optimizer_config.minimize(
+            op_loss.clone(),
+            op_learning_rate.clone(),
+            &network.trainable_variables,
             scope,
-            op_loss.output(0),
-            MinimizeOptions::default().with_variables(&network.variables),
         )?;
 
-        let io = ModelIO::new(network.variables.clone(), scope)?;
+        let io = ModelIO::new(
+            network.config,
+            network.variables.clone(),
+            optimizer_vars.clone(),
+            scope,
+        )?;
 
         let mut variables = Vec::new();
         variables.extend(network.variables);
@@ -95,8 +213,17 @@ impl AgentModel {
             op_v_loss,
             op_pi_input,
             op_p_loss: network.op_p_loss,
+            op_l2_loss,
+            op_reply_pi_input,
+            op_reply_p_loss,
+            op_ownership_input,
+            op_ownership_loss,
             op_loss,
+            op_step,
+            op_learning_rate,
             op_minimize,
+            op_training: network.op_training,
+            op_update_moving_averages: network.op_update_moving_averages,
             variables,
             io,
         })
@@ -133,24 +260,54 @@ impl AgentModel {
         ))
     }
 
+    /// Performs a parameter update at the given training step, returning the policy, value and total losses.
+    /// The targets of the auxiliary heads are ignored if the network does not have them.
     pub fn train(
         &self,
         session: &Session,
+        step: u64,
         input: Tensor<f32>,
         policy_target: Tensor<f32>,
         value_target: Tensor<f32>,
+        reply_policy_target: Tensor<f32>,
+        ownership_target: Tensor<f32>,
     ) -> Result<(f32, f32, f32), Status> {
+        let training = Tensor::from(true);
+        let step = Tensor::from(step as f32);
+
         let mut run_args = SessionRunArgs::new();
         run_args.add_feed(&self.op_input, 0, &input);
         run_args.add_feed(&self.op_pi_input, 0, &policy_target);
         run_args.add_feed(&self.op_z_input, 0, &value_target);
+        run_args.add_feed(&self.op_training, 0, &training);
+
+        if let Some(op_reply_pi_input) = &self.op_reply_pi_input {
+            run_args.add_feed(op_reply_pi_input, 0, &reply_policy_target);
+        }
+
+        if let Some(op_ownership_input) = &self.op_ownership_input {
+            run_args.add_feed(op_ownership_input, 0, &ownership_target);
+        }
+
+        run_args.add_feed(&self.op_step, 0, &step);
         run_args.add_target(&self.op_minimize);
+        run_args.add_target(&self.op_update_moving_averages);
         session.run(&mut run_args)?;
 
         let mut run_args = SessionRunArgs::new();
         run_args.add_feed(&self.op_input, 0, &input);
         run_args.add_feed(&self.op_pi_input, 0, &policy_target);
         run_args.add_feed(&self.op_z_input, 0, &value_target);
+        run_args.add_feed(&self.op_training, 0, &training);
+
+        if let Some(op_reply_pi_input) = &self.op_reply_pi_input {
+            run_args.add_feed(op_reply_pi_input, 0, &reply_policy_target);
+        }
+
+        if let Some(op_ownership_input) = &self.op_ownership_input {
+            run_args.add_feed(op_ownership_input, 0, &ownership_target);
+        }
+
         run_args.add_target(&self.op_p_loss);
         run_args.add_target(&self.op_v_loss);
         run_args.add_target(&self.op_loss);
@@ -170,3 +327,10 @@ impl AgentModel {
 
 unsafe impl Send for AgentModel {}
 unsafe impl Sync for AgentModel {}
+
+/// Sums the given tensors, which must have the same shape.
+fn add_n(inputs: Vec<Operation>, scope: &mut Scope) -> Result<Operation, Status> {
+    Ok(AddN::new()
+        .build_instance(inputs.into_iter().map(Into::into).collect(), scope)?
+        .into())
+}
@@ -0,0 +1,369 @@
+use crate::{checkpoint_store::temporary_path, InferenceError, NetworkConfig};
+use bincode::{deserialize, deserialize_from, serialize_into};
+use environment::Environment;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{rename, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ModelIOError {
+    #[cfg(feature = "tensorflow")]
+    #[error("Tensorflow error: {0}")]
+    Tensorflow(#[from] tensorflow::Status),
+    #[error("Inference error: {0}")]
+    Inference(#[from] InferenceError),
+    #[error("IO error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("Bincode error: {0}")]
+    Bincode(#[from] bincode::Error),
+    #[error("Unsupported checkpoint format version {found}; up to {supported} is supported")]
+    UnsupportedFormatVersion { found: u32, supported: u32 },
+    #[error("Board size mismatch: expected {expected}, found {found}")]
+    BoardSizeMismatch { expected: usize, found: usize },
+    #[error("Rule set mismatch: expected {expected}, found {found}")]
+    RuleSetMismatch { expected: String, found: String },
+    #[error("Input channels mismatch: expected {expected}, found {found}")]
+    InputChannelsMismatch { expected: i64, found: i64 },
+    #[error("Network architecture mismatch: expected {expected:?}, found {found:?}")]
+    ArchitectureMismatch {
+        expected: Box<NetworkConfig>,
+        found: Box<NetworkConfig>,
+    },
+    #[error("Variable count mismatch: expected {expected}, found {found}")]
+    VariableCountMismatch { expected: usize, found: usize },
+    #[error("Variable {0} is missing from the checkpoint")]
+    MissingVariable(String),
+    #[error("Variable {0} in the checkpoint does not exist in the model")]
+    UnknownVariable(String),
+    #[error("Shape mismatch of variable {name}: expected {expected:?}, found {found:?}")]
+    ShapeMismatch {
+        name: String,
+        expected: Vec<u64>,
+        found: Vec<u64>,
+    },
+    #[error("Checkpoint pointer {path:?} does not name a checkpoint: {contents:?}")]
+    InvalidCheckpointPointer { path: PathBuf, contents: String },
+    #[error("Checkpoint store {0:?} has no checkpoint")]
+    EmptyCheckpointStore(PathBuf),
+}
+
+/// Describes the model stored in a [Checkpoint].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointHeader {
+    /// Version of the file format. Files written before the format was versioned have version 0.
+    pub format_version: u32,
+    pub board_size: usize,
+    pub input_channels: i64,
+    /// Stored as TOML, so that fields added to [NetworkConfig] later take their defaults when reading this header.
+    #[serde(with = "network_toml")]
+    pub network: NetworkConfig,
+    /// See [Environment::RULE_SET].
+    pub rule_set: String,
+    /// Number of parameter updates performed on the model, i.e. the global step of the training.
+    pub training_step: u64,
+    /// Seconds since the Unix epoch at which the checkpoint has been written.
+    pub timestamp: u64,
+}
+
+impl CheckpointHeader {
+    /// Creates a header describing a network of the given architecture, written now.
+    pub fn new(network: NetworkConfig, training_step: u64) -> Self {
+        Self {
+            format_version: Checkpoint::FORMAT_VERSION,
+            board_size: Environment::BOARD_SIZE,
+            input_channels: network.input_channels(),
+            network,
+            rule_set: Environment::RULE_SET.to_owned(),
+            training_step,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0),
+        }
+    }
+
+    /// Checks that the checkpoint has been written for the current format and board, and that its input channels match its features.
+    /// The architecture is checked separately by [CheckpointHeader::check_network], as [CpuBackend](crate::CpuBackend) follows the stored one.
+    pub fn check_compatible(&self) -> Result<(), ModelIOError> {
+        if Checkpoint::FORMAT_VERSION < self.format_version {
+            return Err(ModelIOError::UnsupportedFormatVersion {
+                found: self.format_version,
+                supported: Checkpoint::FORMAT_VERSION,
+            });
+        }
+
+        if self.board_size != Environment::BOARD_SIZE {
+            return Err(ModelIOError::BoardSizeMismatch {
+                expected: Environment::BOARD_SIZE,
+                found: self.board_size,
+            });
+        }
+
+        if self.rule_set != Environment::RULE_SET {
+            return Err(ModelIOError::RuleSetMismatch {
+                expected: Environment::RULE_SET.to_owned(),
+                found: self.rule_set.clone(),
+            });
+        }
+
+        if self.input_channels != self.network.input_channels() {
+            return Err(ModelIOError::InputChannelsMismatch {
+                expected: self.network.input_channels(),
+                found: self.input_channels,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the checkpoint has been written by a network of the given architecture.
+    pub fn check_network(&self, network: &NetworkConfig) -> Result<(), ModelIOError> {
+        if &self.network != network {
+            return Err(ModelIOError::ArchitectureMismatch {
+                expected: Box::new(*network),
+                found: Box::new(self.network),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+mod network_toml {
+    use crate::NetworkConfig;
+    use serde::{de::Error as _, ser::Error as _, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        network: &NetworkConfig,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&toml::to_string(network).map_err(S::Error::custom)?)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<NetworkConfig, D::Error> {
+        toml::from_str(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
+
+/// A saved variable of the network.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedVariable {
+    pub name: String,
+    /// Shape of the variable. Files of format version 0 do not store shapes, so it is `[values.len()]`.
+    pub shape: Vec<u64>,
+    pub values: Vec<f32>,
+}
+
+impl SavedVariable {
+    /// Checks that the variable has the given shape.
+    /// Only the number of values is checked for format version 0, which does not store shapes.
+    pub fn check_shape(&self, format_version: u32, shape: &[u64]) -> Result<(), ModelIOError> {
+        let matches = if format_version == 0 {
+            self.values.len() as u64 == shape.iter().product::<u64>()
+        } else {
+            self.shape == shape && self.values.len() as u64 == shape.iter().product::<u64>()
+        };
+
+        if matches {
+            Ok(())
+        } else {
+            Err(ModelIOError::ShapeMismatch {
+                name: self.name.clone(),
+                expected: shape.to_vec(),
+                found: self.shape.clone(),
+            })
+        }
+    }
+}
+
+/// Parameters of the network along with a header describing them.
+///
+/// The file starts with [Checkpoint::MAGIC], followed by the header, the variables and the optimizer variables, all encoded with bincode.
+/// Files without the magic are read as format version 0, which is a bare list of names and values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub header: CheckpointHeader,
+    pub variables: Vec<SavedVariable>,
+    /// Slots of the optimizer. This is empty if the optimizer state has not been saved.
+    pub optimizer_variables: Vec<SavedVariable>,
+}
+
+/// The unversioned format written before [Checkpoint] has been introduced.
+#[derive(Serialize, Deserialize)]
+struct SavedData {
+    variable_names: Vec<String>,
+    parameters: Vec<Vec<f32>>,
+}
+
+impl Checkpoint {
+    pub const MAGIC: [u8; 8] = *b"OMOKCKPT";
+    pub const FORMAT_VERSION: u32 = 1;
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, ModelIOError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        if !bytes.starts_with(&Self::MAGIC) {
+            return Self::read_unversioned(&bytes);
+        }
+
+        let mut reader = &bytes[Self::MAGIC.len()..];
+        // The header starts with the format version. Reject newer files before decoding the rest, whose layout may have changed.
+        let format_version: u32 = deserialize(reader)?;
+
+        if Self::FORMAT_VERSION < format_version {
+            return Err(ModelIOError::UnsupportedFormatVersion {
+                found: format_version,
+                supported: Self::FORMAT_VERSION,
+            });
+        }
+
+        let header = deserialize_from(&mut reader)?;
+        let variables = deserialize_from(&mut reader)?;
+        let optimizer_variables = deserialize_from(&mut reader)?;
+
+        Ok(Self {
+            header,
+            variables,
+            optimizer_variables,
+        })
+    }
+
+    fn read_unversioned(bytes: &[u8]) -> Result<Self, ModelIOError> {
+        let saved_data: SavedData = deserialize_from(bytes)?;
+
+        // The unversioned format has been written by the default network only.
+        let header = CheckpointHeader {
+            format_version: 0,
+            training_step: 0,
+            timestamp: 0,
+            ..CheckpointHeader::new(NetworkConfig::default(), 0)
+        };
+        let variables = saved_data
+            .variable_names
+            .into_iter()
+            .zip(saved_data.parameters)
+            .map(|(name, values)| SavedVariable {
+                name,
+                shape: vec![values.len() as u64],
+                values,
+            })
+            .collect();
+
+        Ok(Self {
+            header,
+            variables,
+            optimizer_variables: Vec::new(),
+        })
+    }
+
+    /// Writes the checkpoint to a temporary file which then replaces `path`, so that readers never see a partial file.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), ModelIOError> {
+        let path = path.as_ref();
+        let temporary_path = temporary_path(path);
+        let mut writer = BufWriter::new(File::create(&temporary_path)?);
+        writer.write_all(&Self::MAGIC)?;
+        serialize_into(&mut writer, &self.header)?;
+        serialize_into(&mut writer, &self.variables)?;
+        serialize_into(&mut writer, &self.optimizer_variables)?;
+        writer.flush()?;
+        drop(writer);
+        rename(&temporary_path, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{BlockType, FeatureConfig};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let checkpoint = Checkpoint {
+            header: CheckpointHeader::new(
+                NetworkConfig {
+                    features: FeatureConfig {
+                        color_to_move: true,
+                        last_moves: 2,
+                        forbidden_points: false,
+                    },
+                    block_type: BlockType::Residual,
+                    batch_norm: true,
+                    ..Default::default()
+                },
+                42,
+            ),
+            variables: vec![SavedVariable {
+                name: "conv_w".to_owned(),
+                shape: vec![2, 3],
+                values: vec![1f32, 2f32, 3f32, 4f32, 5f32, 6f32],
+            }],
+            optimizer_variables: vec![SavedVariable {
+                name: "conv_w/accum".to_owned(),
+                shape: vec![2, 3],
+                values: vec![0f32; 6],
+            }],
+        };
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("checkpoint");
+        checkpoint.write(&path).unwrap();
+
+        let read = Checkpoint::read(&path).unwrap();
+        assert_eq!(read, checkpoint);
+        assert!(read.header.check_compatible().is_ok());
+        assert!(read.variables[0].check_shape(1, &[2, 3]).is_ok());
+        assert!(matches!(
+            read.variables[0].check_shape(1, &[3, 2]),
+            Err(ModelIOError::ShapeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_read_unversioned() {
+        let saved_data = SavedData {
+            variable_names: vec!["conv_w".to_owned()],
+            parameters: vec![vec![1f32, 2f32, 3f32, 4f32, 5f32, 6f32]],
+        };
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("checkpoint");
+        serialize_into(File::create(&path).unwrap(), &saved_data).unwrap();
+
+        let read = Checkpoint::read(&path).unwrap();
+        assert_eq!(read.header.format_version, 0);
+        assert!(read.optimizer_variables.is_empty());
+        assert_eq!(read.variables[0].name, "conv_w");
+        // Only the number of values can be checked.
+        assert!(read.variables[0].check_shape(0, &[3, 2]).is_ok());
+        assert!(read.variables[0].check_shape(0, &[4, 2]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_incompatible_header() {
+        let header = CheckpointHeader::new(NetworkConfig::default(), 0);
+        let mut network = NetworkConfig::default();
+        network.block_count += 1;
+        assert!(matches!(
+            header.check_network(&network),
+            Err(ModelIOError::ArchitectureMismatch { .. })
+        ));
+
+        let mut header = CheckpointHeader::new(NetworkConfig::default(), 0);
+        header.format_version = Checkpoint::FORMAT_VERSION + 1;
+        assert!(matches!(
+            header.check_compatible(),
+            Err(ModelIOError::UnsupportedFormatVersion { .. })
+        ));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::ModelIOError;
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsString,
+    fs::{create_dir_all, read_dir, read_to_string, remove_file, rename, write},
+    path::{Path, PathBuf},
+};
+
+/// Which checkpoints of a [CheckpointStore] to keep. The latest and the best checkpoints are always kept.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionPolicy {
+    /// Number of most recent checkpoints to keep.
+    pub keep_last: usize,
+    /// Keeps the checkpoints of the iterations which are multiples of it, as a history of the training. None are kept if it is 0.
+    pub keep_every: u64,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            keep_last: 5,
+            keep_every: 10,
+        }
+    }
+}
+
+/// Numbered checkpoints of a training run, `iter-{iteration:06}.bin` in one directory,
+/// with the `latest` and `best` files holding the names of the most recent and the best checkpoints.
+///
+/// The checkpoints and the pointers are written to temporary files which then replace them,
+/// so that other processes never read a partial file.
+pub struct CheckpointStore {
+    pub path: PathBuf,
+}
+
+impl CheckpointStore {
+    pub const LATEST: &str = "latest";
+    pub const BEST: &str = "best";
+
+    /// Opens the store at `path`, creating the directory if needed.
+    /// A single checkpoint file written at `path` by earlier versions is moved into the store as iteration 0, which is both the latest and the best.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ModelIOError> {
+        let path = path.as_ref().to_path_buf();
+        let store = Self { path };
+
+        if store.path.is_file() {
+            let legacy_path = temporary_path(&store.path);
+            rename(&store.path, &legacy_path)?;
+            create_dir_all(&store.path)?;
+            rename(&legacy_path, store.checkpoint_path(0))?;
+            store.set_latest(0)?;
+            store.set_best(0)?;
+        } else {
+            create_dir_all(&store.path)?;
+        }
+
+        Ok(store)
+    }
+
+    /// Resolves `path` to a checkpoint file: the best checkpoint if it is a store, or its latest one if none is the best yet.
+    /// Any other path is returned as is.
+    pub fn resolve(path: impl AsRef<Path>) -> Result<PathBuf, ModelIOError> {
+        let path = path.as_ref();
+
+        if !path.is_dir() {
+            return Ok(path.to_path_buf());
+        }
+
+        let store = Self {
+            path: path.to_path_buf(),
+        };
+
+        match store.best()?.or(store.latest()?) {
+            Some(iteration) => Ok(store.checkpoint_path(iteration)),
+            None => Err(ModelIOError::EmptyCheckpointStore(path.to_path_buf())),
+        }
+    }
+
+    pub fn checkpoint_path(&self, iteration: u64) -> PathBuf {
+        self.path.join(checkpoint_name(iteration))
+    }
+
+    /// Returns the iterations of the checkpoints in the store, in increasing order.
+    pub fn iterations(&self) -> Result<Vec<u64>, ModelIOError> {
+        let mut iterations = Vec::new();
+
+        for entry in read_dir(&self.path)? {
+            if let Some(iteration) = entry?.file_name().to_str().and_then(parse_checkpoint_name) {
+                iterations.push(iteration);
+            }
+        }
+
+        iterations.sort_unstable();
+
+        Ok(iterations)
+    }
+
+    pub fn latest(&self) -> Result<Option<u64>, ModelIOError> {
+        self.read_pointer(Self::LATEST)
+    }
+
+    pub fn best(&self) -> Result<Option<u64>, ModelIOError> {
+        self.read_pointer(Self::BEST)
+    }
+
+    pub fn set_latest(&self, iteration: u64) -> Result<(), ModelIOError> {
+        self.write_pointer(Self::LATEST, iteration)
+    }
+
+    pub fn set_best(&self, iteration: u64) -> Result<(), ModelIOError> {
+        self.write_pointer(Self::BEST, iteration)
+    }
+
+    /// Deletes the checkpoints which the policy does not keep, returning their iterations.
+    pub fn retain(&self, policy: &RetentionPolicy) -> Result<Vec<u64>, ModelIOError> {
+        let iterations = self.iterations()?;
+        let latest = self.latest()?;
+        let best = self.best()?;
+        let recent = iterations.len().saturating_sub(policy.keep_last);
+        let mut deleted = Vec::new();
+
+        for (index, &iteration) in iterations.iter().enumerate() {
+            let is_kept = recent <= index
+                || (policy.keep_every != 0 && iteration % policy.keep_every == 0)
+                || Some(iteration) == latest
+                || Some(iteration) == best;
+
+            if !is_kept {
+                remove_file(self.checkpoint_path(iteration))?;
+                deleted.push(iteration);
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    fn read_pointer(&self, name: &str) -> Result<Option<u64>, ModelIOError> {
+        let path = self.path.join(name);
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = read_to_string(&path)?;
+
+        match parse_checkpoint_name(contents.trim()) {
+            Some(iteration) => Ok(Some(iteration)),
+            None => Err(ModelIOError::InvalidCheckpointPointer { path, contents }),
+        }
+    }
+
+    fn write_pointer(&self, name: &str, iteration: u64) -> Result<(), ModelIOError> {
+        let path = self.path.join(name);
+        let temporary_path = temporary_path(&path);
+        write(&temporary_path, checkpoint_name(iteration))?;
+        rename(&temporary_path, &path)?;
+        Ok(())
+    }
+}
+
+fn checkpoint_name(iteration: u64) -> String {
+    format!("iter-{:06}.bin", iteration)
+}
+
+fn parse_checkpoint_name(name: &str) -> Option<u64> {
+    name.strip_prefix("iter-")?
+        .strip_suffix(".bin")?
+        .parse()
+        .ok()
+}
+
+/// The path of the temporary file written before replacing `path`.
+pub(crate) fn temporary_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{test_checkpoint::zero_checkpoint, Checkpoint, NetworkConfig};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_checkpoint_store() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("model");
+        let store = CheckpointStore::open(&path).unwrap();
+        let checkpoint = zero_checkpoint(NetworkConfig::default());
+
+        assert_eq!(store.latest().unwrap(), None);
+        assert!(CheckpointStore::resolve(&path).is_err());
+
+        for iteration in 1..=12 {
+            checkpoint.write(store.checkpoint_path(iteration)).unwrap();
+            store.set_latest(iteration).unwrap();
+        }
+
+        store.set_best(3).unwrap();
+        assert_eq!(store.latest().unwrap(), Some(12));
+        assert_eq!(
+            CheckpointStore::resolve(&path).unwrap(),
+            store.checkpoint_path(3)
+        );
+
+        let deleted = store
+            .retain(&RetentionPolicy {
+                keep_last: 2,
+                keep_every: 5,
+            })
+            .unwrap();
+        assert_eq!(deleted, [1, 2, 4, 6, 7, 8, 9]);
+        assert_eq!(store.iterations().unwrap(), [3, 5, 10, 11, 12]);
+    }
+
+    #[test]
+    fn test_checkpoint_store_legacy_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("model");
+        let checkpoint = zero_checkpoint(NetworkConfig::default());
+        checkpoint.write(&path).unwrap();
+
+        let store = CheckpointStore::open(&path).unwrap();
+        assert_eq!(store.latest().unwrap(), Some(0));
+        assert_eq!(store.best().unwrap(), Some(0));
+
+        let read = Checkpoint::read(CheckpointStore::resolve(&path).unwrap()).unwrap();
+        assert_eq!(read, checkpoint);
+    }
+}
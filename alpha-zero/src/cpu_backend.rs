@@ -0,0 +1,993 @@
+use crate::{
+    inference_backend::check_input_size, BlockType, Checkpoint, CheckpointStore, FeatureConfig,
+    InferenceBackend, InferenceError, ModelIOError, NetworkConfig, SavedVariable,
+};
+use environment::Environment;
+use network_utils::{Activation, BATCH_NORM_EPSILON};
+use std::path::Path;
+
+const SIZE: usize = Environment::BOARD_SIZE;
+
+/// Evaluates the network on the CPU without TensorFlow.
+/// It loads the parameters saved by `ModelIO::save` and mirrors the graph built by `Network::new`
+/// with the architecture stored in the checkpoint,
+/// so it can be used for inference, e.g. in the GUI or the benchmark, but not for training.
+pub struct CpuBackend {
+    pub(crate) network: NetworkConfig,
+    pub(crate) conv: Conv2D,
+    pub(crate) conv_bn: Option<BatchNorm>,
+    pub(crate) blocks: Vec<Block>,
+    pub(crate) v_conv: Conv2D,
+    pub(crate) v_conv_bn: Option<BatchNorm>,
+    pub(crate) v_fc0: Fc,
+    /// Output layer of the value head if it has a hidden layer.
+    pub(crate) v_fc1: Option<Fc>,
+    pub(crate) p_conv: Conv2D,
+    pub(crate) p_conv_bn: Option<BatchNorm>,
+    pub(crate) p_fc0: Fc,
+}
+
+impl CpuBackend {
+    /// Slope of the negative part of the leaky ReLU, which is the default of TensorFlow's `LeakyRelu`.
+    pub const LEAKY_RELU_ALPHA: f32 = 0.2;
+
+    /// Loads a checkpoint file, or the best checkpoint of a [CheckpointStore] directory.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ModelIOError> {
+        Self::from_checkpoint(Checkpoint::read(CheckpointStore::resolve(path)?)?)
+    }
+
+    /// Builds the network from the checkpoint, whose variables are in the order of `Network::variables`.
+    pub fn from_checkpoint(checkpoint: Checkpoint) -> Result<Self, ModelIOError> {
+        checkpoint.header.check_compatible()?;
+
+        let network = checkpoint.header.network;
+        let mut parameters = Parameters {
+            format_version: checkpoint.header.format_version,
+            variables: checkpoint.variables.into_iter(),
+            index: 0,
+        };
+
+        let channels = network.channels as usize;
+
+        let conv = Conv2D::take(
+            &mut parameters,
+            1,
+            network.input_channels() as usize,
+            channels,
+        )?;
+        let conv_bn = BatchNorm::take_if(&mut parameters, &network, channels)?;
+
+        let mut blocks = Vec::with_capacity(network.block_count as usize);
+
+        for _ in 0..network.block_count {
+            blocks.push(Block::take(&mut parameters, &network)?);
+        }
+
+        let v_conv = Conv2D::take(
+            &mut parameters,
+            NetworkConfig::V_CONV_FILTER_SIZE as usize,
+            channels,
+            network.v_conv_channels as usize,
+        )?;
+        let v_conv_bn =
+            BatchNorm::take_if(&mut parameters, &network, network.v_conv_channels as usize)?;
+        let (v_fc0, v_fc1) = if network.v_fc_size == 0 {
+            let v_fc0 = Fc::take(
+                &mut parameters,
+                network.v_flatten_size() as usize,
+                NetworkConfig::V_OUTPUT_SIZE as usize,
+            )?;
+            (v_fc0, None)
+        } else {
+            let v_fc0 = Fc::take(
+                &mut parameters,
+                network.v_flatten_size() as usize,
+                network.v_fc_size as usize,
+            )?;
+            let v_fc1 = Fc::take(
+                &mut parameters,
+                network.v_fc_size as usize,
+                NetworkConfig::V_OUTPUT_SIZE as usize,
+            )?;
+            (v_fc0, Some(v_fc1))
+        };
+        let p_conv = Conv2D::take(
+            &mut parameters,
+            NetworkConfig::P_CONV_FILTER_SIZE as usize,
+            channels,
+            network.p_conv_channels as usize,
+        )?;
+        let p_conv_bn =
+            BatchNorm::take_if(&mut parameters, &network, network.p_conv_channels as usize)?;
+        let p_fc0 = Fc::take(
+            &mut parameters,
+            network.p_flatten_size() as usize,
+            NetworkConfig::P_FC0_SIZE as usize,
+        )?;
+
+        // The auxiliary heads are only used for training, so their parameters are checked and dropped.
+        if network.reply_policy_head {
+            Conv2D::take(
+                &mut parameters,
+                NetworkConfig::P_CONV_FILTER_SIZE as usize,
+                channels,
+                network.p_conv_channels as usize,
+            )?;
+            BatchNorm::take_if(&mut parameters, &network, network.p_conv_channels as usize)?;
+            Fc::take(
+                &mut parameters,
+                network.p_flatten_size() as usize,
+                NetworkConfig::P_FC0_SIZE as usize,
+            )?;
+        }
+
+        if network.ownership_head {
+            Conv2D::take(
+                &mut parameters,
+                NetworkConfig::OWNERSHIP_CONV_FILTER_SIZE as usize,
+                channels,
+                1,
+            )?;
+        }
+
+        parameters.finish()?;
+
+        Ok(Self {
+            network,
+            conv,
+            conv_bn,
+            blocks,
+            v_conv,
+            v_conv_bn,
+            v_fc0,
+            v_fc1,
+            p_conv,
+            p_conv_bn,
+            p_fc0,
+        })
+    }
+
+    /// Returns the architecture of the network, as stored in the checkpoint.
+    pub fn network(&self) -> &NetworkConfig {
+        &self.network
+    }
+
+    /// Computes the output of the residual tower, shared by the policy and value heads.
+    fn forward_tower(&self, batch_size: usize, input: &[f32]) -> Result<Vec<f32>, InferenceError> {
+        check_input_size(&self.network.features, batch_size, input)?;
+
+        let activation = self.network.activation;
+
+        let mut x = self.conv.forward(input, batch_size);
+        normalize(self.conv_bn.as_ref(), &mut x);
+        activate(activation, &mut x);
+
+        for block in &self.blocks {
+            x = block.forward(&x, batch_size, activation);
+        }
+
+        Ok(x)
+    }
+
+    fn forward_p(&self, batch_size: usize, x: &[f32]) -> Vec<f32> {
+        let mut p_conv = self.p_conv.forward(x, batch_size);
+        normalize(self.p_conv_bn.as_ref(), &mut p_conv);
+        activate(self.network.activation, &mut p_conv);
+
+        let mut p = self.p_fc0.forward(&p_conv, batch_size);
+
+        for logits in p.chunks_exact_mut(self.p_fc0.outputs) {
+            softmax(logits);
+        }
+
+        p
+    }
+
+    fn forward_v(&self, batch_size: usize, x: &[f32]) -> Vec<f32> {
+        let mut v_conv = self.v_conv.forward(x, batch_size);
+        normalize(self.v_conv_bn.as_ref(), &mut v_conv);
+        activate(self.network.activation, &mut v_conv);
+
+        let mut v = self.v_fc0.forward(&v_conv, batch_size);
+
+        if let Some(v_fc1) = &self.v_fc1 {
+            activate(self.network.activation, &mut v);
+            v = v_fc1.forward(&v, batch_size);
+        }
+
+        for v in &mut v {
+            *v = v.tanh();
+        }
+
+        v
+    }
+}
+
+impl InferenceBackend for CpuBackend {
+    fn features(&self) -> &FeatureConfig {
+        &self.network.features
+    }
+
+    fn evaluate_p(&self, batch_size: usize, input: &[f32]) -> Result<Vec<f32>, InferenceError> {
+        let x = self.forward_tower(batch_size, input)?;
+        Ok(self.forward_p(batch_size, &x))
+    }
+
+    fn evaluate_pv(
+        &self,
+        batch_size: usize,
+        input: &[f32],
+    ) -> Result<(Vec<f32>, Vec<f32>), InferenceError> {
+        let x = self.forward_tower(batch_size, input)?;
+        Ok((
+            self.forward_p(batch_size, &x),
+            self.forward_v(batch_size, &x),
+        ))
+    }
+}
+
+/// Hands out the saved variables in order, checking their shapes.
+struct Parameters {
+    format_version: u32,
+    variables: std::vec::IntoIter<SavedVariable>,
+    index: usize,
+}
+
+impl Parameters {
+    fn take(&mut self, shape: &[usize]) -> Result<Vec<f32>, ModelIOError> {
+        let variable = self
+            .variables
+            .next()
+            .ok_or_else(|| ModelIOError::MissingVariable(format!("#{}", self.index)))?;
+        self.index += 1;
+
+        let shape = shape.iter().map(|&dim| dim as u64).collect::<Vec<_>>();
+        variable.check_shape(self.format_version, &shape)?;
+
+        Ok(variable.values)
+    }
+
+    fn finish(self) -> Result<(), ModelIOError> {
+        // The checkpoint has variables left over that the network does not use.
+        if !self.variables.as_slice().is_empty() {
+            return Err(ModelIOError::VariableCountMismatch {
+                expected: self.index,
+                found: self.index + self.variables.len(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// A convolution with stride 1 and `SAME` padding over NHWC activations of the board size.
+/// The weights are in HWIO order, as in [network_utils::conv2d].
+pub(crate) struct Conv2D {
+    pub(crate) w: Vec<f32>,
+    pub(crate) b: Vec<f32>,
+    pub(crate) filter_size: usize,
+    pub(crate) input_channels: usize,
+    pub(crate) output_channels: usize,
+}
+
+impl Conv2D {
+    fn take(
+        parameters: &mut Parameters,
+        filter_size: usize,
+        input_channels: usize,
+        output_channels: usize,
+    ) -> Result<Self, ModelIOError> {
+        Ok(Self {
+            w: parameters.take(&[filter_size, filter_size, input_channels, output_channels])?,
+            b: parameters.take(&[output_channels])?,
+            filter_size,
+            input_channels,
+            output_channels,
+        })
+    }
+
+    fn forward(&self, x: &[f32], batch_size: usize) -> Vec<f32> {
+        // TensorFlow puts the smaller half of the `SAME` padding before the input.
+        let padding = (self.filter_size - 1) / 2;
+        let mut y = self.b.repeat(batch_size * SIZE * SIZE);
+
+        for (pixel_index, y) in y.chunks_exact_mut(self.output_channels).enumerate() {
+            let batch_index = pixel_index / (SIZE * SIZE);
+            let row = pixel_index / SIZE % SIZE;
+            let col = pixel_index % SIZE;
+
+            for filter_row in 0..self.filter_size {
+                let input_row = match (row + filter_row).checked_sub(padding) {
+                    Some(input_row) if input_row < SIZE => input_row,
+                    _ => continue,
+                };
+
+                for filter_col in 0..self.filter_size {
+                    let input_col = match (col + filter_col).checked_sub(padding) {
+                        Some(input_col) if input_col < SIZE => input_col,
+                        _ => continue,
+                    };
+
+                    let x_offset =
+                        ((batch_index * SIZE + input_row) * SIZE + input_col) * self.input_channels;
+                    let w_offset = (filter_row * self.filter_size + filter_col)
+                        * self.input_channels
+                        * self.output_channels;
+
+                    for input_channel in 0..self.input_channels {
+                        let x = x[x_offset + input_channel];
+                        let w_offset = w_offset + input_channel * self.output_channels;
+                        let w = &self.w[w_offset..w_offset + self.output_channels];
+
+                        for (y, w) in y.iter_mut().zip(w) {
+                            *y += x * w;
+                        }
+                    }
+                }
+            }
+        }
+
+        y
+    }
+}
+
+/// A depthwise convolution with a channel multiplier of 1, stride 1 and `SAME` padding, without bias.
+pub(crate) struct DepthwiseConv2D {
+    pub(crate) w: Vec<f32>,
+    pub(crate) filter_size: usize,
+    pub(crate) channels: usize,
+}
+
+impl DepthwiseConv2D {
+    fn forward(&self, x: &[f32], batch_size: usize) -> Vec<f32> {
+        let padding = (self.filter_size - 1) / 2;
+        let mut y = vec![0f32; batch_size * SIZE * SIZE * self.channels];
+
+        for (pixel_index, y) in y.chunks_exact_mut(self.channels).enumerate() {
+            let batch_index = pixel_index / (SIZE * SIZE);
+            let row = pixel_index / SIZE % SIZE;
+            let col = pixel_index % SIZE;
+
+            for filter_row in 0..self.filter_size {
+                let input_row = match (row + filter_row).checked_sub(padding) {
+                    Some(input_row) if input_row < SIZE => input_row,
+                    _ => continue,
+                };
+
+                for filter_col in 0..self.filter_size {
+                    let input_col = match (col + filter_col).checked_sub(padding) {
+                        Some(input_col) if input_col < SIZE => input_col,
+                        _ => continue,
+                    };
+
+                    let x_offset =
+                        ((batch_index * SIZE + input_row) * SIZE + input_col) * self.channels;
+                    let w_offset = (filter_row * self.filter_size + filter_col) * self.channels;
+                    let x = &x[x_offset..x_offset + self.channels];
+                    let w = &self.w[w_offset..w_offset + self.channels];
+
+                    for ((y, x), w) in y.iter_mut().zip(x).zip(w) {
+                        *y += x * w;
+                    }
+                }
+            }
+        }
+
+        y
+    }
+}
+
+/// A fully connected layer. The weights are in `[inputs, outputs]` order, as in [network_utils::fc].
+pub(crate) struct Fc {
+    pub(crate) w: Vec<f32>,
+    pub(crate) b: Vec<f32>,
+    pub(crate) inputs: usize,
+    pub(crate) outputs: usize,
+}
+
+impl Fc {
+    fn take(
+        parameters: &mut Parameters,
+        inputs: usize,
+        outputs: usize,
+    ) -> Result<Self, ModelIOError> {
+        Ok(Self {
+            w: parameters.take(&[inputs, outputs])?,
+            b: parameters.take(&[outputs])?,
+            inputs,
+            outputs,
+        })
+    }
+
+    fn forward(&self, x: &[f32], batch_size: usize) -> Vec<f32> {
+        let mut y = self.b.repeat(batch_size);
+
+        for (x, y) in x
+            .chunks_exact(self.inputs)
+            .zip(y.chunks_exact_mut(self.outputs))
+        {
+            for (&x, w) in x.iter().zip(self.w.chunks_exact(self.outputs)) {
+                for (y, w) in y.iter_mut().zip(w) {
+                    *y += x * w;
+                }
+            }
+        }
+
+        y
+    }
+}
+
+/// A depthwise convolution followed by a pointwise convolution, as in [network_utils::separable_conv2d].
+pub(crate) struct SeparableConv2D {
+    pub(crate) depthwise: DepthwiseConv2D,
+    pub(crate) pointwise: Conv2D,
+}
+
+impl SeparableConv2D {
+    fn take(
+        parameters: &mut Parameters,
+        filter_size: usize,
+        input_channels: usize,
+        output_channels: usize,
+    ) -> Result<Self, ModelIOError> {
+        let depthwise_w = parameters.take(&[filter_size, filter_size, input_channels, 1])?;
+        let pointwise_w = parameters.take(&[1, 1, input_channels, output_channels])?;
+        let b = parameters.take(&[output_channels])?;
+
+        Ok(Self {
+            depthwise: DepthwiseConv2D {
+                w: depthwise_w,
+                filter_size,
+                channels: input_channels,
+            },
+            pointwise: Conv2D {
+                w: pointwise_w,
+                b,
+                filter_size: 1,
+                input_channels,
+                output_channels,
+            },
+        })
+    }
+
+    fn forward(&self, x: &[f32], batch_size: usize) -> Vec<f32> {
+        let y = self.depthwise.forward(x, batch_size);
+        self.pointwise.forward(&y, batch_size)
+    }
+}
+
+/// A block of the residual tower, followed by the activation applied in [Network::new].
+pub(crate) enum Block {
+    Residual(Residual),
+    Bottleneck(BottleneckResidual),
+    Separable(SeparableResidual),
+    SqueezeExcitation(SeResidual),
+}
+
+impl Block {
+    fn take(parameters: &mut Parameters, network: &NetworkConfig) -> Result<Self, ModelIOError> {
+        let channels = network.channels as usize;
+        let filter_size = network.filter_size as usize;
+
+        Ok(match network.block_type {
+            BlockType::Residual => {
+                let conv0 = Conv2D::take(parameters, filter_size, channels, channels)?;
+                let conv1 = Conv2D::take(parameters, filter_size, channels, channels)?;
+
+                Block::Residual(Residual {
+                    conv0,
+                    conv1,
+                    batch_norms: BatchNorm::take_all(parameters, network, &[channels, channels])?,
+                })
+            }
+            BlockType::Bottleneck => {
+                let middle_channels = network.bottleneck_channels as usize;
+                let conv0 = Conv2D::take(parameters, 1, channels, middle_channels)?;
+                let conv1 = SeparableConv2D::take(
+                    parameters,
+                    filter_size,
+                    middle_channels,
+                    middle_channels,
+                )?;
+                let conv2 = Conv2D::take(parameters, 1, middle_channels, channels)?;
+
+                Block::Bottleneck(BottleneckResidual {
+                    conv0,
+                    conv1,
+                    conv2,
+                    batch_norms: BatchNorm::take_all(
+                        parameters,
+                        network,
+                        &[middle_channels, middle_channels, channels],
+                    )?,
+                })
+            }
+            BlockType::Separable => {
+                let conv0 = SeparableConv2D::take(parameters, filter_size, channels, channels)?;
+                let conv1 = SeparableConv2D::take(parameters, filter_size, channels, channels)?;
+
+                Block::Separable(SeparableResidual {
+                    conv0,
+                    conv1,
+                    batch_norms: BatchNorm::take_all(parameters, network, &[channels, channels])?,
+                })
+            }
+            BlockType::SqueezeExcitation => {
+                let se_channels = network.se_channels as usize;
+                let conv0 = Conv2D::take(parameters, filter_size, channels, channels)?;
+                let conv1 = Conv2D::take(parameters, filter_size, channels, channels)?;
+                let se_fc0 = Fc::take(parameters, 2 * channels, se_channels)?;
+                let se_scale = Fc::take(parameters, se_channels, channels)?;
+                let se_bias = Fc::take(parameters, se_channels, channels)?;
+
+                Block::SqueezeExcitation(SeResidual {
+                    conv0,
+                    conv1,
+                    se_fc0,
+                    se_scale,
+                    se_bias,
+                    batch_norms: BatchNorm::take_all(parameters, network, &[channels, channels])?,
+                })
+            }
+        })
+    }
+
+    fn forward(&self, x: &[f32], batch_size: usize, activation: Activation) -> Vec<f32> {
+        let mut y = match self {
+            Block::Residual(residual) => {
+                let mut y = residual.conv0.forward(x, batch_size);
+                normalize(residual.batch_norms.first(), &mut y);
+                activate(activation, &mut y);
+
+                let mut y = residual.conv1.forward(&y, batch_size);
+                normalize(residual.batch_norms.get(1), &mut y);
+                y
+            }
+            Block::Bottleneck(residual) => {
+                let mut y = residual.conv0.forward(x, batch_size);
+                normalize(residual.batch_norms.first(), &mut y);
+                activate(activation, &mut y);
+
+                let mut y = residual.conv1.forward(&y, batch_size);
+                normalize(residual.batch_norms.get(1), &mut y);
+                activate(activation, &mut y);
+
+                let mut y = residual.conv2.forward(&y, batch_size);
+                normalize(residual.batch_norms.get(2), &mut y);
+                y
+            }
+            Block::Separable(residual) => {
+                let mut y = residual.conv0.forward(x, batch_size);
+                normalize(residual.batch_norms.first(), &mut y);
+                activate(activation, &mut y);
+
+                let mut y = residual.conv1.forward(&y, batch_size);
+                normalize(residual.batch_norms.get(1), &mut y);
+                y
+            }
+            Block::SqueezeExcitation(residual) => {
+                let mut y = residual.conv0.forward(x, batch_size);
+                normalize(residual.batch_norms.first(), &mut y);
+                activate(activation, &mut y);
+
+                let mut y = residual.conv1.forward(&y, batch_size);
+                normalize(residual.batch_norms.get(1), &mut y);
+
+                let channels = residual.se_scale.outputs;
+                let mut se = residual
+                    .se_fc0
+                    .forward(&global_pool(&y, channels), batch_size);
+                activate(activation, &mut se);
+
+                let scale = residual.se_scale.forward(&se, batch_size);
+                let bias = residual.se_bias.forward(&se, batch_size);
+
+                for ((y, scale), bias) in y
+                    .chunks_exact_mut(SIZE * SIZE * channels)
+                    .zip(scale.chunks_exact(channels))
+                    .zip(bias.chunks_exact(channels))
+                {
+                    for y in y.chunks_exact_mut(channels) {
+                        for ((y, scale), bias) in y.iter_mut().zip(scale).zip(bias) {
+                            *y = *y * sigmoid(*scale) + bias;
+                        }
+                    }
+                }
+
+                y
+            }
+        };
+
+        for (y, x) in y.iter_mut().zip(x) {
+            *y += x;
+        }
+
+        activate(activation, &mut y);
+        y
+    }
+}
+
+/// Mirrors [network_utils::conv2d_residual].
+pub(crate) struct Residual {
+    pub(crate) conv0: Conv2D,
+    pub(crate) conv1: Conv2D,
+    pub(crate) batch_norms: Vec<BatchNorm>,
+}
+
+/// Mirrors [network_utils::conv2d_bottleneck_residual].
+pub(crate) struct BottleneckResidual {
+    pub(crate) conv0: Conv2D,
+    pub(crate) conv1: SeparableConv2D,
+    pub(crate) conv2: Conv2D,
+    pub(crate) batch_norms: Vec<BatchNorm>,
+}
+
+/// Mirrors [network_utils::conv2d_separable_residual].
+pub(crate) struct SeparableResidual {
+    pub(crate) conv0: SeparableConv2D,
+    pub(crate) conv1: SeparableConv2D,
+    pub(crate) batch_norms: Vec<BatchNorm>,
+}
+
+/// Mirrors [network_utils::se_residual].
+pub(crate) struct SeResidual {
+    pub(crate) conv0: Conv2D,
+    pub(crate) conv1: Conv2D,
+    pub(crate) se_fc0: Fc,
+    pub(crate) se_scale: Fc,
+    pub(crate) se_bias: Fc,
+    pub(crate) batch_norms: Vec<BatchNorm>,
+}
+
+/// Batch normalization with the moving averages, as [network_utils::batch_norm] does for inference.
+pub(crate) struct BatchNorm {
+    pub(crate) scale: Vec<f32>,
+    pub(crate) offset: Vec<f32>,
+    pub(crate) mean: Vec<f32>,
+    pub(crate) variance: Vec<f32>,
+}
+
+impl BatchNorm {
+    fn take(parameters: &mut Parameters, channels: usize) -> Result<Self, ModelIOError> {
+        Ok(Self {
+            scale: parameters.take(&[channels])?,
+            offset: parameters.take(&[channels])?,
+            mean: parameters.take(&[channels])?,
+            variance: parameters.take(&[channels])?,
+        })
+    }
+
+    /// Takes a layer if the network uses batch normalization.
+    fn take_if(
+        parameters: &mut Parameters,
+        network: &NetworkConfig,
+        channels: usize,
+    ) -> Result<Option<Self>, ModelIOError> {
+        if network.batch_norm {
+            Ok(Some(Self::take(parameters, channels)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Takes a layer for each of `channels` if the network uses batch normalization.
+    fn take_all(
+        parameters: &mut Parameters,
+        network: &NetworkConfig,
+        channels: &[usize],
+    ) -> Result<Vec<Self>, ModelIOError> {
+        let mut batch_norms = Vec::new();
+
+        for &channels in channels {
+            batch_norms.extend(Self::take_if(parameters, network, channels)?);
+        }
+
+        Ok(batch_norms)
+    }
+
+    fn forward(&self, x: &mut [f32]) {
+        let channels = self.scale.len();
+        let multipliers = self
+            .scale
+            .iter()
+            .zip(&self.variance)
+            .map(|(scale, variance)| scale / (variance + BATCH_NORM_EPSILON).sqrt())
+            .collect::<Vec<_>>();
+
+        for x in x.chunks_exact_mut(channels) {
+            for (((x, multiplier), mean), offset) in x
+                .iter_mut()
+                .zip(&multipliers)
+                .zip(&self.mean)
+                .zip(&self.offset)
+            {
+                *x = (*x - mean) * multiplier + offset;
+            }
+        }
+    }
+}
+
+fn normalize(batch_norm: Option<&BatchNorm>, x: &mut [f32]) {
+    if let Some(batch_norm) = batch_norm {
+        batch_norm.forward(x);
+    }
+}
+
+fn activate(activation: Activation, x: &mut [f32]) {
+    match activation {
+        Activation::LeakyRelu => {
+            for x in x {
+                if *x < 0f32 {
+                    *x *= CpuBackend::LEAKY_RELU_ALPHA;
+                }
+            }
+        }
+        Activation::Relu => {
+            for x in x {
+                *x = x.max(0f32);
+            }
+        }
+        Activation::Elu => {
+            for x in x {
+                if *x < 0f32 {
+                    *x = x.exp_m1();
+                }
+            }
+        }
+    }
+}
+
+/// Pools NHWC activations over the board into `[N, 2 * channels]`, as in [network_utils::global_pool].
+fn global_pool(x: &[f32], channels: usize) -> Vec<f32> {
+    let mut y = Vec::with_capacity(x.len() / (SIZE * SIZE) * 2);
+
+    for x in x.chunks_exact(SIZE * SIZE * channels) {
+        let mut sum = vec![0f32; channels];
+        let mut max = vec![f32::NEG_INFINITY; channels];
+
+        for x in x.chunks_exact(channels) {
+            for ((x, sum), max) in x.iter().zip(&mut sum).zip(&mut max) {
+                *sum += x;
+                *max = max.max(*x);
+            }
+        }
+
+        y.extend(sum.into_iter().map(|sum| sum / (SIZE * SIZE) as f32));
+        y.extend(max);
+    }
+
+    y
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1f32 / (1f32 + (-x).exp())
+}
+
+fn softmax(x: &mut [f32]) {
+    let max = x.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mut sum = 0f32;
+
+    for x in x.iter_mut() {
+        *x = (*x - max).exp();
+        sum += *x;
+    }
+
+    let sum_inv = sum.recip();
+
+    for x in x.iter_mut() {
+        *x *= sum_inv;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        encode_nn_input,
+        test_checkpoint::{test_networks, zero_checkpoint},
+        CheckpointHeader, EnvTurnMode,
+    };
+
+    #[test]
+    fn test_conv2d_same_padding() {
+        let conv = Conv2D {
+            w: vec![1f32; 9],
+            b: vec![0.5],
+            filter_size: 3,
+            input_channels: 1,
+            output_channels: 1,
+        };
+        let mut x = vec![0f32; SIZE * SIZE];
+        x[0] = 1f32;
+
+        let y = conv.forward(&x, 1);
+
+        assert_eq!(y[0], 1.5);
+        assert_eq!(y[1], 1.5);
+        assert_eq!(y[SIZE + 1], 1.5);
+        assert_eq!(y[2], 0.5);
+        assert_eq!(y[2 * SIZE + 2], 0.5);
+    }
+
+    #[test]
+    fn test_depthwise_conv2d() {
+        let conv = DepthwiseConv2D {
+            w: [1f32, 2f32].repeat(9),
+            filter_size: 3,
+            channels: 2,
+        };
+        let mut x = vec![0f32; SIZE * SIZE * 2];
+        x[(SIZE + 1) * 2 + 1] = 1f32;
+
+        let y = conv.forward(&x, 1);
+
+        assert_eq!(y[1], 2f32);
+        assert_eq!(y[0], 0f32);
+        assert_eq!(y[(2 * SIZE + 2) * 2 + 1], 2f32);
+        assert_eq!(y[(3 * SIZE + 3) * 2 + 1], 0f32);
+    }
+
+    #[test]
+    fn test_fc_softmax() {
+        let fc = Fc {
+            w: vec![1f32, 0f32, 0f32, 1f32, 1f32, 1f32],
+            b: vec![0f32, 1f32],
+            inputs: 3,
+            outputs: 2,
+        };
+
+        let mut y = fc.forward(&[1f32, 2f32, 3f32], 1);
+        assert_eq!(y, vec![4f32, 6f32]);
+
+        softmax(&mut y);
+        assert!((y[0] - 1f32 / (1f32 + 2f32.exp())).abs() < 1e-6);
+        assert!((y.iter().sum::<f32>() - 1f32).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_global_pool() {
+        let mut x = vec![0f32; SIZE * SIZE * 2];
+        x[0] = SIZE as f32 * SIZE as f32;
+        x[3] = -1f32;
+
+        let y = global_pool(&x, 2);
+        assert_eq!(
+            y,
+            vec![
+                1f32,
+                -1f32 / (SIZE * SIZE) as f32,
+                SIZE as f32 * SIZE as f32,
+                0f32
+            ]
+        );
+    }
+
+    #[test]
+    fn test_batch_norm() {
+        let batch_norm = BatchNorm {
+            scale: vec![1f32, 2f32],
+            offset: vec![0f32, 1f32],
+            mean: vec![1f32, -1f32],
+            variance: vec![1f32 - BATCH_NORM_EPSILON, 4f32 - BATCH_NORM_EPSILON],
+        };
+
+        let mut x = vec![3f32, 1f32, 1f32, -1f32];
+        batch_norm.forward(&mut x);
+
+        for (x, expected) in x.iter().zip([2f32, 3f32, 0f32, 1f32]) {
+            assert!((x - expected).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_rejects_mismatching_parameters() {
+        let checkpoint = Checkpoint {
+            header: CheckpointHeader::new(NetworkConfig::default(), 0),
+            variables: vec![SavedVariable {
+                name: "conv_w".to_owned(),
+                shape: vec![3],
+                values: vec![0f32; 3],
+            }],
+            optimizer_variables: vec![],
+        };
+
+        assert!(matches!(
+            CpuBackend::from_checkpoint(checkpoint),
+            Err(ModelIOError::ShapeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_zero_checkpoint() {
+        for network in test_networks() {
+            let cpu_backend = CpuBackend::from_checkpoint(zero_checkpoint(network)).unwrap();
+            assert_eq!(cpu_backend.network(), &network);
+
+            let (p, v) = cpu_backend
+                .evaluate_pv(
+                    1,
+                    &encode_nn_input(
+                        &network.features,
+                        1,
+                        EnvTurnMode::Player,
+                        [Environment::new()].iter(),
+                    ),
+                )
+                .unwrap();
+
+            assert_eq!(p.len(), SIZE * SIZE);
+            assert_eq!(v.len(), 1);
+            assert!(p
+                .iter()
+                .all(|&p| (p - 1f32 / (SIZE * SIZE) as f32).abs() < 1e-6));
+            assert_eq!(v[0], 0f32);
+        }
+    }
+
+    #[test]
+    fn test_rejects_mismatching_input() {
+        let cpu_backend = CpuBackend::from_checkpoint(zero_checkpoint(test_networks()[0])).unwrap();
+
+        assert!(matches!(
+            cpu_backend.evaluate_p(2, &[0f32; SIZE * SIZE * 2]),
+            Err(InferenceError::InputSizeMismatch { .. })
+        ));
+    }
+
+    #[cfg(feature = "tensorflow")]
+    #[test]
+    fn test_matches_tensorflow() {
+        use crate::{AgentModel, TensorflowBackend};
+        use tempfile::tempdir;
+        use tensorflow::{Scope, Session, SessionOptions, SessionRunArgs};
+
+        let mut envs = vec![Environment::new()];
+
+        for action in [112, 113, 97, 128, 0, 224] {
+            let mut env = envs.last().unwrap().clone();
+            env.place_stone(action);
+            envs.push(env);
+        }
+
+        for network in test_networks() {
+            let mut scope = Scope::new_root_scope();
+            let agent_model = AgentModel::new(network, Default::default(), &mut scope).unwrap();
+            let session = Session::new(&SessionOptions::new(), &scope.graph()).unwrap();
+
+            let mut init_run_args = SessionRunArgs::new();
+
+            for variable in &agent_model.variables {
+                init_run_args.add_target(&variable.initializer());
+            }
+
+            session.run(&mut init_run_args).unwrap();
+
+            let dir = tempdir().unwrap();
+            let path = dir.path().join("checkpoint");
+            agent_model.io.save(&session, &path, 0, true).unwrap();
+            let cpu_backend = CpuBackend::load(&path).unwrap();
+
+            let input = encode_nn_input(
+                &network.features,
+                envs.len(),
+                EnvTurnMode::Player,
+                envs.iter(),
+            );
+            let (expected_p, expected_v) = TensorflowBackend::new(&agent_model, &session)
+                .evaluate_pv(envs.len(), &input)
+                .unwrap();
+            let (p, v) = cpu_backend.evaluate_pv(envs.len(), &input).unwrap();
+
+            assert_eq!(p.len(), expected_p.len());
+            assert_eq!(v.len(), expected_v.len());
+
+            for (actual, expected) in p.iter().zip(expected_p.iter()) {
+                assert!((actual - expected).abs() <= 1e-5 + 1e-3 * expected.abs());
+            }
+
+            for (actual, expected) in v.iter().zip(expected_v.iter()) {
+                assert!((actual - expected).abs() <= 1e-5 + 1e-3 * expected.abs());
+            }
+        }
+    }
+}
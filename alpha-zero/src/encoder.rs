@@ -1,5 +1,5 @@
-use environment::Environment;
-use tensorflow::Tensor;
+use environment::{Environment, Turn};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum EnvTurnMode {
@@ -7,26 +7,95 @@ pub enum EnvTurnMode {
     Opponent,
 }
 
+/// Input planes of the network after the stones of the side to move and of its opponent, in this order.
+/// The default has no extra planes. This is part of the [NetworkConfig](super::NetworkConfig).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeatureConfig {
+    /// A plane of ones if the side to move is black, and of zeros otherwise.
+    pub color_to_move: bool,
+    /// Number of planes marking the most recent moves, from the latest one.
+    pub last_moves: usize,
+    /// A plane marking the points where the side to move may not place a stone, see [Environment::is_legal_move].
+    pub forbidden_points: bool,
+}
+
+impl FeatureConfig {
+    /// Planes of the stones of the side to move and of its opponent, which are always encoded.
+    pub const STONE_CHANNELS: usize = 2;
+
+    pub fn channels(&self) -> usize {
+        Self::STONE_CHANNELS
+            + self.color_to_move as usize
+            + self.last_moves
+            + self.forbidden_points as usize
+    }
+
+    /// Encodes the board from the perspective of `turn` into `dst`, of shape `[BOARD_SIZE, BOARD_SIZE, channels]`.
+    pub fn encode(&self, env: &Environment, turn: Turn, dst: &mut [f32]) {
+        let channels = self.channels();
+
+        if channels == Self::STONE_CHANNELS {
+            env.encode_board(turn, dst);
+            return;
+        }
+
+        let mut stones = [0f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE * 2];
+        env.encode_board(turn, &mut stones);
+
+        let move_count = env.move_count();
+
+        for (index, (dst, stones)) in dst
+            .chunks_exact_mut(channels)
+            .zip(stones.chunks_exact(Self::STONE_CHANNELS))
+            .enumerate()
+        {
+            dst.fill(0f32);
+            dst[..Self::STONE_CHANNELS].copy_from_slice(stones);
+
+            let mut offset = Self::STONE_CHANNELS;
+
+            if self.color_to_move {
+                if turn == Turn::Black {
+                    dst[offset] = 1f32;
+                }
+
+                offset += 1;
+            }
+
+            let move_number = env.move_numbers[index] as usize;
+
+            if move_number != 0 && move_count - move_number < self.last_moves {
+                dst[offset + move_count - move_number] = 1f32;
+            }
+
+            offset += self.last_moves;
+
+            if self.forbidden_points && !env.is_legal_move(index) {
+                dst[offset] = 1f32;
+            }
+        }
+    }
+}
+
+/// Encodes the positions into shape `[input_count, BOARD_SIZE, BOARD_SIZE, channels]`.
 pub fn encode_nn_input<'a>(
+    features: &FeatureConfig,
     input_count: usize,
     env_turn_mode: EnvTurnMode,
     env_iter: impl Iterator<Item = &'a Environment>,
-) -> Tensor<f32> {
-    let mut input = Tensor::new(&[
-        input_count as _,
-        Environment::BOARD_SIZE as _,
-        Environment::BOARD_SIZE as _,
-        2,
-    ]);
+) -> Vec<f32> {
+    let size = Environment::BOARD_SIZE * Environment::BOARD_SIZE * features.channels();
+    let mut input = vec![0f32; input_count * size];
 
     for (index, env) in env_iter.enumerate() {
-        env.encode_board(
+        features.encode(
+            env,
             match env_turn_mode {
                 EnvTurnMode::Player => env.turn,
                 EnvTurnMode::Opponent => env.turn.opponent(),
             },
-            &mut input[index * Environment::BOARD_SIZE * Environment::BOARD_SIZE * 2
-                ..(index + 1) * Environment::BOARD_SIZE * Environment::BOARD_SIZE * 2],
+            &mut input[index * size..(index + 1) * size],
         );
     }
 
@@ -37,13 +106,10 @@ pub fn encode_nn_targets<'a, const N: usize>(
     input_count: usize,
     pi_iter: impl Iterator<Item = &'a [f32; N]>,
     z_iter: impl Iterator<Item = f32>,
-) -> (Tensor<f32>, Tensor<f32>) {
-    let mut policy_target = Tensor::new(&[
-        input_count as _,
-        Environment::BOARD_SIZE as _,
-        Environment::BOARD_SIZE as _,
-    ]);
-    let mut value_target = Tensor::new(&[input_count as _, 1]);
+) -> (Vec<f32>, Vec<f32>) {
+    let mut policy_target =
+        vec![0f32; input_count * Environment::BOARD_SIZE * Environment::BOARD_SIZE];
+    let mut value_target = vec![0f32; input_count];
 
     for (index, (z, pi)) in (z_iter.zip(pi_iter)).enumerate() {
         policy_target[index * Environment::BOARD_SIZE * Environment::BOARD_SIZE
@@ -54,3 +120,66 @@ pub fn encode_nn_targets<'a, const N: usize>(
 
     (policy_target, value_target)
 }
+
+/// Encodes per-point targets of the auxiliary heads, such as the reply policy or the ownership, into shape `[N, BOARD_SIZE, BOARD_SIZE]`.
+pub fn encode_nn_board_targets<'a, const N: usize>(
+    input_count: usize,
+    target_iter: impl Iterator<Item = &'a [f32; N]>,
+) -> Vec<f32> {
+    let mut target = vec![0f32; input_count * Environment::BOARD_SIZE * Environment::BOARD_SIZE];
+
+    for (index, board_target) in target_iter.enumerate() {
+        target[index * Environment::BOARD_SIZE * Environment::BOARD_SIZE
+            ..(index + 1) * Environment::BOARD_SIZE * Environment::BOARD_SIZE]
+            .copy_from_slice(board_target);
+    }
+
+    target
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_features() {
+        let mut env = Environment::new();
+        env.place_stone(0);
+        env.place_stone(10);
+
+        let features = FeatureConfig::default();
+        assert_eq!(features.channels(), 2);
+
+        let mut expected = [0f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE * 2];
+        env.encode_board(Turn::Black, &mut expected);
+
+        let input = encode_nn_input(&features, 1, EnvTurnMode::Player, std::iter::once(&env));
+        assert_eq!(&input[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_extra_features() {
+        let mut env = Environment::new();
+        env.place_stone(0);
+        env.place_stone(10);
+        env.place_stone(20);
+
+        let features = FeatureConfig {
+            color_to_move: true,
+            last_moves: 2,
+            forbidden_points: true,
+        };
+        assert_eq!(features.channels(), 6);
+
+        let mut dst = [0f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE * 6];
+        features.encode(&env, Turn::White, &mut dst);
+
+        assert_eq!(&dst[0..6], &[0f32, 1f32, 0f32, 0f32, 0f32, 1f32]);
+        assert_eq!(&dst[10 * 6..11 * 6], &[1f32, 0f32, 0f32, 0f32, 1f32, 1f32]);
+        assert_eq!(&dst[20 * 6..21 * 6], &[0f32, 1f32, 0f32, 1f32, 0f32, 1f32]);
+        assert_eq!(&dst[30 * 6..31 * 6], &[0f32; 6]);
+
+        features.encode(&env, Turn::Black, &mut dst);
+        assert_eq!(&dst[30 * 6..31 * 6], &[0f32, 0f32, 1f32, 0f32, 0f32, 0f32]);
+    }
+}
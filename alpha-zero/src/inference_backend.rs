@@ -0,0 +1,49 @@
+use crate::FeatureConfig;
+use environment::Environment;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum InferenceError {
+    #[cfg(feature = "tensorflow")]
+    #[error("Tensorflow error: {0}")]
+    Tensorflow(#[from] tensorflow::Status),
+    #[error("Input size mismatch: expected {expected}, found {found}")]
+    InputSizeMismatch { expected: usize, found: usize },
+}
+
+/// Evaluates the network for the search.
+///
+/// The input is a batch of `batch_size` positions encoded by [encode_nn_input](super::encode_nn_input)
+/// with the [InferenceBackend::features] of the network, in row-major order of shape
+/// `[batch_size, BOARD_SIZE, BOARD_SIZE, input_channels]`.
+/// The policy has shape `[batch_size, BOARD_SIZE, BOARD_SIZE]` and the value has shape `[batch_size, 1]`.
+pub trait InferenceBackend: Sync {
+    /// Returns the input planes the network expects.
+    fn features(&self) -> &FeatureConfig;
+
+    fn evaluate_p(&self, batch_size: usize, input: &[f32]) -> Result<Vec<f32>, InferenceError>;
+    fn evaluate_pv(
+        &self,
+        batch_size: usize,
+        input: &[f32],
+    ) -> Result<(Vec<f32>, Vec<f32>), InferenceError>;
+}
+
+/// Checks that the input holds `batch_size` positions encoded with `features`.
+pub(crate) fn check_input_size(
+    features: &FeatureConfig,
+    batch_size: usize,
+    input: &[f32],
+) -> Result<(), InferenceError> {
+    let expected =
+        batch_size * Environment::BOARD_SIZE * Environment::BOARD_SIZE * features.channels();
+
+    if input.len() != expected {
+        return Err(InferenceError::InputSizeMismatch {
+            expected,
+            found: input.len(),
+        });
+    }
+
+    Ok(())
+}
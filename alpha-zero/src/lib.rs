@@ -1,17 +1,48 @@
 mod agent;
+#[cfg(feature = "tensorflow")]
 mod agent_model;
+mod checkpoint;
+mod checkpoint_store;
+mod cpu_backend;
 mod encoder;
+mod inference_backend;
 mod mcts_executor;
 mod mcts_node;
+#[cfg(feature = "tensorflow")]
 mod model_io;
+#[cfg(feature = "tensorflow")]
 mod network;
+mod network_config;
+mod onnx;
+#[cfg(feature = "tensorflow")]
+mod optimizer;
 mod parallel_mcts_executor;
+mod search_info;
+mod stop_signal;
+#[cfg(feature = "tensorflow")]
+mod tensorflow_backend;
+#[cfg(test)]
+mod test_checkpoint;
 
 pub use agent::*;
+#[cfg(feature = "tensorflow")]
 pub use agent_model::*;
+pub use checkpoint::*;
+pub use checkpoint_store::*;
+pub use cpu_backend::*;
 pub use encoder::*;
+pub use inference_backend::*;
 pub use mcts_executor::*;
 pub use mcts_node::*;
+#[cfg(feature = "tensorflow")]
 pub use model_io::*;
+#[cfg(feature = "tensorflow")]
 pub use network::*;
+pub use network_config::*;
+#[cfg(feature = "tensorflow")]
+pub use optimizer::*;
 pub use parallel_mcts_executor::*;
+pub use search_info::*;
+pub use stop_signal::*;
+#[cfg(feature = "tensorflow")]
+pub use tensorflow_backend::*;
@@ -1,17 +1,19 @@
-use crate::{encode_nn_input, mcts_node::BoardState, Agent, AgentModel, EnvTurnMode};
+use crate::{
+    encode_nn_input, mcts_node::BoardState, Agent, EnvTurnMode, InferenceBackend, InferenceError,
+    StopSignal,
+};
 use atomic_float::AtomicF32;
 use bitvec::vec::BitVec;
 use environment::{Environment, GameStatus, Stone};
 use mcts::{Node, NodePtr, State};
 use parking_lot::RwLock;
-use rand::{seq::SliceRandom, thread_rng};
+use rand::seq::SliceRandom;
 use rand_distr::{Dirichlet, Distribution};
 use rayon::{
     prelude::{IntoParallelIterator, ParallelIterator},
     ThreadPool, ThreadPoolBuilder,
 };
-use std::sync::atomic::Ordering;
-use tensorflow::{Session, Status};
+use std::{sync::atomic::Ordering, time::Instant};
 
 pub struct MCTSExecutor {
     thread_pool: ThreadPool,
@@ -27,18 +29,28 @@ impl MCTSExecutor {
         }
     }
 
+    /// Creates an executor running on the given number of threads.
+    /// Use a single thread to make the search reproducible with [Agent::with_seed].
+    pub fn with_num_threads(num_threads: usize) -> Self {
+        Self {
+            thread_pool: ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .unwrap(),
+        }
+    }
+
     pub fn run(
         &self,
         count: usize,
         batch_size: usize,
         epsilon: f32,
         alpha: f32,
-        agent_model: &AgentModel,
-        session: &Session,
+        backend: &dyn InferenceBackend,
         agent: &Agent,
-    ) -> Result<(), Status> {
+    ) -> Result<(), InferenceError> {
         {
-            let mut rng = thread_rng();
+            let mut rng = agent.fork_rng();
 
             // Apply Dirichlet noise to the root node.
             let noise_dist =
@@ -74,191 +86,241 @@ impl MCTSExecutor {
             exec_count += 1;
         }
 
+        let started = Instant::now();
+        let node_count = agent.mcts.node_count();
+
         self.thread_pool.install(|| {
             (0..exec_count)
                 .into_par_iter()
-                .try_for_each(|_| -> Result<(), Status> {
-                    let mut rng = thread_rng();
-                    let mut requests = Vec::with_capacity(batch_size);
-
-                    for _ in 0..batch_size {
-                        let node = agent.mcts.select_leaf(|parent, children| {
-                            let parent_n = u64::max(1, parent.n.load(Ordering::Relaxed));
-                            children
-                                .iter()
-                                .map(|child| compute_ucb_1(parent_n, child, Self::C_PUCT))
-                                .enumerate()
-                                .max_by(|(_, a), (_, b)| f32::total_cmp(a, b))
-                                .unwrap()
-                                .0
-                        });
-
-                        if node.state.is_terminal() {
-                            // If the leaf node is terminal state, we don't need to expand it.
-                            // Instead we perform backup from the leaf node.
-                            node.propagate(node.state.z.load(Ordering::Relaxed));
-                            node.v_loss.fetch_sub(1, Ordering::Relaxed);
-                            continue;
-                        }
-
-                        // Select any possible action.
-                        // Since the leaf node doesn't have terminal state, we need to expand it.
-                        let action = {
-                            let mut bits = BitVec::<usize>::repeat(
-                                false,
-                                Environment::BOARD_SIZE * Environment::BOARD_SIZE,
-                            );
-
-                            for children in node.children.read().iter() {
-                                bits.set(children.action.unwrap(), true);
-                            }
-
-                            let available_actions = (0..Environment::BOARD_SIZE
-                                * Environment::BOARD_SIZE)
-                                .filter(|&action| {
-                                    node.state.is_available_action(action) && !bits[action]
-                                })
-                                .collect::<Vec<_>>();
-                            available_actions.choose(&mut rng).cloned()
-                        };
-                        let action = if let Some(action) = action {
-                            action
-                        } else {
-                            // There's no action for now.
-                            // Note that this not means the game is over.
-                            node.v_loss.fetch_sub(1, Ordering::Relaxed);
-                            continue;
-                        };
-
-                        // Place the stone.
-                        let mut env = node.state.env.clone();
-                        let status = env.place_stone(action).unwrap();
-                        let terminal_reward = match status {
-                            GameStatus::InProgress => None,
-                            GameStatus::Draw => Some(0f32),
-                            GameStatus::BlackWin => Some(1f32),
-                            GameStatus::WhiteWin => Some(1f32),
-                        };
-
-                        // Pre-compute policy.
-                        // This will be overwritten by the neural network evaluation.
-                        // Until then, we use the uniform distribution.
-                        let mut policy = [1f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE];
-
-                        for action in 0..Environment::BOARD_SIZE * Environment::BOARD_SIZE {
-                            if env.board[action] != Stone::Empty {
-                                policy[action] = 0f32;
-                            }
-                        }
-
-                        let sum = policy.iter().sum::<f32>();
-
-                        if f32::EPSILON <= sum {
-                            let sum_inv = sum.recip();
-
-                            for policy in policy.iter_mut() {
-                                *policy *= sum_inv;
-                            }
-                        }
-
-                        // Pre-expand the node.
-                        let expanded_child = match agent.mcts.expand(
-                            node,
-                            action,
-                            BoardState {
-                                env,
-                                status,
-                                policy: RwLock::new(policy),
-                                z: AtomicF32::new(terminal_reward.unwrap_or(0f32)),
-                            },
-                        ) {
-                            Some(child) => {
-                                node.v_loss.fetch_sub(1, Ordering::Relaxed);
-                                child
-                            }
-                            None => {
-                                // The node is already expanded by other thread.
-                                // We don't need to expand it again.
-                                node.v_loss.fetch_sub(1, Ordering::Relaxed);
-                                continue;
-                            }
-                        };
-
-                        match terminal_reward {
-                            Some(terminal_reward) => {
-                                // Perform backup from the expanded child node.
-                                expanded_child.propagate(terminal_reward);
-                            }
-                            None => {
-                                // Collect the requests.
-                                requests.push(NNEvalRequest {
-                                    node: expanded_child,
-                                });
-                            }
-                        }
-                    }
-
-                    if requests.is_empty() {
-                        // There's no request for now.
-                        return Ok(());
-                    }
-
-                    let input = encode_nn_input(
-                        requests.len(),
-                        EnvTurnMode::Player,
-                        requests.iter().map(|request| &request.node.state.env),
-                    );
-                    let (policy, value) = agent_model.evaluate_pv(session, input)?;
-
-                    for (batch_index, request) in requests.iter().enumerate() {
-                        let node = &*request.node;
-                        let raw_policy = &policy[batch_index
-                            * (Environment::BOARD_SIZE * Environment::BOARD_SIZE)
-                            ..(batch_index + 1)
-                                * (Environment::BOARD_SIZE * Environment::BOARD_SIZE)];
-
-                        // The value should be negated because the value is from the perspective of the opponent.
-                        let value = -value[batch_index];
-
-                        // Filter out illegal actions and normalize the policy.
-                        let mut policy = [0f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE];
-                        policy.copy_from_slice(raw_policy);
-
-                        for action in 0..Environment::BOARD_SIZE * Environment::BOARD_SIZE {
-                            if !node.state.is_available_action(action) {
-                                policy[action] = 0f32;
-                            }
-                        }
-
-                        let sum = policy.iter().sum::<f32>();
-
-                        if f32::EPSILON <= sum {
-                            let sum_inv = sum.recip();
-
-                            for policy in policy.iter_mut() {
-                                *policy *= sum_inv;
-                            }
-                        }
-
-                        // Update the pre-expanded child node.
-                        *node.state.policy.write() = policy;
-
-                        // Update children's prior probability.
-                        // This is required because every node after expanded are holding dummy prior probabilities.
-                        for child in node.children.read().iter() {
-                            let child = &*child;
-                            let action = child.action.unwrap();
-                            let prob = policy[action];
-                            child.p.store(prob, Ordering::Relaxed);
-                        }
-
-                        // Perform backup from the expanded child node.
-                        node.propagate(value);
-                    }
-
-                    Ok(())
-                })
-        })
+                .try_for_each(|_| Self::run_batch(batch_size, backend, agent))
+        })?;
+
+        agent.record_search(started.elapsed(), node_count);
+        // Walking the whole tree is too slow outside of tests.
+        #[cfg(test)]
+        assert_eq!(agent.mcts.validate(), Ok(()));
+        Ok(())
+    }
+
+    /// Keeps running MCTS on the agent's tree until the `signal` is stopped
+    /// or `max_count` simulations are performed, and returns the number of simulations performed.
+    /// Unlike [`run`](Self::run), this does not apply Dirichlet noise to the root node,
+    /// since it is intended to be used while waiting for the opponent. See [`Agent::ponder`].
+    pub fn run_until_stopped(
+        &self,
+        max_count: usize,
+        batch_size: usize,
+        backend: &dyn InferenceBackend,
+        agent: &Agent,
+        signal: &StopSignal,
+    ) -> Result<usize, InferenceError> {
+        let started = Instant::now();
+        let node_count = agent.mcts.node_count();
+
+        let processed_count = self.thread_pool.install(|| {
+            let exec_count = self.thread_pool.current_num_threads();
+            let mut processed_count = 0;
+
+            while processed_count < max_count
+                && !signal.is_stopped()
+                && !agent.mcts.root().state.is_terminal()
+            {
+                (0..exec_count)
+                    .into_par_iter()
+                    .try_for_each(|_| Self::run_batch(batch_size, backend, agent))?;
+                processed_count += exec_count * batch_size;
+            }
+
+            Ok::<_, InferenceError>(processed_count)
+        })?;
+
+        agent.record_search(started.elapsed(), node_count);
+        #[cfg(test)]
+        assert_eq!(agent.mcts.validate(), Ok(()));
+        Ok(processed_count)
+    }
+
+    fn run_batch(
+        batch_size: usize,
+        backend: &dyn InferenceBackend,
+        agent: &Agent,
+    ) -> Result<(), InferenceError> {
+        let mut rng = agent.fork_rng();
+        let mut requests = Vec::with_capacity(batch_size);
+
+        for _ in 0..batch_size {
+            let node = agent.mcts.select_leaf(|parent, children| {
+                let parent_n = u64::max(1, parent.n.load(Ordering::Relaxed));
+                children
+                    .iter()
+                    .map(|child| compute_ucb_1(parent_n, child, Self::C_PUCT))
+                    .enumerate()
+                    .max_by(|(_, a), (_, b)| f32::total_cmp(a, b))
+                    .unwrap()
+                    .0
+            });
+
+            if node.state.is_terminal() {
+                // If the leaf node is terminal state, we don't need to expand it.
+                // Instead we perform backup from the leaf node.
+                node.propagate(node.state.z.load(Ordering::Relaxed));
+                node.v_loss.fetch_sub(1, Ordering::Relaxed);
+                continue;
+            }
+
+            // Select any possible action.
+            // Since the leaf node doesn't have terminal state, we need to expand it.
+            let action = {
+                let mut bits = BitVec::<usize>::repeat(
+                    false,
+                    Environment::BOARD_SIZE * Environment::BOARD_SIZE,
+                );
+
+                for children in node.children.read().iter() {
+                    bits.set(children.action.unwrap(), true);
+                }
+
+                let available_actions = (0..Environment::BOARD_SIZE * Environment::BOARD_SIZE)
+                    .filter(|&action| node.state.is_available_action(action) && !bits[action])
+                    .collect::<Vec<_>>();
+                available_actions.choose(&mut rng).cloned()
+            };
+            let action = if let Some(action) = action {
+                action
+            } else {
+                // There's no action for now.
+                // Note that this not means the game is over.
+                node.v_loss.fetch_sub(1, Ordering::Relaxed);
+                continue;
+            };
+
+            // Place the stone.
+            let mut env = node.state.env.clone();
+            let status = env.place_stone(action).unwrap();
+            let terminal_reward = match status {
+                GameStatus::InProgress => None,
+                GameStatus::Draw => Some(0f32),
+                GameStatus::BlackWin => Some(1f32),
+                GameStatus::WhiteWin => Some(1f32),
+            };
+
+            // Pre-compute policy.
+            // This will be overwritten by the neural network evaluation.
+            // Until then, we use the uniform distribution.
+            let mut policy = [1f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE];
+
+            for action in 0..Environment::BOARD_SIZE * Environment::BOARD_SIZE {
+                if env.board[action] != Stone::Empty {
+                    policy[action] = 0f32;
+                }
+            }
+
+            let sum = policy.iter().sum::<f32>();
+
+            if f32::EPSILON <= sum {
+                let sum_inv = sum.recip();
+
+                for policy in policy.iter_mut() {
+                    *policy *= sum_inv;
+                }
+            }
+
+            // Pre-expand the node.
+            let expanded_child = match agent.mcts.expand(
+                node,
+                action,
+                BoardState {
+                    env,
+                    status,
+                    policy: RwLock::new(policy),
+                    z: AtomicF32::new(terminal_reward.unwrap_or(0f32)),
+                },
+            ) {
+                Some(child) => {
+                    node.v_loss.fetch_sub(1, Ordering::Relaxed);
+                    child
+                }
+                None => {
+                    // The node is already expanded by other thread.
+                    // We don't need to expand it again.
+                    node.v_loss.fetch_sub(1, Ordering::Relaxed);
+                    continue;
+                }
+            };
+
+            match terminal_reward {
+                Some(terminal_reward) => {
+                    // Perform backup from the expanded child node.
+                    expanded_child.propagate(terminal_reward);
+                }
+                None => {
+                    // Collect the requests.
+                    requests.push(NNEvalRequest {
+                        node: expanded_child,
+                    });
+                }
+            }
+        }
+
+        if requests.is_empty() {
+            // There's no request for now.
+            return Ok(());
+        }
+
+        let input = encode_nn_input(
+            backend.features(),
+            requests.len(),
+            EnvTurnMode::Player,
+            requests.iter().map(|request| &request.node.state.env),
+        );
+        let (policy, value) = backend.evaluate_pv(requests.len(), &input)?;
+
+        for (batch_index, request) in requests.iter().enumerate() {
+            let node = &*request.node;
+            let raw_policy = &policy[batch_index
+                * (Environment::BOARD_SIZE * Environment::BOARD_SIZE)
+                ..(batch_index + 1) * (Environment::BOARD_SIZE * Environment::BOARD_SIZE)];
+
+            // The value should be negated because the value is from the perspective of the opponent.
+            let value = -value[batch_index];
+
+            // Filter out illegal actions and normalize the policy.
+            let mut policy = [0f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE];
+            policy.copy_from_slice(raw_policy);
+
+            for action in 0..Environment::BOARD_SIZE * Environment::BOARD_SIZE {
+                if !node.state.is_available_action(action) {
+                    policy[action] = 0f32;
+                }
+            }
+
+            let sum = policy.iter().sum::<f32>();
+
+            if f32::EPSILON <= sum {
+                let sum_inv = sum.recip();
+
+                for policy in policy.iter_mut() {
+                    *policy *= sum_inv;
+                }
+            }
+
+            // Update the pre-expanded child node.
+            *node.state.policy.write() = policy;
+
+            // Update children's prior probability.
+            // This is required because every node after expanded are holding dummy prior probabilities.
+            for child in node.children.read().iter() {
+                let child = &*child;
+                let action = child.action.unwrap();
+                let prob = policy[action];
+                child.p.store(prob, Ordering::Relaxed);
+            }
+
+            // Perform backup from the expanded child node.
+            node.propagate(value);
+        }
+
+        Ok(())
     }
 }
 
@@ -1,121 +1,211 @@
-use bincode::{deserialize_from, serialize_into};
-use serde::{Deserialize, Serialize};
-use std::{fs::File, path::Path};
+use crate::{Checkpoint, CheckpointHeader, ModelIOError, NetworkConfig, SavedVariable};
+use std::{collections::HashMap, path::Path};
 use tensorflow::{
     ops::{assign, NoOp, Placeholder},
     Operation, Scope, Session, SessionRunArgs, Status, Tensor, Variable,
 };
-use thiserror::Error;
-
-#[derive(Error, Debug)]
-pub enum ModelIOError {
-    #[error("Tensorflow error: {0}")]
-    Tensorflow(#[from] Status),
-    #[error("IO error: {0}")]
-    IO(#[from] std::io::Error),
-    #[error("Bincode error: {0}")]
-    Bincode(#[from] bincode::Error),
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct SavedData {
-    pub variable_names: Vec<String>,
-    pub parameters: Vec<Vec<f32>>,
-}
 
 pub struct ModelIO {
+    /// Architecture of the network owning the variables, which is stored in the checkpoints.
+    pub network: NetworkConfig,
     pub variables: Vec<Variable>,
     pub op_variable_inputs: Vec<Operation>,
     pub op_load_variables: Operation,
+    /// Slots of the optimizer, which are needed to resume the training but not for inference.
+    pub optimizer_variables: Vec<Variable>,
+    pub op_optimizer_variable_inputs: Vec<Operation>,
+    pub op_load_optimizer_variables: Operation,
 }
 
 impl ModelIO {
-    pub fn new(variables: Vec<Variable>, scope: &mut Scope) -> Result<Self, Status> {
-        let mut op_variable_inputs = Vec::with_capacity(variables.len());
-        let mut op_load_variables = NoOp::new();
-
-        for variable in &variables {
-            let op_variable_input = Placeholder::new()
-                .dtype(variable.data_type())
-                .shape(variable.shape().clone())
-                .build(scope)?;
-            op_variable_inputs.push(op_variable_input.clone());
-
-            let op_load_variable =
-                assign(variable.output().clone(), op_variable_input.clone(), scope)?;
-            op_load_variables = op_load_variables.add_control_input(op_load_variable);
-        }
-
-        let op_load_variables = op_load_variables.build(scope)?;
+    pub fn new(
+        network: NetworkConfig,
+        variables: Vec<Variable>,
+        optimizer_variables: Vec<Variable>,
+        scope: &mut Scope,
+    ) -> Result<Self, Status> {
+        let (op_variable_inputs, op_load_variables) = build_load_op(&variables, scope)?;
+        let (op_optimizer_variable_inputs, op_load_optimizer_variables) =
+            build_load_op(&optimizer_variables, scope)?;
 
         Ok(Self {
+            network,
             variables,
             op_variable_inputs,
             op_load_variables,
+            optimizer_variables,
+            op_optimizer_variable_inputs,
+            op_load_optimizer_variables,
         })
     }
 
-    /// Save variables with bincode + serde to a single file.
-    pub fn save(&self, session: &Session, path: impl AsRef<Path>) -> Result<(), ModelIOError> {
-        let mut save_run_args = SessionRunArgs::new();
-        let mut fetch_tokens = Vec::with_capacity(self.variables.len());
+    /// Saves the variables to a single [Checkpoint] file.
+    /// The optimizer variables are included if `include_optimizer` is set, so that the training can be resumed exactly.
+    pub fn save(
+        &self,
+        session: &Session,
+        path: impl AsRef<Path>,
+        training_step: u64,
+        include_optimizer: bool,
+    ) -> Result<(), ModelIOError> {
+        let variables = fetch_variables(session, &self.variables)?;
+        let optimizer_variables = if include_optimizer {
+            fetch_variables(session, &self.optimizer_variables)?
+        } else {
+            Vec::new()
+        };
 
-        for variable in &self.variables {
-            fetch_tokens.push(save_run_args.request_fetch(&variable.output().operation, 0));
-            save_run_args.add_target(&variable.output().operation);
+        Checkpoint {
+            header: CheckpointHeader::new(self.network, training_step),
+            variables,
+            optimizer_variables,
         }
+        .write(path)
+    }
 
-        session.run(&mut save_run_args)?;
+    /// Reads the current values of the network variables, without the optimizer slots.
+    pub fn read_variables(&self, session: &Session) -> Result<Vec<SavedVariable>, ModelIOError> {
+        fetch_variables(session, &self.variables)
+    }
 
-        let mut parameters = Vec::with_capacity(self.variables.len());
+    /// Loads the variables from a [Checkpoint] file and returns its header.
+    /// The checkpoint must be compatible with the model, and the variables are matched by name and checked for shape.
+    ///
+    /// The optimizer variables are restored only if the checkpoint includes them;
+    /// otherwise they are left as they are, which is the initial state for a new session.
+    pub fn load(
+        &self,
+        session: &Session,
+        path: impl AsRef<Path>,
+    ) -> Result<CheckpointHeader, ModelIOError> {
+        let checkpoint = Checkpoint::read(path)?;
+        checkpoint.header.check_compatible()?;
+        checkpoint.header.check_network(&self.network)?;
+
+        let format_version = checkpoint.header.format_version;
+        let tensor_inputs = match_variables(format_version, &self.variables, checkpoint.variables)?;
+        let optimizer_tensor_inputs = if checkpoint.optimizer_variables.is_empty() {
+            None
+        } else {
+            Some(match_variables(
+                format_version,
+                &self.optimizer_variables,
+                checkpoint.optimizer_variables,
+            )?)
+        };
 
-        for fetch_token in fetch_tokens {
-            let variable_input = save_run_args.fetch::<f32>(fetch_token)?;
-            parameters.push(variable_input.to_vec());
+        let mut load_run_args = SessionRunArgs::new();
+        load_run_args.add_target(&self.op_load_variables);
+
+        for (op_variable_input, tensor_input) in self.op_variable_inputs.iter().zip(&tensor_inputs)
+        {
+            load_run_args.add_feed(op_variable_input, 0, tensor_input);
         }
 
-        let saved_data = SavedData {
-            variable_names: self
-                .variables
+        if let Some(optimizer_tensor_inputs) = &optimizer_tensor_inputs {
+            load_run_args.add_target(&self.op_load_optimizer_variables);
+
+            for (op_variable_input, tensor_input) in self
+                .op_optimizer_variable_inputs
                 .iter()
-                .map(|variable| variable.name().to_string())
-                .collect(),
-            parameters,
-        };
+                .zip(optimizer_tensor_inputs)
+            {
+                load_run_args.add_feed(op_variable_input, 0, tensor_input);
+            }
+        }
+
+        session.run(&mut load_run_args)?;
 
-        let file = File::create(path)?;
-        serialize_into(file, &saved_data)?;
+        Ok(checkpoint.header)
+    }
+}
 
-        Ok(())
+/// Builds a placeholder for each variable and an operation assigning all of them.
+fn build_load_op(
+    variables: &[Variable],
+    scope: &mut Scope,
+) -> Result<(Vec<Operation>, Operation), Status> {
+    let mut op_variable_inputs = Vec::with_capacity(variables.len());
+    let mut op_load_variables = NoOp::new();
+
+    for variable in variables {
+        let op_variable_input = Placeholder::new()
+            .dtype(variable.data_type())
+            .shape(variable.shape().clone())
+            .build(scope)?;
+        op_variable_inputs.push(op_variable_input.clone());
+
+        let op_load_variable = assign(variable.output().clone(), op_variable_input.clone(), scope)?;
+        op_load_variables = op_load_variables.add_control_input(op_load_variable);
     }
 
-    pub fn load(&self, session: &Session, path: impl AsRef<Path>) -> Result<(), ModelIOError> {
-        let file = File::open(path)?;
-        let saved_data: SavedData = deserialize_from(file)?;
+    Ok((op_variable_inputs, op_load_variables.build(scope)?))
+}
 
-        let mut tensor_inputs = Vec::with_capacity(self.variables.len());
+fn fetch_variables(
+    session: &Session,
+    variables: &[Variable],
+) -> Result<Vec<SavedVariable>, ModelIOError> {
+    let mut save_run_args = SessionRunArgs::new();
+    let mut fetch_tokens = Vec::with_capacity(variables.len());
 
-        for (variable, parameter) in self.variables.iter().zip(saved_data.parameters) {
-            let shape = Option::<Vec<Option<i64>>>::from(variable.shape().clone())
-                .unwrap()
-                .iter()
-                .map(|x| x.unwrap() as u64)
-                .collect::<Vec<_>>();
-            let mut tensor_input = Tensor::new(&shape);
-            tensor_input.copy_from_slice(&parameter);
-            tensor_inputs.push(tensor_input);
-        }
+    for variable in variables {
+        fetch_tokens.push(save_run_args.request_fetch(&variable.output().operation, 0));
+        save_run_args.add_target(&variable.output().operation);
+    }
 
-        let mut load_run_args = SessionRunArgs::new();
-        load_run_args.add_target(&self.op_load_variables);
+    session.run(&mut save_run_args)?;
 
-        for (op_variable_input, tensor_input) in self.op_variable_inputs.iter().zip(&tensor_inputs)
-        {
-            load_run_args.add_feed(op_variable_input, 0, tensor_input);
-        }
+    let mut saved_variables = Vec::with_capacity(variables.len());
 
-        session.run(&mut load_run_args)?;
+    for (variable, fetch_token) in variables.iter().zip(fetch_tokens) {
+        let values = save_run_args.fetch::<f32>(fetch_token)?;
+        saved_variables.push(SavedVariable {
+            name: variable.name().to_string(),
+            shape: variable_shape(variable),
+            values: values.to_vec(),
+        });
+    }
+
+    Ok(saved_variables)
+}
 
-        Ok(())
+/// Matches the saved variables to the variables by name, returning the tensors to feed in the order of `variables`.
+fn match_variables(
+    format_version: u32,
+    variables: &[Variable],
+    saved_variables: Vec<SavedVariable>,
+) -> Result<Vec<Tensor<f32>>, ModelIOError> {
+    let mut saved_variables = saved_variables
+        .into_iter()
+        .map(|variable| (variable.name.clone(), variable))
+        .collect::<HashMap<_, _>>();
+
+    let mut tensor_inputs = Vec::with_capacity(variables.len());
+
+    for variable in variables {
+        let saved_variable = saved_variables
+            .remove(variable.name())
+            .ok_or_else(|| ModelIOError::MissingVariable(variable.name().to_string()))?;
+        let shape = variable_shape(variable);
+        saved_variable.check_shape(format_version, &shape)?;
+
+        let mut tensor_input = Tensor::new(&shape);
+        tensor_input.copy_from_slice(&saved_variable.values);
+        tensor_inputs.push(tensor_input);
     }
+
+    if let Some(name) = saved_variables.into_keys().next() {
+        return Err(ModelIOError::UnknownVariable(name));
+    }
+
+    Ok(tensor_inputs)
+}
+
+pub(crate) fn variable_shape(variable: &Variable) -> Vec<u64> {
+    Option::<Vec<Option<i64>>>::from(variable.shape().clone())
+        .unwrap()
+        .iter()
+        .map(|x| x.unwrap() as u64)
+        .collect()
 }
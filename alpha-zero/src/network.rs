@@ -1,51 +1,38 @@
-use environment::Environment;
-use network_utils::{Conv2DPadding, WeightInitializer};
+use crate::{BlockType, NetworkConfig};
+use network_utils::{BatchNorm, BatchNormOptions, Conv2DPadding, WeightInitializer};
 use tensorflow::{
     ops::{
-        constant, leaky_relu, mean, reshape, softmax, softmax_cross_entropy_with_logits, tanh,
-        Placeholder,
+        constant, mean, reshape, softmax, softmax_cross_entropy_with_logits, tanh, NoOp,
+        Placeholder, PlaceholderWithDefault,
     },
-    DataType, Operation, Scope, Status, Variable,
+    DataType, Operation, Scope, Shape, Status, Variable,
 };
 
 pub struct Network {
+    pub config: NetworkConfig,
     pub op_input: Operation,
     pub op_v_output: Operation,
     pub op_p_output: Operation,
     pub op_p_loss: Operation,
+    /// Logits of the reply policy head, of shape `[N, BOARD_SIZE * BOARD_SIZE]`, if [NetworkConfig::reply_policy_head] is set.
+    pub op_reply_p_logits: Option<Operation>,
+    /// Output of the ownership head, of shape `[N, BOARD_SIZE, BOARD_SIZE]`, if [NetworkConfig::ownership_head] is set.
+    pub op_ownership_output: Option<Operation>,
+    /// Boolean scalar switching the batch normalization layers to the statistics of the batch. It defaults to false.
+    pub op_training: Operation,
+    /// Updates the moving averages of the batch normalization layers; it should be run with the training step.
+    pub op_update_moving_averages: Operation,
+    /// All variables of the network, which are saved in the checkpoints.
     pub variables: Vec<Variable>,
+    /// The variables updated by the optimizer, i.e. all but the moving averages of the batch normalization layers.
+    pub trainable_variables: Vec<Variable>,
 }
 
 impl Network {
-    pub const INPUT_SIZE: i64 = Environment::BOARD_SIZE as i64;
-    pub const INPUT_CHANNELS: i64 = 2;
-
-    pub const RESIDUAL_FILTER_SIZE: i64 = 3;
-    pub const RESIDUAL_CHANNELS: i64 = 128;
-    pub const RESIDUAL_MIDDLE_CHANNELS: i64 = 32;
-    pub const RESIDUAL_STRIDE: i64 = 1;
-    pub const RESIDUAL_COUNT: i64 = 7;
-
-    pub const V_CONV_FILTER_SIZE: i64 = 1;
-    pub const V_CONV_CHANNELS: i64 = 1;
-    pub const V_CONV_STRIDE: i64 = 1;
-
-    pub const V_FLATTEN_SIZE: i64 =
-        Environment::BOARD_SIZE as i64 * Environment::BOARD_SIZE as i64 * Self::V_CONV_CHANNELS;
-
-    pub const V_FC0_SIZE: i64 = 1;
-
-    pub const P_CONV_FILTER_SIZE: i64 = 1;
-    pub const P_CONV_CHANNELS: i64 = 2;
-    pub const P_CONV_STRIDE: i64 = 1;
-
-    pub const P_FLATTEN_SIZE: i64 =
-        Environment::BOARD_SIZE as i64 * Environment::BOARD_SIZE as i64 * Self::P_CONV_CHANNELS;
-
-    pub const P_FC0_SIZE: i64 = Environment::BOARD_SIZE as i64 * Environment::BOARD_SIZE as i64;
-    pub const P_OUTPUT_SIZE: i64 = Environment::BOARD_SIZE as i64;
+    pub const BATCH_NORM_MOMENTUM: f32 = 0.99;
 
     pub fn new(
+        config: NetworkConfig,
         op_p_label: Operation,
         scope: &mut Scope,
         input_name: impl AsRef<str>,
@@ -56,117 +43,307 @@ impl Network {
         let mut variables = Vec::new();
         let op_input = Placeholder::new()
             .dtype(DataType::Float)
-            .shape([-1, Self::INPUT_SIZE, Self::INPUT_SIZE, Self::INPUT_CHANNELS])
+            .shape([
+                -1,
+                NetworkConfig::INPUT_SIZE,
+                NetworkConfig::INPUT_SIZE,
+                config.input_channels(),
+            ])
             .build(&mut scope.with_op_name(input_name.as_ref()))?;
+        let op_training = PlaceholderWithDefault::new()
+            .dtype(DataType::Bool)
+            .shape(Shape::from(Some(vec![])))
+            .build(constant(false, scope)?, &mut scope.with_op_name("training"))?;
+
+        let batch_norm = config.batch_norm.then(|| BatchNormOptions {
+            training: op_training.clone(),
+            momentum: Self::BATCH_NORM_MOMENTUM,
+        });
+        let mut batch_norms = Vec::new();
 
         let conv = network_utils::conv2d(
             "conv",
             DataType::Float,
             op_input.clone(),
-            Self::INPUT_CHANNELS,
-            Self::RESIDUAL_CHANNELS,
+            config.input_channels(),
+            config.channels,
             &[1, 1],
             &[1, 1],
             Conv2DPadding::Same,
             WeightInitializer::He,
             scope,
         )?;
-        let activation = leaky_relu(conv.output, &mut scope.with_op_name("conv_activation"))?;
+        let mut conv_batch_norms = Vec::new();
+        let conv_output = network_utils::normalize(
+            "conv_bn",
+            DataType::Float,
+            conv.output,
+            config.channels,
+            batch_norm.as_ref(),
+            &mut conv_batch_norms,
+            scope,
+        )?;
+        let activation = config
+            .activation
+            .build(conv_output, &mut scope.with_op_name("conv_activation"))?;
         variables.push(conv.w);
         variables.push(conv.b);
+        push_batch_norms(&mut variables, &mut batch_norms, conv_batch_norms);
 
         let mut previous = activation;
 
-        for i in 0..Self::RESIDUAL_COUNT {
-            let residual = network_utils::conv2d_bottleneck_residual(
-                format!("residual_{}", i),
-                DataType::Float,
-                previous,
-                Self::RESIDUAL_CHANNELS,
-                Self::RESIDUAL_MIDDLE_CHANNELS,
-                &[Self::RESIDUAL_FILTER_SIZE, Self::RESIDUAL_FILTER_SIZE],
-                &[Self::RESIDUAL_STRIDE, Self::RESIDUAL_STRIDE],
-                Conv2DPadding::Same,
-                WeightInitializer::He,
-                scope,
-            )?;
-            let activation = leaky_relu(
-                residual.output,
-                &mut scope.with_op_name(&format!("residual_{}_activation", i)),
-            )?;
+        for i in 0..config.block_count {
+            let name = format!("residual_{}", i);
+            let filter_size = [config.filter_size, config.filter_size];
+            let stride = [
+                NetworkConfig::RESIDUAL_STRIDE,
+                NetworkConfig::RESIDUAL_STRIDE,
+            ];
+
+            let output = match config.block_type {
+                BlockType::Residual => {
+                    let residual = network_utils::conv2d_residual(
+                        name,
+                        DataType::Float,
+                        previous,
+                        config.channels,
+                        config.channels,
+                        &filter_size,
+                        &stride,
+                        Conv2DPadding::Same,
+                        WeightInitializer::He,
+                        config.activation,
+                        batch_norm.as_ref(),
+                        scope,
+                    )?;
+
+                    variables.push(residual.w0);
+                    variables.push(residual.b0);
+
+                    variables.push(residual.w1);
+                    variables.push(residual.b1);
+
+                    push_batch_norms(&mut variables, &mut batch_norms, residual.batch_norms);
+
+                    residual.output
+                }
+                BlockType::Bottleneck => {
+                    let residual = network_utils::conv2d_bottleneck_residual(
+                        name,
+                        DataType::Float,
+                        previous,
+                        config.channels,
+                        config.bottleneck_channels,
+                        &filter_size,
+                        &stride,
+                        Conv2DPadding::Same,
+                        WeightInitializer::He,
+                        config.activation,
+                        batch_norm.as_ref(),
+                        scope,
+                    )?;
 
-            variables.push(residual.w0);
-            variables.push(residual.b0);
+                    variables.push(residual.w0);
+                    variables.push(residual.b0);
 
-            variables.push(residual.depthwise_w1);
-            variables.push(residual.pointwise_w1);
-            variables.push(residual.b1);
+                    variables.push(residual.depthwise_w1);
+                    variables.push(residual.pointwise_w1);
+                    variables.push(residual.b1);
 
-            variables.push(residual.w2);
-            variables.push(residual.b2);
+                    variables.push(residual.w2);
+                    variables.push(residual.b2);
 
-            previous = activation;
+                    push_batch_norms(&mut variables, &mut batch_norms, residual.batch_norms);
+
+                    residual.output
+                }
+                BlockType::Separable => {
+                    let residual = network_utils::conv2d_separable_residual(
+                        name,
+                        DataType::Float,
+                        previous,
+                        config.channels,
+                        &filter_size,
+                        &stride,
+                        Conv2DPadding::Same,
+                        WeightInitializer::He,
+                        config.activation,
+                        batch_norm.as_ref(),
+                        scope,
+                    )?;
+
+                    variables.push(residual.depthwise_w0);
+                    variables.push(residual.pointwise_w0);
+                    variables.push(residual.b0);
+
+                    variables.push(residual.depthwise_w1);
+                    variables.push(residual.pointwise_w1);
+                    variables.push(residual.b1);
+
+                    push_batch_norms(&mut variables, &mut batch_norms, residual.batch_norms);
+
+                    residual.output
+                }
+                BlockType::SqueezeExcitation => {
+                    let residual = network_utils::se_residual(
+                        name,
+                        DataType::Float,
+                        previous,
+                        config.channels,
+                        config.se_channels,
+                        &filter_size,
+                        &stride,
+                        Conv2DPadding::Same,
+                        WeightInitializer::He,
+                        config.activation,
+                        batch_norm.as_ref(),
+                        scope,
+                    )?;
+
+                    variables.push(residual.w0);
+                    variables.push(residual.b0);
+
+                    variables.push(residual.w1);
+                    variables.push(residual.b1);
+
+                    variables.push(residual.se_w0);
+                    variables.push(residual.se_b0);
+                    variables.push(residual.se_scale_w);
+                    variables.push(residual.se_scale_b);
+                    variables.push(residual.se_bias_w);
+                    variables.push(residual.se_bias_b);
+
+                    push_batch_norms(&mut variables, &mut batch_norms, residual.batch_norms);
+
+                    residual.output
+                }
+            };
+
+            previous = config.activation.build(
+                output,
+                &mut scope.with_op_name(&format!("residual_{}_activation", i)),
+            )?;
         }
 
         let v_conv = network_utils::conv2d(
             "v_conv",
             DataType::Float,
             previous.clone(),
-            Self::RESIDUAL_CHANNELS,
-            Self::V_CONV_CHANNELS,
-            &[Self::V_CONV_FILTER_SIZE, Self::V_CONV_FILTER_SIZE],
-            &[Self::V_CONV_STRIDE, Self::V_CONV_STRIDE],
+            config.channels,
+            config.v_conv_channels,
+            &[
+                NetworkConfig::V_CONV_FILTER_SIZE,
+                NetworkConfig::V_CONV_FILTER_SIZE,
+            ],
+            &[NetworkConfig::V_CONV_STRIDE, NetworkConfig::V_CONV_STRIDE],
             Conv2DPadding::Same,
             WeightInitializer::He,
             scope,
         )?;
-        let v_conv_activation =
-            leaky_relu(v_conv.output, &mut scope.with_op_name("v_conv_activation"))?;
+        let mut v_conv_batch_norms = Vec::new();
+        let v_conv_output = network_utils::normalize(
+            "v_conv_bn",
+            DataType::Float,
+            v_conv.output,
+            config.v_conv_channels,
+            batch_norm.as_ref(),
+            &mut v_conv_batch_norms,
+            scope,
+        )?;
+        let v_conv_activation = config
+            .activation
+            .build(v_conv_output, &mut scope.with_op_name("v_conv_activation"))?;
         variables.push(v_conv.w);
         variables.push(v_conv.b);
+        push_batch_norms(&mut variables, &mut batch_norms, v_conv_batch_norms);
 
         let v_flatten = reshape(
             v_conv_activation,
-            constant(&[-1, Self::V_FLATTEN_SIZE], scope)?,
+            constant(&[-1, config.v_flatten_size()], scope)?,
             &mut scope.with_op_name("v_flatten"),
         )?;
 
-        let v_fc0 = network_utils::fc(
-            "v_fc0",
-            DataType::Float,
-            v_flatten,
-            Self::V_FLATTEN_SIZE,
-            Self::V_FC0_SIZE,
-            WeightInitializer::Xavier,
-            scope,
-        )?;
-        let v_fc0_activation = tanh(
-            v_fc0.output,
-            &mut scope.with_op_name(v_output_name.as_ref()),
-        )?;
-        variables.push(v_fc0.w);
-        variables.push(v_fc0.b);
+        let v_fc_output = if config.v_fc_size == 0 {
+            let v_fc0 = network_utils::fc(
+                "v_fc0",
+                DataType::Float,
+                v_flatten,
+                config.v_flatten_size(),
+                NetworkConfig::V_OUTPUT_SIZE,
+                WeightInitializer::Xavier,
+                scope,
+            )?;
+            variables.push(v_fc0.w);
+            variables.push(v_fc0.b);
+
+            v_fc0.output
+        } else {
+            let v_fc0 = network_utils::fc(
+                "v_fc0",
+                DataType::Float,
+                v_flatten,
+                config.v_flatten_size(),
+                config.v_fc_size,
+                WeightInitializer::He,
+                scope,
+            )?;
+            let v_fc0_activation = config
+                .activation
+                .build(v_fc0.output, &mut scope.with_op_name("v_fc0_activation"))?;
+            variables.push(v_fc0.w);
+            variables.push(v_fc0.b);
+
+            let v_fc1 = network_utils::fc(
+                "v_fc1",
+                DataType::Float,
+                v_fc0_activation,
+                config.v_fc_size,
+                NetworkConfig::V_OUTPUT_SIZE,
+                WeightInitializer::Xavier,
+                scope,
+            )?;
+            variables.push(v_fc1.w);
+            variables.push(v_fc1.b);
+
+            v_fc1.output
+        };
+        let v_output = tanh(v_fc_output, &mut scope.with_op_name(v_output_name.as_ref()))?;
 
         let p_conv = network_utils::conv2d(
             "p_conv",
             DataType::Float,
             previous.clone(),
-            Self::RESIDUAL_CHANNELS,
-            Self::P_CONV_CHANNELS,
-            &[Self::P_CONV_FILTER_SIZE, Self::P_CONV_FILTER_SIZE],
-            &[Self::P_CONV_STRIDE, Self::P_CONV_STRIDE],
+            config.channels,
+            config.p_conv_channels,
+            &[
+                NetworkConfig::P_CONV_FILTER_SIZE,
+                NetworkConfig::P_CONV_FILTER_SIZE,
+            ],
+            &[NetworkConfig::P_CONV_STRIDE, NetworkConfig::P_CONV_STRIDE],
             Conv2DPadding::Same,
             WeightInitializer::He,
             scope,
         )?;
-        let p_conv_activation =
-            leaky_relu(p_conv.output, &mut scope.with_op_name("p_conv_activation"))?;
+        let mut p_conv_batch_norms = Vec::new();
+        let p_conv_output = network_utils::normalize(
+            "p_conv_bn",
+            DataType::Float,
+            p_conv.output,
+            config.p_conv_channels,
+            batch_norm.as_ref(),
+            &mut p_conv_batch_norms,
+            scope,
+        )?;
+        let p_conv_activation = config
+            .activation
+            .build(p_conv_output, &mut scope.with_op_name("p_conv_activation"))?;
         variables.push(p_conv.w);
         variables.push(p_conv.b);
+        push_batch_norms(&mut variables, &mut batch_norms, p_conv_batch_norms);
 
         let p_flatten = reshape(
             p_conv_activation,
-            constant(&[-1, Self::P_FLATTEN_SIZE], scope)?,
+            constant(&[-1, config.p_flatten_size()], scope)?,
             &mut scope.with_op_name("p_flatten"),
         )?;
 
@@ -174,8 +351,8 @@ impl Network {
             "p_fc0",
             DataType::Float,
             p_flatten,
-            Self::P_FLATTEN_SIZE,
-            Self::P_FC0_SIZE,
+            config.p_flatten_size(),
+            NetworkConfig::P_FC0_SIZE,
             WeightInitializer::Xavier,
             scope,
         )?;
@@ -188,7 +365,14 @@ impl Network {
 
         let p_output = reshape(
             p_fc0_activation.clone(),
-            constant(&[-1, Self::P_OUTPUT_SIZE, Self::P_OUTPUT_SIZE], scope)?,
+            constant(
+                &[
+                    -1,
+                    NetworkConfig::P_OUTPUT_SIZE,
+                    NetworkConfig::P_OUTPUT_SIZE,
+                ],
+                scope,
+            )?,
             &mut scope.with_op_name(p_output_name.as_ref()),
         )?;
 
@@ -198,12 +382,145 @@ impl Network {
             &mut scope.with_op_name(p_loss_name.as_ref()),
         )?;
 
+        let reply_p_logits = if config.reply_policy_head {
+            let reply_conv = network_utils::conv2d(
+                "reply_conv",
+                DataType::Float,
+                previous.clone(),
+                config.channels,
+                config.p_conv_channels,
+                &[
+                    NetworkConfig::P_CONV_FILTER_SIZE,
+                    NetworkConfig::P_CONV_FILTER_SIZE,
+                ],
+                &[NetworkConfig::P_CONV_STRIDE, NetworkConfig::P_CONV_STRIDE],
+                Conv2DPadding::Same,
+                WeightInitializer::He,
+                scope,
+            )?;
+            let mut reply_conv_batch_norms = Vec::new();
+            let reply_conv_output = network_utils::normalize(
+                "reply_conv_bn",
+                DataType::Float,
+                reply_conv.output,
+                config.p_conv_channels,
+                batch_norm.as_ref(),
+                &mut reply_conv_batch_norms,
+                scope,
+            )?;
+            let reply_conv_activation = config.activation.build(
+                reply_conv_output,
+                &mut scope.with_op_name("reply_conv_activation"),
+            )?;
+            variables.push(reply_conv.w);
+            variables.push(reply_conv.b);
+            push_batch_norms(&mut variables, &mut batch_norms, reply_conv_batch_norms);
+
+            let reply_flatten = reshape(
+                reply_conv_activation,
+                constant(&[-1, config.p_flatten_size()], scope)?,
+                &mut scope.with_op_name("reply_flatten"),
+            )?;
+
+            let reply_fc0 = network_utils::fc(
+                "reply_fc0",
+                DataType::Float,
+                reply_flatten,
+                config.p_flatten_size(),
+                NetworkConfig::P_FC0_SIZE,
+                WeightInitializer::Xavier,
+                scope,
+            )?;
+            variables.push(reply_fc0.w);
+            variables.push(reply_fc0.b);
+
+            Some(reply_fc0.output)
+        } else {
+            None
+        };
+
+        let ownership_output = if config.ownership_head {
+            let ownership_conv = network_utils::conv2d(
+                "ownership_conv",
+                DataType::Float,
+                previous.clone(),
+                config.channels,
+                1,
+                &[
+                    NetworkConfig::OWNERSHIP_CONV_FILTER_SIZE,
+                    NetworkConfig::OWNERSHIP_CONV_FILTER_SIZE,
+                ],
+                &[
+                    NetworkConfig::OWNERSHIP_CONV_STRIDE,
+                    NetworkConfig::OWNERSHIP_CONV_STRIDE,
+                ],
+                Conv2DPadding::Same,
+                WeightInitializer::Xavier,
+                scope,
+            )?;
+            variables.push(ownership_conv.w);
+            variables.push(ownership_conv.b);
+
+            Some(reshape(
+                tanh(ownership_conv.output, scope)?,
+                constant(
+                    &[-1, NetworkConfig::INPUT_SIZE, NetworkConfig::INPUT_SIZE],
+                    scope,
+                )?,
+                &mut scope.with_op_name("ownership_output"),
+            )?)
+        } else {
+            None
+        };
+
+        let mut op_update_moving_averages = NoOp::new();
+
+        for batch_norm in &batch_norms {
+            op_update_moving_averages =
+                op_update_moving_averages.add_control_input(batch_norm.update.clone());
+        }
+
+        let op_update_moving_averages =
+            op_update_moving_averages.build(&mut scope.with_op_name("update_moving_averages"))?;
+
+        let trainable_variables = variables
+            .iter()
+            .filter(|variable| {
+                batch_norms.iter().all(|batch_norm| {
+                    variable.name() != batch_norm.mean.name()
+                        && variable.name() != batch_norm.variance.name()
+                })
+            })
+            .cloned()
+            .collect();
+
         Ok(Self {
+            config,
             op_input,
-            op_v_output: v_fc0_activation,
+            op_v_output: v_output,
             op_p_output: p_output,
             op_p_loss: p_loss,
+            op_reply_p_logits: reply_p_logits,
+            op_ownership_output: ownership_output,
+            op_training,
+            op_update_moving_averages,
             variables,
+            trainable_variables,
         })
     }
 }
+
+/// Appends the variables of the batch normalization layers in the order scale, offset, mean and variance.
+fn push_batch_norms(
+    variables: &mut Vec<Variable>,
+    batch_norms: &mut Vec<BatchNorm>,
+    layer_batch_norms: Vec<BatchNorm>,
+) {
+    for batch_norm in layer_batch_norms {
+        variables.push(batch_norm.scale.clone());
+        variables.push(batch_norm.offset.clone());
+        variables.push(batch_norm.mean.clone());
+        variables.push(batch_norm.variance.clone());
+        batch_norms.push(batch_norm);
+    }
+}
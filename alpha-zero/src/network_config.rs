@@ -0,0 +1,99 @@
+use crate::FeatureConfig;
+use environment::Environment;
+use network_utils::Activation;
+use serde::{Deserialize, Serialize};
+
+/// Type of the residual blocks of the tower.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockType {
+    /// Two full convolutions, see [network_utils::conv2d_residual].
+    Residual,
+    /// A 1x1 reduction, a separable convolution and a 1x1 expansion, see [network_utils::conv2d_bottleneck_residual].
+    Bottleneck,
+    /// Two separable convolutions, see [network_utils::conv2d_separable_residual].
+    Separable,
+    /// Two full convolutions with squeeze-and-excitation over global pooling, see [network_utils::se_residual].
+    SqueezeExcitation,
+}
+
+/// Architecture of the network. This is read from the trainer config and stored in model checkpoints.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// Input planes of the network.
+    pub features: FeatureConfig,
+    pub block_count: i64,
+    pub block_type: BlockType,
+    pub channels: i64,
+    /// Channels inside the bottleneck blocks; unused by the other block types.
+    pub bottleneck_channels: i64,
+    /// Channels of the squeeze layer of the squeeze-and-excitation blocks; unused by the other block types.
+    pub se_channels: i64,
+    pub filter_size: i64,
+    pub activation: Activation,
+    /// Applies batch normalization after every convolution of the tower and the heads,
+    /// except the output convolution of the ownership head.
+    pub batch_norm: bool,
+    pub v_conv_channels: i64,
+    /// Size of the hidden layer of the value head. The value head has no hidden layer if it is 0.
+    pub v_fc_size: i64,
+    pub p_conv_channels: i64,
+    /// Adds an auxiliary head predicting the reply of the opponent, built like the policy head. It is only used for training.
+    pub reply_policy_head: bool,
+    /// Adds an auxiliary head predicting, for each point, whether it is part of the winning line of the side to move (1)
+    /// or of its opponent (-1). It is only used for training.
+    pub ownership_head: bool,
+}
+
+impl NetworkConfig {
+    pub const INPUT_SIZE: i64 = Environment::BOARD_SIZE as i64;
+
+    pub const RESIDUAL_STRIDE: i64 = 1;
+
+    pub const V_CONV_FILTER_SIZE: i64 = 1;
+    pub const V_CONV_STRIDE: i64 = 1;
+    pub const V_OUTPUT_SIZE: i64 = 1;
+
+    pub const P_CONV_FILTER_SIZE: i64 = 1;
+    pub const P_CONV_STRIDE: i64 = 1;
+
+    pub const P_FC0_SIZE: i64 = Environment::BOARD_SIZE as i64 * Environment::BOARD_SIZE as i64;
+    pub const P_OUTPUT_SIZE: i64 = Environment::BOARD_SIZE as i64;
+
+    pub const OWNERSHIP_CONV_FILTER_SIZE: i64 = 1;
+    pub const OWNERSHIP_CONV_STRIDE: i64 = 1;
+
+    pub fn input_channels(&self) -> i64 {
+        self.features.channels() as i64
+    }
+
+    pub fn v_flatten_size(&self) -> i64 {
+        Environment::BOARD_SIZE as i64 * Environment::BOARD_SIZE as i64 * self.v_conv_channels
+    }
+
+    pub fn p_flatten_size(&self) -> i64 {
+        Environment::BOARD_SIZE as i64 * Environment::BOARD_SIZE as i64 * self.p_conv_channels
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            features: FeatureConfig::default(),
+            block_count: 7,
+            block_type: BlockType::Bottleneck,
+            channels: 128,
+            bottleneck_channels: 32,
+            se_channels: 32,
+            filter_size: 3,
+            activation: Activation::LeakyRelu,
+            batch_norm: false,
+            v_conv_channels: 1,
+            v_fc_size: 0,
+            p_conv_channels: 2,
+            reply_policy_head: false,
+            ownership_head: false,
+        }
+    }
+}
@@ -0,0 +1,655 @@
+use crate::{
+    cpu_backend::{BatchNorm, Block, Conv2D, DepthwiseConv2D, Fc, SeparableConv2D},
+    CpuBackend, ModelIOError, NetworkConfig,
+};
+use environment::Environment;
+use network_utils::{Activation, BATCH_NORM_EPSILON};
+use prost::Message;
+use std::{fs::File, io::Write, path::Path};
+
+impl CpuBackend {
+    /// ONNX operator set the exported model is written against.
+    pub const ONNX_OPSET_VERSION: i64 = 13;
+
+    /// Writes the network and its weights as an ONNX model.
+    ///
+    /// The model takes `input` of shape `[N, BOARD_SIZE, BOARD_SIZE, input_channels]`, the same NHWC layout fed to TensorFlow,
+    /// and produces `p_output` of shape `[N, BOARD_SIZE, BOARD_SIZE]` and `v_output` of shape `[N, 1]`.
+    /// Nodes are named after the corresponding TensorFlow operations.
+    pub fn export_onnx(&self, path: impl AsRef<Path>) -> Result<(), ModelIOError> {
+        let model = self.build_onnx_model();
+        let mut file = File::create(path)?;
+        file.write_all(&model.encode_to_vec())?;
+        Ok(())
+    }
+
+    fn build_onnx_model(&self) -> proto::ModelProto {
+        let mut graph = GraphBuilder::default();
+
+        // ONNX convolutions are NCHW, while the network is NHWC.
+        let x = graph.node(
+            "Transpose",
+            "input_nchw",
+            &["input"],
+            vec![ints("perm", &[0, 3, 1, 2])],
+        );
+
+        let activation = self.network.activation;
+
+        let x = graph.conv2d("conv", &x, &self.conv);
+        let x = graph.normalize("conv_bn", &x, self.conv_bn.as_ref());
+        let mut x = graph.activation("conv_activation", &x, activation);
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            let block = graph.block(&format!("residual_{}", index), &x, block, activation);
+            x = graph.activation(
+                &format!("residual_{}_activation", index),
+                &block,
+                activation,
+            );
+        }
+
+        let v = graph.conv2d("v_conv", &x, &self.v_conv);
+        let v = graph.normalize("v_conv_bn", &v, self.v_conv_bn.as_ref());
+        let v = graph.activation("v_conv_activation", &v, activation);
+        let v = graph.flatten("v_flatten", &v, self.network.v_flatten_size());
+        let mut v = graph.fc("v_fc0", &v, &self.v_fc0);
+
+        if let Some(v_fc1) = &self.v_fc1 {
+            let v_fc0 = graph.activation("v_fc0_activation", &v, activation);
+            v = graph.fc("v_fc1", &v_fc0, v_fc1);
+        }
+
+        graph.node("Tanh", "v_output", &[&v], vec![]);
+
+        let p = graph.conv2d("p_conv", &x, &self.p_conv);
+        let p = graph.normalize("p_conv_bn", &p, self.p_conv_bn.as_ref());
+        let p = graph.activation("p_conv_activation", &p, activation);
+        let p = graph.flatten("p_flatten", &p, self.network.p_flatten_size());
+        let p = graph.fc("p_fc0", &p, &self.p_fc0);
+        let p = graph.node("Softmax", "p_fc0_activation", &[&p], vec![int("axis", 1)]);
+        let shape = graph.shape(
+            "p_output_shape",
+            &[
+                -1,
+                Environment::BOARD_SIZE as i64,
+                Environment::BOARD_SIZE as i64,
+            ],
+        );
+        graph.node("Reshape", "p_output", &[&p, &shape], vec![]);
+
+        let board_size = Some(Environment::BOARD_SIZE as i64);
+
+        proto::ModelProto {
+            ir_version: 7,
+            opset_import: vec![proto::OperatorSetIdProto {
+                domain: String::new(),
+                version: Self::ONNX_OPSET_VERSION,
+            }],
+            producer_name: env!("CARGO_PKG_NAME").to_owned(),
+            producer_version: env!("CARGO_PKG_VERSION").to_owned(),
+            graph: Some(proto::GraphProto {
+                node: graph.nodes,
+                name: "omok-ai".to_owned(),
+                initializer: graph.initializers,
+                input: vec![value_info(
+                    "input",
+                    &[
+                        None,
+                        board_size,
+                        board_size,
+                        Some(self.network.input_channels()),
+                    ],
+                )],
+                output: vec![
+                    value_info("p_output", &[None, board_size, board_size]),
+                    value_info("v_output", &[None, Some(NetworkConfig::V_OUTPUT_SIZE)]),
+                ],
+            }),
+        }
+    }
+}
+
+/// Collects the nodes and initializers of the graph.
+/// Each node has a single output named after the node, which is returned by the builder methods.
+#[derive(Default)]
+struct GraphBuilder {
+    nodes: Vec<proto::NodeProto>,
+    initializers: Vec<proto::TensorProto>,
+}
+
+impl GraphBuilder {
+    fn node(
+        &mut self,
+        op_type: &str,
+        name: &str,
+        inputs: &[&str],
+        attribute: Vec<proto::AttributeProto>,
+    ) -> String {
+        self.nodes.push(proto::NodeProto {
+            input: inputs.iter().map(|&input| input.to_owned()).collect(),
+            output: vec![name.to_owned()],
+            name: name.to_owned(),
+            op_type: op_type.to_owned(),
+            attribute,
+        });
+        name.to_owned()
+    }
+
+    fn initializer(&mut self, name: &str, dims: &[i64], float_data: Vec<f32>) -> String {
+        self.initializers.push(proto::TensorProto {
+            dims: dims.to_vec(),
+            data_type: proto::TensorProto::FLOAT,
+            float_data,
+            int64_data: vec![],
+            name: name.to_owned(),
+        });
+        name.to_owned()
+    }
+
+    fn shape(&mut self, name: &str, shape: &[i64]) -> String {
+        self.initializers.push(proto::TensorProto {
+            dims: vec![shape.len() as i64],
+            data_type: proto::TensorProto::INT64,
+            float_data: vec![],
+            int64_data: shape.to_vec(),
+            name: name.to_owned(),
+        });
+        name.to_owned()
+    }
+
+    fn activation(&mut self, name: &str, x: &str, activation: Activation) -> String {
+        match activation {
+            Activation::LeakyRelu => self.node(
+                "LeakyRelu",
+                name,
+                &[x],
+                vec![float("alpha", CpuBackend::LEAKY_RELU_ALPHA)],
+            ),
+            Activation::Relu => self.node("Relu", name, &[x], vec![]),
+            Activation::Elu => self.node("Elu", name, &[x], vec![float("alpha", 1f32)]),
+        }
+    }
+
+    fn conv2d(&mut self, name: &str, x: &str, conv: &Conv2D) -> String {
+        // HWIO to OIHW.
+        let k = conv.filter_size;
+        let mut w = vec![0f32; conv.w.len()];
+
+        for row in 0..k {
+            for col in 0..k {
+                for i in 0..conv.input_channels {
+                    for o in 0..conv.output_channels {
+                        w[((o * conv.input_channels + i) * k + row) * k + col] =
+                            conv.w[((row * k + col) * conv.input_channels + i)
+                                * conv.output_channels
+                                + o];
+                    }
+                }
+            }
+        }
+
+        let w = self.initializer(
+            &format!("{}_w", name),
+            &[
+                conv.output_channels as i64,
+                conv.input_channels as i64,
+                k as i64,
+                k as i64,
+            ],
+            w,
+        );
+        let b = self.initializer(
+            &format!("{}_b", name),
+            &[conv.output_channels as i64],
+            conv.b.clone(),
+        );
+        let padding = ((k - 1) / 2) as i64;
+
+        self.node(
+            "Conv",
+            name,
+            &[x, &w, &b],
+            vec![
+                ints("kernel_shape", &[k as i64, k as i64]),
+                ints("pads", &[padding, padding, padding, padding]),
+            ],
+        )
+    }
+
+    fn depthwise_conv2d(&mut self, name: &str, x: &str, conv: &DepthwiseConv2D) -> String {
+        // HWC1 to C1HW.
+        let k = conv.filter_size;
+        let mut w = vec![0f32; conv.w.len()];
+
+        for row in 0..k {
+            for col in 0..k {
+                for c in 0..conv.channels {
+                    w[(c * k + row) * k + col] = conv.w[(row * k + col) * conv.channels + c];
+                }
+            }
+        }
+
+        let w = self.initializer(
+            &format!("{}_w", name),
+            &[conv.channels as i64, 1, k as i64, k as i64],
+            w,
+        );
+        let padding = ((k - 1) / 2) as i64;
+
+        self.node(
+            "Conv",
+            name,
+            &[x, &w],
+            vec![
+                int("group", conv.channels as i64),
+                ints("kernel_shape", &[k as i64, k as i64]),
+                ints("pads", &[padding, padding, padding, padding]),
+            ],
+        )
+    }
+
+    fn separable_conv2d(&mut self, name: &str, x: &str, conv: &SeparableConv2D) -> String {
+        let y = self.depthwise_conv2d(&format!("{}_depthwise", name), x, &conv.depthwise);
+        self.conv2d(&format!("{}_pointwise", name), &y, &conv.pointwise)
+    }
+
+    fn batch_norm(&mut self, name: &str, x: &str, batch_norm: &BatchNorm) -> String {
+        let channels = [batch_norm.scale.len() as i64];
+        let scale = self.initializer(
+            &format!("{}_scale", name),
+            &channels,
+            batch_norm.scale.clone(),
+        );
+        let offset = self.initializer(
+            &format!("{}_offset", name),
+            &channels,
+            batch_norm.offset.clone(),
+        );
+        let mean = self.initializer(
+            &format!("{}_mean", name),
+            &channels,
+            batch_norm.mean.clone(),
+        );
+        let variance = self.initializer(
+            &format!("{}_variance", name),
+            &channels,
+            batch_norm.variance.clone(),
+        );
+
+        self.node(
+            "BatchNormalization",
+            name,
+            &[x, &scale, &offset, &mean, &variance],
+            vec![float("epsilon", BATCH_NORM_EPSILON)],
+        )
+    }
+
+    /// Applies the batch normalization if there is one.
+    fn normalize(&mut self, name: &str, x: &str, batch_norm: Option<&BatchNorm>) -> String {
+        match batch_norm {
+            Some(batch_norm) => self.batch_norm(name, x, batch_norm),
+            None => x.to_owned(),
+        }
+    }
+
+    fn block(&mut self, name: &str, x: &str, block: &Block, activation: Activation) -> String {
+        let y = match block {
+            Block::Residual(residual) => {
+                let y = self.conv2d(&format!("{}_conv0", name), x, &residual.conv0);
+                let y = self.normalize(&format!("{}_bn0", name), &y, residual.batch_norms.first());
+                let y = self.activation(&format!("{}_relu", name), &y, activation);
+                let y = self.conv2d(&format!("{}_conv1", name), &y, &residual.conv1);
+                self.normalize(&format!("{}_bn1", name), &y, residual.batch_norms.get(1))
+            }
+            Block::Bottleneck(residual) => {
+                let y = self.conv2d(&format!("{}_conv0", name), x, &residual.conv0);
+                let y = self.normalize(&format!("{}_bn0", name), &y, residual.batch_norms.first());
+                let y = self.activation(&format!("{}_activation0", name), &y, activation);
+                let y = self.separable_conv2d(&format!("{}_conv1", name), &y, &residual.conv1);
+                let y = self.normalize(&format!("{}_bn1", name), &y, residual.batch_norms.get(1));
+                let y = self.activation(&format!("{}_activation1", name), &y, activation);
+                let y = self.conv2d(&format!("{}_conv2", name), &y, &residual.conv2);
+                self.normalize(&format!("{}_bn2", name), &y, residual.batch_norms.get(2))
+            }
+            Block::Separable(residual) => {
+                let y = self.separable_conv2d(&format!("{}_conv0", name), x, &residual.conv0);
+                let y = self.normalize(&format!("{}_bn0", name), &y, residual.batch_norms.first());
+                let y = self.activation(&format!("{}_activation0", name), &y, activation);
+                let y = self.separable_conv2d(&format!("{}_conv1", name), &y, &residual.conv1);
+                self.normalize(&format!("{}_bn1", name), &y, residual.batch_norms.get(1))
+            }
+            Block::SqueezeExcitation(residual) => {
+                let y = self.conv2d(&format!("{}_conv0", name), x, &residual.conv0);
+                let y = self.normalize(&format!("{}_bn0", name), &y, residual.batch_norms.first());
+                let y = self.activation(&format!("{}_activation0", name), &y, activation);
+                let y = self.conv2d(&format!("{}_conv1", name), &y, &residual.conv1);
+                let y = self.normalize(&format!("{}_bn1", name), &y, residual.batch_norms.get(1));
+
+                let pool = self.global_pool(&format!("{}_pool", name), &y);
+                let se = self.fc(&format!("{}_se_fc0", name), &pool, &residual.se_fc0);
+                let se = self.activation(&format!("{}_se_activation", name), &se, activation);
+
+                // The per-channel outputs are broadcast over the board as `[N, C, 1, 1]`.
+                let shape = self.shape(
+                    &format!("{}_se_shape", name),
+                    &[-1, residual.se_scale.outputs as i64, 1, 1],
+                );
+                let scale = self.fc(&format!("{}_se_scale", name), &se, &residual.se_scale);
+                let scale = self.node(
+                    "Reshape",
+                    &format!("{}_se_scale_reshape", name),
+                    &[&scale, &shape],
+                    vec![],
+                );
+                let scale = self.node(
+                    "Sigmoid",
+                    &format!("{}_se_sigmoid", name),
+                    &[&scale],
+                    vec![],
+                );
+                let bias = self.fc(&format!("{}_se_bias", name), &se, &residual.se_bias);
+                let bias = self.node(
+                    "Reshape",
+                    &format!("{}_se_bias_reshape", name),
+                    &[&bias, &shape],
+                    vec![],
+                );
+
+                let y = self.node("Mul", &format!("{}_se_mul", name), &[&y, &scale], vec![]);
+                self.node("Add", &format!("{}_se_add", name), &[&y, &bias], vec![])
+            }
+        };
+        self.node("Add", &format!("{}_add", name), &[&y, x], vec![])
+    }
+
+    /// Pools NCHW activations into `[N, 2 * C]`: the averages followed by the maxima.
+    fn global_pool(&mut self, name: &str, x: &str) -> String {
+        let avg = self.node(
+            "GlobalAveragePool",
+            &format!("{}_avg_pool", name),
+            &[x],
+            vec![],
+        );
+        let max = self.node("GlobalMaxPool", &format!("{}_max_pool", name), &[x], vec![]);
+        let pool = self.node(
+            "Concat",
+            &format!("{}_concat", name),
+            &[&avg, &max],
+            vec![int("axis", 1)],
+        );
+        self.node("Flatten", name, &[&pool], vec![])
+    }
+
+    /// Flattens NCHW activations in the NHWC order used by TensorFlow.
+    fn flatten(&mut self, name: &str, x: &str, size: i64) -> String {
+        let nhwc = self.node(
+            "Transpose",
+            &format!("{}_nhwc", name),
+            &[x],
+            vec![ints("perm", &[0, 2, 3, 1])],
+        );
+        let shape = self.shape(&format!("{}_shape", name), &[-1, size]);
+        self.node("Reshape", name, &[&nhwc, &shape], vec![])
+    }
+
+    fn fc(&mut self, name: &str, x: &str, fc: &Fc) -> String {
+        let w = self.initializer(
+            &format!("{}_w", name),
+            &[fc.inputs as i64, fc.outputs as i64],
+            fc.w.clone(),
+        );
+        let b = self.initializer(&format!("{}_b", name), &[fc.outputs as i64], fc.b.clone());
+        self.node("Gemm", name, &[x, &w, &b], vec![])
+    }
+}
+
+fn float(name: &str, f: f32) -> proto::AttributeProto {
+    proto::AttributeProto {
+        name: name.to_owned(),
+        r#type: proto::AttributeProto::FLOAT,
+        f,
+        ..Default::default()
+    }
+}
+
+fn int(name: &str, i: i64) -> proto::AttributeProto {
+    proto::AttributeProto {
+        name: name.to_owned(),
+        r#type: proto::AttributeProto::INT,
+        i,
+        ..Default::default()
+    }
+}
+
+fn ints(name: &str, ints: &[i64]) -> proto::AttributeProto {
+    proto::AttributeProto {
+        name: name.to_owned(),
+        r#type: proto::AttributeProto::INTS,
+        ints: ints.to_vec(),
+        ..Default::default()
+    }
+}
+
+/// Describes a float tensor; `None` dimensions are named `N`, the batch size.
+fn value_info(name: &str, shape: &[Option<i64>]) -> proto::ValueInfoProto {
+    proto::ValueInfoProto {
+        name: name.to_owned(),
+        r#type: Some(proto::TypeProto {
+            tensor_type: Some(proto::TypeProtoTensor {
+                elem_type: proto::TensorProto::FLOAT,
+                shape: Some(proto::TensorShapeProto {
+                    dim: shape
+                        .iter()
+                        .map(|dim| proto::Dimension {
+                            value: Some(match dim {
+                                Some(dim) => proto::DimensionValue::DimValue(*dim),
+                                None => proto::DimensionValue::DimParam("N".to_owned()),
+                            }),
+                        })
+                        .collect(),
+                }),
+            }),
+        }),
+    }
+}
+
+/// The subset of `onnx.proto` used by the exporter, with the same field numbers.
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ModelProto {
+        #[prost(int64, tag = "1")]
+        pub ir_version: i64,
+        #[prost(message, repeated, tag = "8")]
+        pub opset_import: Vec<OperatorSetIdProto>,
+        #[prost(string, tag = "2")]
+        pub producer_name: String,
+        #[prost(string, tag = "3")]
+        pub producer_version: String,
+        #[prost(message, optional, tag = "7")]
+        pub graph: Option<GraphProto>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct OperatorSetIdProto {
+        #[prost(string, tag = "1")]
+        pub domain: String,
+        #[prost(int64, tag = "2")]
+        pub version: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GraphProto {
+        #[prost(message, repeated, tag = "1")]
+        pub node: Vec<NodeProto>,
+        #[prost(string, tag = "2")]
+        pub name: String,
+        #[prost(message, repeated, tag = "5")]
+        pub initializer: Vec<TensorProto>,
+        #[prost(message, repeated, tag = "11")]
+        pub input: Vec<ValueInfoProto>,
+        #[prost(message, repeated, tag = "12")]
+        pub output: Vec<ValueInfoProto>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct NodeProto {
+        #[prost(string, repeated, tag = "1")]
+        pub input: Vec<String>,
+        #[prost(string, repeated, tag = "2")]
+        pub output: Vec<String>,
+        #[prost(string, tag = "3")]
+        pub name: String,
+        #[prost(string, tag = "4")]
+        pub op_type: String,
+        #[prost(message, repeated, tag = "5")]
+        pub attribute: Vec<AttributeProto>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AttributeProto {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(int32, tag = "20")]
+        pub r#type: i32,
+        #[prost(float, tag = "2")]
+        pub f: f32,
+        #[prost(int64, tag = "3")]
+        pub i: i64,
+        #[prost(int64, repeated, tag = "8")]
+        pub ints: Vec<i64>,
+    }
+
+    impl AttributeProto {
+        pub const FLOAT: i32 = 1;
+        pub const INT: i32 = 2;
+        pub const INTS: i32 = 7;
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TensorProto {
+        #[prost(int64, repeated, tag = "1")]
+        pub dims: Vec<i64>,
+        #[prost(int32, tag = "2")]
+        pub data_type: i32,
+        #[prost(float, repeated, tag = "4")]
+        pub float_data: Vec<f32>,
+        #[prost(int64, repeated, tag = "7")]
+        pub int64_data: Vec<i64>,
+        #[prost(string, tag = "8")]
+        pub name: String,
+    }
+
+    impl TensorProto {
+        pub const FLOAT: i32 = 1;
+        pub const INT64: i32 = 7;
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ValueInfoProto {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(message, optional, tag = "2")]
+        pub r#type: Option<TypeProto>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TypeProto {
+        #[prost(message, optional, tag = "1")]
+        pub tensor_type: Option<TypeProtoTensor>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TypeProtoTensor {
+        #[prost(int32, tag = "1")]
+        pub elem_type: i32,
+        #[prost(message, optional, tag = "2")]
+        pub shape: Option<TensorShapeProto>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TensorShapeProto {
+        #[prost(message, repeated, tag = "1")]
+        pub dim: Vec<Dimension>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Dimension {
+        #[prost(oneof = "DimensionValue", tags = "1, 2")]
+        pub value: Option<DimensionValue>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum DimensionValue {
+        #[prost(int64, tag = "1")]
+        DimValue(i64),
+        #[prost(string, tag = "2")]
+        DimParam(String),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        test_checkpoint::{test_networks, variable_shapes, zero_checkpoint},
+        Checkpoint, NetworkConfig,
+    };
+
+    #[test]
+    fn test_export_onnx() {
+        for network in test_networks() {
+            check_export_onnx(zero_checkpoint(network));
+        }
+    }
+
+    fn check_export_onnx(checkpoint: Checkpoint) {
+        // The auxiliary heads are only used in training, so they are not exported.
+        let saved = variable_shapes(&NetworkConfig {
+            reply_policy_head: false,
+            ownership_head: false,
+            ..checkpoint.header.network
+        })
+        .iter()
+        .map(|shape| shape.iter().product::<u64>() as usize)
+        .sum::<usize>();
+        let backend = CpuBackend::from_checkpoint(checkpoint).unwrap();
+        let bytes = backend.build_onnx_model().encode_to_vec();
+        let model = proto::ModelProto::decode(&bytes[..]).unwrap();
+        let graph = model.graph.unwrap();
+
+        assert_eq!(graph.input.len(), 1);
+        assert_eq!(graph.input[0].name, "input");
+        assert_eq!(
+            graph
+                .output
+                .iter()
+                .map(|output| output.name.as_str())
+                .collect::<Vec<_>>(),
+            ["p_output", "v_output"]
+        );
+
+        // Every node input is either a graph input, an initializer or an output of a previous node.
+        let mut defined = vec!["input".to_owned()];
+        defined.extend(graph.initializer.iter().map(|tensor| tensor.name.clone()));
+
+        for node in &graph.node {
+            for input in &node.input {
+                assert!(defined.contains(input), "{} is not defined", input);
+            }
+
+            defined.extend(node.output.iter().cloned());
+        }
+
+        assert!(defined.contains(&"p_output".to_owned()));
+        assert!(defined.contains(&"v_output".to_owned()));
+
+        // All weights are exported.
+        let exported = graph
+            .initializer
+            .iter()
+            .map(|tensor| tensor.float_data.len())
+            .sum::<usize>();
+        assert_eq!(exported, saved);
+    }
+}
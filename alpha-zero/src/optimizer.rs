@@ -0,0 +1,437 @@
+use crate::model_io::variable_shape;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use tensorflow::{
+    ops::{
+        add, constant, cos, div, floor, minimum, mul, pow, sub, ApplyAdam, ApplyMomentum, Assign,
+        NoOp,
+    },
+    train::{
+        AdadeltaOptimizer, ComputeGradientsOptions, GradientDescentOptimizer, MinimizeOptions,
+        Optimizer,
+    },
+    Operation, Output, Scope, Status, Tensor, Variable,
+};
+
+/// Optimizer of the training and its hyperparameters.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum OptimizerType {
+    /// Stochastic gradient descent with momentum.
+    Sgd {
+        momentum: f32,
+        nesterov: bool,
+    },
+    Adam {
+        beta1: f32,
+        beta2: f32,
+        epsilon: f32,
+    },
+    Adadelta {
+        rho: f32,
+        epsilon: f32,
+    },
+}
+
+/// Decay of the learning rate over the training steps.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LearningRateSchedule {
+    Constant,
+    /// Multiplies the learning rate by `decay` every `interval` steps.
+    Step {
+        interval: u64,
+        decay: f32,
+    },
+    /// Decays the learning rate to `min_learning_rate` along a half cosine over `decay_steps` steps, and keeps it there.
+    Cosine {
+        decay_steps: u64,
+        min_learning_rate: f32,
+    },
+}
+
+/// Optimization of the [AgentModel](crate::AgentModel). This is read from the trainer config.
+///
+/// The optimizer variables are saved in the checkpoints, so changing the optimizer of a saved model
+/// requires a checkpoint saved without them.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct OptimizerConfig {
+    pub learning_rate: f32,
+    /// Number of steps over which the learning rate rises linearly to the scheduled one. There is no warm-up if it is 0.
+    pub warmup_steps: u64,
+    /// Coefficient of the L2 regularization of the network weights. Biases and batch normalization are not regularized.
+    pub l2_regularization: f32,
+    /// Weight of the loss of the reply policy head, relative to the policy and value losses. Unused if the network has no such head.
+    pub reply_policy_loss_weight: f32,
+    /// Weight of the loss of the ownership head, relative to the policy and value losses. Unused if the network has no such head.
+    pub ownership_loss_weight: f32,
+    pub optimizer: OptimizerType,
+    pub schedule: LearningRateSchedule,
+}
+
+impl OptimizerConfig {
+    /// Returns the learning rate at the given training step, as computed by [OptimizerConfig::build_learning_rate].
+    pub fn learning_rate(&self, step: u64) -> f32 {
+        let step = step as f32;
+        let learning_rate = match self.schedule {
+            LearningRateSchedule::Constant => self.learning_rate,
+            LearningRateSchedule::Step { interval, decay } => {
+                self.learning_rate * decay.powf((step / interval as f32).floor())
+            }
+            LearningRateSchedule::Cosine {
+                decay_steps,
+                min_learning_rate,
+            } => {
+                let progress = (step / decay_steps as f32).min(1f32);
+                min_learning_rate
+                    + (self.learning_rate - min_learning_rate)
+                        * 0.5
+                        * (1f32 + (PI * progress).cos())
+            }
+        };
+
+        if self.warmup_steps == 0 {
+            learning_rate
+        } else {
+            learning_rate * ((step + 1f32) / self.warmup_steps as f32).min(1f32)
+        }
+    }
+
+    /// Builds the learning rate from `step`, a float scalar holding the training step.
+    pub fn build_learning_rate(
+        &self,
+        step: Operation,
+        scope: &mut Scope,
+    ) -> Result<Operation, Status> {
+        let learning_rate = constant(self.learning_rate, scope)?;
+        let learning_rate = match self.schedule {
+            LearningRateSchedule::Constant => learning_rate,
+            LearningRateSchedule::Step { interval, decay } => mul(
+                learning_rate,
+                pow(
+                    constant(decay, scope)?,
+                    floor(
+                        div(step.clone(), constant(interval as f32, scope)?, scope)?,
+                        scope,
+                    )?,
+                    scope,
+                )?,
+                scope,
+            )?,
+            LearningRateSchedule::Cosine {
+                decay_steps,
+                min_learning_rate,
+            } => {
+                let progress = minimum(
+                    div(step.clone(), constant(decay_steps as f32, scope)?, scope)?,
+                    constant(1f32, scope)?,
+                    scope,
+                )?;
+                let cosine = mul(
+                    constant(0.5f32, scope)?,
+                    add(
+                        constant(1f32, scope)?,
+                        cos(mul(constant(PI, scope)?, progress, scope)?, scope)?,
+                        scope,
+                    )?,
+                    scope,
+                )?;
+                let min_learning_rate = constant(min_learning_rate, scope)?;
+                add(
+                    min_learning_rate.clone(),
+                    mul(sub(learning_rate, min_learning_rate, scope)?, cosine, scope)?,
+                    scope,
+                )?
+            }
+        };
+
+        if self.warmup_steps == 0 {
+            Ok(learning_rate)
+        } else {
+            let warmup = minimum(
+                div(
+                    add(step, constant(1f32, scope)?, scope)?,
+                    constant(self.warmup_steps as f32, scope)?,
+                    scope,
+                )?,
+                constant(1f32, scope)?,
+                scope,
+            )?;
+            mul(learning_rate, warmup, scope)
+        }
+    }
+
+    /// Builds the update of `variables` minimizing `loss`; returns the variables of the optimizer and the update.
+    pub fn minimize(
+        &self,
+        loss: Operation,
+        learning_rate: Operation,
+        variables: &[Variable],
+        scope: &mut Scope,
+    ) -> Result<(Vec<Variable>, Operation), Status> {
+        let options = MinimizeOptions::default().with_variables(variables);
+
+        match self.optimizer {
+            OptimizerType::Sgd { momentum, nesterov } => {
+                minimize_momentum(loss, learning_rate, momentum, nesterov, variables, scope)
+            }
+            OptimizerType::Adam {
+                beta1,
+                beta2,
+                epsilon,
+            } => minimize_adam(loss, learning_rate, beta1, beta2, epsilon, variables, scope),
+            OptimizerType::Adadelta { rho, epsilon } => {
+                let mut optimizer = AdadeltaOptimizer::new();
+                optimizer.set_learning_rate(learning_rate);
+                optimizer.set_rho(constant(rho, scope)?);
+                optimizer.set_epsilon(constant(epsilon, scope)?);
+                optimizer.minimize(scope, loss.output(0), options)
+            }
+        }
+    }
+}
+
+impl Default for OptimizerConfig {
+    fn default() -> Self {
+        Self {
+            learning_rate: 0.01,
+            warmup_steps: 0,
+            l2_regularization: 0f32,
+            reply_policy_loss_weight: 0.15,
+            ownership_loss_weight: 0.15,
+            optimizer: OptimizerType::Adadelta {
+                rho: 0.95,
+                epsilon: 1e-8,
+            },
+            schedule: LearningRateSchedule::Constant,
+        }
+    }
+}
+
+/// TensorFlow's Rust API has no momentum optimizer, so the update is built from the gradients directly.
+/// Each variable gets an accumulator named `{variable}_momentum`.
+fn minimize_momentum(
+    loss: Operation,
+    learning_rate: Operation,
+    momentum: f32,
+    nesterov: bool,
+    variables: &[Variable],
+    scope: &mut Scope,
+) -> Result<(Vec<Variable>, Operation), Status> {
+    let grads_and_vars = compute_gradients(loss, learning_rate.clone(), variables, scope)?;
+    let momentum = constant(momentum, scope)?;
+
+    let mut accumulators = Vec::with_capacity(grads_and_vars.len());
+    let mut op_minimize = NoOp::new();
+
+    for (grad, variable) in grads_and_vars {
+        let grad = match grad {
+            Some(grad) => grad,
+            None => continue,
+        };
+
+        let accumulator = zeros_like(&variable, "momentum", scope)?;
+        let update = ApplyMomentum::new().use_nesterov(nesterov).build(
+            variable.output().clone(),
+            accumulator.output().clone(),
+            learning_rate.clone(),
+            grad,
+            momentum.clone(),
+            scope,
+        )?;
+
+        op_minimize = op_minimize.add_control_input(update);
+        accumulators.push(accumulator);
+    }
+
+    Ok((
+        accumulators,
+        op_minimize.build(&mut scope.with_op_name("minimize"))?,
+    ))
+}
+
+/// TensorFlow's Rust API has no Adam optimizer either. Each variable gets the moment estimates
+/// `{variable}_adam_m` and `{variable}_adam_v`, and the powers of the decay rates are shared by all of them.
+fn minimize_adam(
+    loss: Operation,
+    learning_rate: Operation,
+    beta1: f32,
+    beta2: f32,
+    epsilon: f32,
+    variables: &[Variable],
+    scope: &mut Scope,
+) -> Result<(Vec<Variable>, Operation), Status> {
+    let grads_and_vars = compute_gradients(loss, learning_rate.clone(), variables, scope)?;
+    let beta1_power = Variable::builder()
+        .const_initial_value(beta1)
+        .build(&mut scope.with_op_name("adam_beta1_power"))?;
+    let beta2_power = Variable::builder()
+        .const_initial_value(beta2)
+        .build(&mut scope.with_op_name("adam_beta2_power"))?;
+    let beta1 = constant(beta1, scope)?;
+    let beta2 = constant(beta2, scope)?;
+    let epsilon = constant(epsilon, scope)?;
+
+    let mut moments = Vec::with_capacity(2 * grads_and_vars.len() + 2);
+    let mut updates = Vec::with_capacity(grads_and_vars.len());
+
+    for (grad, variable) in grads_and_vars {
+        let grad = match grad {
+            Some(grad) => grad,
+            None => continue,
+        };
+
+        let m = zeros_like(&variable, "adam_m", scope)?;
+        let v = zeros_like(&variable, "adam_v", scope)?;
+        let update = ApplyAdam::new().build(
+            variable.output().clone(),
+            m.output().clone(),
+            v.output().clone(),
+            beta1_power.output().clone(),
+            beta2_power.output().clone(),
+            learning_rate.clone(),
+            beta1.clone(),
+            beta2.clone(),
+            epsilon.clone(),
+            grad,
+            scope,
+        )?;
+
+        updates.push(update);
+        moments.push(m);
+        moments.push(v);
+    }
+
+    // The powers are advanced only after every variable has been updated with the current ones.
+    let mut update_beta1_power = Assign::new();
+    let mut update_beta2_power = Assign::new();
+
+    for update in &updates {
+        update_beta1_power = update_beta1_power.add_control_input(update.clone());
+        update_beta2_power = update_beta2_power.add_control_input(update.clone());
+    }
+
+    let update_beta1_power = update_beta1_power.build(
+        beta1_power.output().clone(),
+        mul(beta1_power.output().clone(), beta1, scope)?,
+        scope,
+    )?;
+    let update_beta2_power = update_beta2_power.build(
+        beta2_power.output().clone(),
+        mul(beta2_power.output().clone(), beta2, scope)?,
+        scope,
+    )?;
+    let op_minimize = NoOp::new()
+        .add_control_input(update_beta1_power)
+        .add_control_input(update_beta2_power)
+        .build(&mut scope.with_op_name("minimize"))?;
+
+    moments.push(beta1_power);
+    moments.push(beta2_power);
+    Ok((moments, op_minimize))
+}
+
+fn compute_gradients(
+    loss: Operation,
+    learning_rate: Operation,
+    variables: &[Variable],
+    scope: &mut Scope,
+) -> Result<Vec<(Option<Output>, Variable)>, Status> {
+    GradientDescentOptimizer::new(learning_rate).compute_gradients(
+        scope,
+        loss.output(0),
+        ComputeGradientsOptions::default().with_variables(variables),
+    )
+}
+
+/// Creates a variable of zeros shaped like `variable`, named `{variable}_{suffix}`.
+fn zeros_like(variable: &Variable, suffix: &str, scope: &mut Scope) -> Result<Variable, Status> {
+    Variable::builder()
+        .data_type(variable.data_type())
+        .shape(variable.shape().clone())
+        .const_initial_value(Tensor::<f32>::new(&variable_shape(variable)))
+        .build(&mut scope.with_op_name(&format!("{}_{}", variable.name(), suffix)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_learning_rate_schedules() {
+        let step = OptimizerConfig {
+            learning_rate: 0.1,
+            schedule: LearningRateSchedule::Step {
+                interval: 100,
+                decay: 0.5,
+            },
+            ..Default::default()
+        };
+        assert_eq!(step.learning_rate(0), 0.1);
+        assert_eq!(step.learning_rate(99), 0.1);
+        assert_eq!(step.learning_rate(100), 0.05);
+        assert_eq!(step.learning_rate(250), 0.025);
+
+        let cosine = OptimizerConfig {
+            learning_rate: 0.1,
+            schedule: LearningRateSchedule::Cosine {
+                decay_steps: 100,
+                min_learning_rate: 0.01,
+            },
+            ..Default::default()
+        };
+        assert_eq!(cosine.learning_rate(0), 0.1);
+        assert!((cosine.learning_rate(50) - 0.055).abs() < 1e-6);
+        assert_eq!(cosine.learning_rate(100), 0.01);
+        assert_eq!(cosine.learning_rate(1000), 0.01);
+    }
+
+    #[test]
+    fn test_learning_rate_warmup() {
+        let config = OptimizerConfig {
+            learning_rate: 0.1,
+            warmup_steps: 10,
+            ..Default::default()
+        };
+        assert!((config.learning_rate(0) - 0.01).abs() < 1e-6);
+        assert!((config.learning_rate(4) - 0.05).abs() < 1e-6);
+        assert_eq!(config.learning_rate(9), 0.1);
+        assert_eq!(config.learning_rate(100), 0.1);
+    }
+
+    #[test]
+    fn test_config_toml() {
+        let config = OptimizerConfig {
+            optimizer: OptimizerType::Sgd {
+                momentum: 0.9,
+                nesterov: true,
+            },
+            schedule: LearningRateSchedule::Cosine {
+                decay_steps: 1000,
+                min_learning_rate: 0.001,
+            },
+            ..Default::default()
+        };
+
+        let contents = toml::to_string(&config).unwrap();
+        assert_eq!(
+            toml::from_str::<OptimizerConfig>(&contents).unwrap(),
+            config
+        );
+
+        let config = toml::from_str::<OptimizerConfig>(
+            "learning_rate = 0.001\n[optimizer]\ntype = \"adam\"\nbeta1 = 0.9\nbeta2 = 0.999\nepsilon = 1e-7\n",
+        )
+        .unwrap();
+        assert_eq!(config.schedule, LearningRateSchedule::Constant);
+        assert_eq!(
+            config.optimizer,
+            OptimizerType::Adam {
+                beta1: 0.9,
+                beta2: 0.999,
+                epsilon: 1e-7,
+            }
+        );
+    }
+}
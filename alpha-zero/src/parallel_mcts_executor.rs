@@ -1,4 +1,4 @@
-use crate::{encode_nn_input, Agent, AgentModel, BoardState, EnvTurnMode};
+use crate::{encode_nn_input, Agent, BoardState, EnvTurnMode, InferenceBackend, InferenceError};
 use atomic_float::AtomicF32;
 use bitvec::vec::BitVec;
 use environment::{Environment, GameStatus, Stone};
@@ -7,8 +7,7 @@ use parking_lot::RwLock;
 use rand::prelude::*;
 use rand_distr::Dirichlet;
 use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
-use std::sync::atomic::Ordering;
-use tensorflow::{Session, Status};
+use std::{sync::atomic::Ordering, time::Instant};
 
 pub struct ParallelMCTSExecutor {
     thread_pool: ThreadPool,
@@ -23,16 +22,33 @@ impl ParallelMCTSExecutor {
         }
     }
 
+    /// Creates an executor running on the given number of threads.
+    /// Since each agent is searched by a single thread at a time, the search is reproducible
+    /// with [Agent::with_seed] for any number of threads.
+    pub fn with_num_threads(num_threads: usize) -> Self {
+        Self {
+            thread_pool: ThreadPoolBuilder::new()
+                .num_threads(num_threads)
+                .build()
+                .unwrap(),
+        }
+    }
+
     pub fn execute(
         &self,
         count: usize,
         batch_size: usize,
         epsilon: f32,
         alpha: f32,
-        agent_model: &AgentModel,
-        session: &Session,
+        backend: &dyn InferenceBackend,
         agents: &[Agent],
-    ) -> Result<(), Status> {
+    ) -> Result<(), InferenceError> {
+        let started = Instant::now();
+        let node_counts = agents
+            .iter()
+            .map(|agent| agent.mcts.node_count())
+            .collect::<Vec<_>>();
+
         self.thread_pool.install(|| {
             let mut processed_count = 0;
 
@@ -44,7 +60,7 @@ impl ParallelMCTSExecutor {
                 let requests = agents
                     .par_iter()
                     .flat_map(|agent| {
-                        let mut rng = thread_rng();
+                        let mut rng = agent.fork_rng();
 
                         // Apply Dirichlet noise to the root node.
                         if processed_count == 0 {
@@ -210,11 +226,12 @@ impl ParallelMCTSExecutor {
                 }
 
                 let input = encode_nn_input(
+                    backend.features(),
                     requests.len(),
                     EnvTurnMode::Player,
                     requests.iter().map(|request| &request.node.state.env),
                 );
-                let (policy, value) = agent_model.evaluate_pv(session, input)?;
+                let (policy, value) = backend.evaluate_pv(requests.len(), &input)?;
 
                 for (batch_index, request) in requests.iter().enumerate() {
                     let node = &*request.node;
@@ -262,8 +279,18 @@ impl ParallelMCTSExecutor {
                 }
             }
 
-            Ok(())
-        })
+            Ok::<_, InferenceError>(())
+        })?;
+
+        let elapsed = started.elapsed();
+
+        for (agent, node_count) in agents.iter().zip(node_counts) {
+            agent.record_search(elapsed, node_count);
+            #[cfg(test)]
+            assert_eq!(agent.mcts.validate(), Ok(()));
+        }
+
+        Ok(())
     }
 }
 
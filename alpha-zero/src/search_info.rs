@@ -0,0 +1,60 @@
+use environment::Environment;
+use std::fmt::Display;
+
+/// Statistics of a root child after the search.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChildInfo {
+    pub action: usize,
+    /// Number of visits.
+    pub n: u64,
+    /// Mean action value, in perspective of the player to move at the root.
+    pub q: f32,
+    /// Prior probability.
+    pub p: f32,
+}
+
+/// Summary of the search performed on the current tree. See [Agent::search_info](super::Agent::search_info).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchInfo {
+    /// Root children, sorted by the number of visits in descending order.
+    pub children: Vec<ChildInfo>,
+    /// Actions obtained by following the most visited child from the root.
+    pub principal_variation: Vec<usize>,
+    /// Number of visits of the root node.
+    pub root_n: u64,
+    /// Number of nodes in the tree, including the root node.
+    pub node_count: usize,
+    /// Depth of the deepest node, where the root node has depth 0.
+    pub depth: usize,
+    /// Number of nodes expanded per second since the root node has been set.
+    pub nodes_per_second: f32,
+}
+
+impl SearchInfo {
+    /// Returns the most visited root child, which is the action selected by [ActionSamplingMode::Best](super::ActionSamplingMode::Best).
+    pub fn best_child(&self) -> Option<&ChildInfo> {
+        self.children.first()
+    }
+}
+
+impl Display for SearchInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "depth {} nodes {} nps {:.0} visits {}",
+            self.depth, self.node_count, self.nodes_per_second, self.root_n
+        )?;
+
+        if let Some(best) = self.best_child() {
+            write!(f, " q {:.3}", best.q)?;
+        }
+
+        write!(f, " pv")?;
+
+        for &action in &self.principal_variation {
+            write!(f, " {}", Environment::action_to_notation(action))?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+use crate::{
+    inference_backend::check_input_size, AgentModel, FeatureConfig, InferenceBackend,
+    InferenceError,
+};
+use environment::Environment;
+use tensorflow::{Session, Tensor};
+
+/// Evaluates the network with the TensorFlow graph of the [AgentModel], which is required for training.
+/// For inference only, [CpuBackend](super::CpuBackend) can be used instead.
+pub struct TensorflowBackend<'a> {
+    pub agent_model: &'a AgentModel,
+    pub session: &'a Session,
+}
+
+impl<'a> TensorflowBackend<'a> {
+    pub fn new(agent_model: &'a AgentModel, session: &'a Session) -> Self {
+        Self {
+            agent_model,
+            session,
+        }
+    }
+
+    fn input_tensor(
+        &self,
+        batch_size: usize,
+        input: &[f32],
+    ) -> Result<Tensor<f32>, InferenceError> {
+        check_input_size(self.features(), batch_size, input)?;
+
+        Ok(Tensor::new(&[
+            batch_size as u64,
+            Environment::BOARD_SIZE as u64,
+            Environment::BOARD_SIZE as u64,
+            self.features().channels() as u64,
+        ])
+        .with_values(input)?)
+    }
+}
+
+impl<'a> InferenceBackend for TensorflowBackend<'a> {
+    fn features(&self) -> &FeatureConfig {
+        &self.agent_model.io.network.features
+    }
+
+    fn evaluate_p(&self, batch_size: usize, input: &[f32]) -> Result<Vec<f32>, InferenceError> {
+        let input = self.input_tensor(batch_size, input)?;
+        Ok(self.agent_model.evaluate_p(self.session, input)?.to_vec())
+    }
+
+    fn evaluate_pv(
+        &self,
+        batch_size: usize,
+        input: &[f32],
+    ) -> Result<(Vec<f32>, Vec<f32>), InferenceError> {
+        let input = self.input_tensor(batch_size, input)?;
+        let (p, v) = self.agent_model.evaluate_pv(self.session, input)?;
+        Ok((p.to_vec(), v.to_vec()))
+    }
+}
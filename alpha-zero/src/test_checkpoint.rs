@@ -0,0 +1,184 @@
+//! Checkpoints shared by the tests, built without TensorFlow.
+
+use crate::{BlockType, Checkpoint, CheckpointHeader, FeatureConfig, NetworkConfig, SavedVariable};
+use network_utils::Activation;
+
+/// Small architectures covering every block type, activation and value head,
+/// with and without batch normalization, extra input planes and auxiliary heads.
+pub fn test_networks() -> Vec<NetworkConfig> {
+    vec![
+        NetworkConfig::default(),
+        NetworkConfig {
+            block_count: 2,
+            block_type: BlockType::Residual,
+            channels: 8,
+            activation: Activation::Relu,
+            batch_norm: true,
+            v_fc_size: 16,
+            ..Default::default()
+        },
+        NetworkConfig {
+            features: FeatureConfig {
+                color_to_move: true,
+                last_moves: 3,
+                forbidden_points: true,
+            },
+            block_count: 2,
+            block_type: BlockType::Separable,
+            channels: 8,
+            activation: Activation::Elu,
+            v_conv_channels: 2,
+            p_conv_channels: 1,
+            ..Default::default()
+        },
+        NetworkConfig {
+            block_count: 2,
+            block_type: BlockType::SqueezeExcitation,
+            channels: 8,
+            se_channels: 4,
+            batch_norm: true,
+            reply_policy_head: true,
+            ownership_head: true,
+            ..Default::default()
+        },
+    ]
+}
+
+/// Returns the shapes of [Network::variables] in order.
+pub fn variable_shapes(network: &NetworkConfig) -> Vec<Vec<u64>> {
+    let input_channels = network.input_channels() as u64;
+    let channels = network.channels as u64;
+    let middle_channels = network.bottleneck_channels as u64;
+    let filter_size = network.filter_size as u64;
+
+    let batch_norm = |channels: u64| -> Vec<Vec<u64>> {
+        if network.batch_norm {
+            vec![vec![channels]; 4]
+        } else {
+            vec![]
+        }
+    };
+
+    let mut shapes = vec![vec![1, 1, input_channels, channels], vec![channels]];
+    shapes.extend(batch_norm(channels));
+
+    for _ in 0..network.block_count {
+        match network.block_type {
+            BlockType::Residual => shapes.extend(
+                [
+                    vec![filter_size, filter_size, channels, channels],
+                    vec![channels],
+                    vec![filter_size, filter_size, channels, channels],
+                    vec![channels],
+                ]
+                .into_iter()
+                .chain(batch_norm(channels))
+                .chain(batch_norm(channels)),
+            ),
+            BlockType::Bottleneck => shapes.extend(
+                [
+                    vec![1, 1, channels, middle_channels],
+                    vec![middle_channels],
+                    vec![filter_size, filter_size, middle_channels, 1],
+                    vec![1, 1, middle_channels, middle_channels],
+                    vec![middle_channels],
+                    vec![1, 1, middle_channels, channels],
+                    vec![channels],
+                ]
+                .into_iter()
+                .chain(batch_norm(middle_channels))
+                .chain(batch_norm(middle_channels))
+                .chain(batch_norm(channels)),
+            ),
+            BlockType::Separable => shapes.extend(
+                [
+                    vec![filter_size, filter_size, channels, 1],
+                    vec![1, 1, channels, channels],
+                    vec![channels],
+                    vec![filter_size, filter_size, channels, 1],
+                    vec![1, 1, channels, channels],
+                    vec![channels],
+                ]
+                .into_iter()
+                .chain(batch_norm(channels))
+                .chain(batch_norm(channels)),
+            ),
+            BlockType::SqueezeExcitation => {
+                let se_channels = network.se_channels as u64;
+                shapes.extend(
+                    [
+                        vec![filter_size, filter_size, channels, channels],
+                        vec![channels],
+                        vec![filter_size, filter_size, channels, channels],
+                        vec![channels],
+                        vec![2 * channels, se_channels],
+                        vec![se_channels],
+                        vec![se_channels, channels],
+                        vec![channels],
+                        vec![se_channels, channels],
+                        vec![channels],
+                    ]
+                    .into_iter()
+                    .chain(batch_norm(channels))
+                    .chain(batch_norm(channels)),
+                )
+            }
+        }
+    }
+
+    let v_conv_channels = network.v_conv_channels as u64;
+    let v_flatten_size = network.v_flatten_size() as u64;
+    let v_output_size = NetworkConfig::V_OUTPUT_SIZE as u64;
+
+    shapes.extend([vec![1, 1, channels, v_conv_channels], vec![v_conv_channels]]);
+    shapes.extend(batch_norm(v_conv_channels));
+
+    if network.v_fc_size == 0 {
+        shapes.extend([vec![v_flatten_size, v_output_size], vec![v_output_size]]);
+    } else {
+        let v_fc_size = network.v_fc_size as u64;
+        shapes.extend([
+            vec![v_flatten_size, v_fc_size],
+            vec![v_fc_size],
+            vec![v_fc_size, v_output_size],
+            vec![v_output_size],
+        ]);
+    }
+
+    let p_conv_channels = network.p_conv_channels as u64;
+    let p_flatten_size = network.p_flatten_size() as u64;
+    let p_fc0_size = NetworkConfig::P_FC0_SIZE as u64;
+
+    shapes.extend([vec![1, 1, channels, p_conv_channels], vec![p_conv_channels]]);
+    shapes.extend(batch_norm(p_conv_channels));
+    shapes.extend([vec![p_flatten_size, p_fc0_size], vec![p_fc0_size]]);
+
+    if network.reply_policy_head {
+        shapes.extend([vec![1, 1, channels, p_conv_channels], vec![p_conv_channels]]);
+        shapes.extend(batch_norm(p_conv_channels));
+        shapes.extend([vec![p_flatten_size, p_fc0_size], vec![p_fc0_size]]);
+    }
+
+    if network.ownership_head {
+        shapes.extend([vec![1, 1, channels, 1], vec![1]]);
+    }
+
+    shapes
+}
+
+/// A checkpoint of zeros for the given architecture.
+pub fn zero_checkpoint(network: NetworkConfig) -> Checkpoint {
+    Checkpoint {
+        header: CheckpointHeader::new(network, 0),
+        variables: variable_shapes(&network)
+            .into_iter()
+            .enumerate()
+            .map(|(index, shape)| SavedVariable {
+                name: format!("variable_{}", index),
+                values: vec![0f32; shape.iter().product::<u64>() as usize],
+                shape,
+            })
+            .collect(),
+        optimizer_variables: vec![],
+    }
+}
@@ -1,13 +1,16 @@
 use std::path::Path;
 
-use alpha_zero::{ActionSamplingMode, AgentModel, MCTSExecutor};
-use tensorflow::{Scope, Session, SessionOptions};
+use alpha_zero::{ActionSamplingMode, CpuBackend, MCTSExecutor, ModelIOError};
 
 pub struct Agent {
     pub agent: alpha_zero::Agent,
-    pub agent_model: AgentModel,
-    pub session: Session,
+    pub backend: CpuBackend,
     pub mcts_executor: MCTSExecutor,
+    pub move_count: usize,
+    pub nodes_per_second_sum: f32,
+    /// Number of opening moves of each game sampled from the search policy instead of played greedily,
+    /// so that repeated games between the same models differ.
+    pub opening_move_count: usize,
 }
 
 impl Agent {
@@ -15,41 +18,58 @@ impl Agent {
     pub const ALPHA: f32 = 1.0;
 
     pub fn new(path: impl AsRef<Path>) -> Self {
-        let mut scope = Scope::new_root_scope();
-        let agent_model = AgentModel::new(&mut scope).unwrap();
-        let session = Session::new(&SessionOptions::new(), &scope.graph()).unwrap();
-
-        agent_model.io.load(&session, path).unwrap();
+        Self::load(path).unwrap()
+    }
 
-        let agent = alpha_zero::Agent::new(&agent_model, &session).unwrap();
+    /// Loads the model from a checkpoint file or a checkpoint store, like [CpuBackend::load].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ModelIOError> {
+        let backend = CpuBackend::load(path)?;
+        let agent = alpha_zero::Agent::new(&backend)?;
 
-        Self {
-            agent_model,
-            session,
+        Ok(Self {
+            backend,
             agent,
             mcts_executor: MCTSExecutor::new(),
-        }
+            move_count: 0,
+            nodes_per_second_sum: 0f32,
+            opening_move_count: 0,
+        })
     }
 
-    pub fn make_move(&self, mcts_count: usize, mcts_batch_size: usize) -> usize {
+    pub fn make_move(&mut self, mcts_count: usize, mcts_batch_size: usize) -> usize {
         self.mcts_executor
             .run(
                 mcts_count,
                 mcts_batch_size,
                 Self::EPSILON,
                 Self::ALPHA,
-                &self.agent_model,
-                &self.session,
+                &self.backend,
                 &self.agent,
             )
             .unwrap();
-        self.agent
-            .sample_action(ActionSamplingMode::Best)
-            .unwrap()
-            .0
+
+        self.move_count += 1;
+        self.nodes_per_second_sum += self.agent.search_info().nodes_per_second;
+
+        let mode = if self.agent.env.move_count() < self.opening_move_count {
+            ActionSamplingMode::Boltzmann(1f32)
+        } else {
+            ActionSamplingMode::Best
+        };
+
+        self.agent.sample_action(mode).unwrap().0
+    }
+
+    /// Returns the average search speed over all moves made so far.
+    pub fn average_nodes_per_second(&self) -> f32 {
+        if self.move_count == 0 {
+            return 0f32;
+        }
+
+        self.nodes_per_second_sum / self.move_count as f32
     }
 
     pub fn reset(&mut self) {
-        self.agent = alpha_zero::Agent::new(&self.agent_model, &self.session).unwrap();
+        self.agent = alpha_zero::Agent::new(&self.backend).unwrap();
     }
 }
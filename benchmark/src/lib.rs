@@ -0,0 +1,230 @@
+mod agent;
+
+pub use agent::*;
+
+use environment::{Environment, GameStatus, Turn};
+use rand::Rng;
+
+/// Outcome of the games of a match, from the perspective of the left agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MatchResult {
+    pub wins: usize,
+    pub losses: usize,
+    pub draws: usize,
+}
+
+impl MatchResult {
+    pub fn game_count(&self) -> usize {
+        self.wins + self.losses + self.draws
+    }
+
+    /// Returns the ratio of the games won, counting draws as half a win.
+    pub fn score(&self) -> f32 {
+        if self.game_count() == 0 {
+            return 0f32;
+        }
+
+        (self.wins as f32 + self.draws as f32 * 0.5) / self.game_count() as f32
+    }
+}
+
+/// Plays `game_count` games between the agents, the first half with `left` as black and the rest with `right` as black.
+pub fn play_match(
+    left: &mut Agent,
+    right: &mut Agent,
+    game_count: usize,
+    mcts_count: usize,
+    mcts_batch_size: usize,
+) -> MatchResult {
+    play_games(game_count, |left_is_black| {
+        let outcome = if left_is_black {
+            play_game(left, right, mcts_count, mcts_batch_size)
+        } else {
+            play_game(right, left, mcts_count, mcts_batch_size)
+        };
+
+        left.reset();
+        right.reset();
+        outcome
+    })
+}
+
+/// Plays the games of [play_match] with `play`, which is given whether the left agent is black and returns the outcome as [play_game] does.
+fn play_games(game_count: usize, mut play: impl FnMut(bool) -> i32) -> MatchResult {
+    let mut result = MatchResult::default();
+
+    for index in 0..game_count {
+        let left_is_black = index < game_count / 2;
+        let outcome = play(left_is_black);
+
+        // The outcome is 1 if black wins; turn it into the perspective of the left agent.
+        match if left_is_black { outcome } else { -outcome } {
+            1 => {
+                result.wins += 1;
+            }
+            -1 => {
+                result.losses += 1;
+            }
+            _ => {
+                result.draws += 1;
+            }
+        }
+    }
+
+    result
+}
+
+/// Plays a game between the agents, with `left` as black. Returns 1 if black wins, -1 if white wins and 0 for a draw.
+pub fn play_game(
+    left: &mut Agent,
+    right: &mut Agent,
+    mcts_count: usize,
+    mcts_batch_size: usize,
+) -> i32 {
+    loop {
+        let left_action = left.make_move(mcts_count, mcts_batch_size);
+        if let Some(status) = left.agent.play_action(left_action) {
+            match status {
+                GameStatus::InProgress => {}
+                GameStatus::Draw => {
+                    return 0;
+                }
+                GameStatus::BlackWin => {
+                    return 1;
+                }
+                GameStatus::WhiteWin => {
+                    return -1;
+                }
+            }
+        }
+
+        right
+            .agent
+            .ensure_action_exists(left_action, &right.backend)
+            .unwrap();
+        right.agent.play_action(left_action).unwrap();
+
+        let right_action = right.make_move(mcts_count, mcts_batch_size);
+        if let Some(status) = right.agent.play_action(right_action) {
+            match status {
+                GameStatus::InProgress => {}
+                GameStatus::Draw => {
+                    return 0;
+                }
+                GameStatus::BlackWin => {
+                    return 1;
+                }
+                GameStatus::WhiteWin => {
+                    return -1;
+                }
+            }
+        }
+
+        left.agent
+            .ensure_action_exists(right_action, &left.backend)
+            .unwrap();
+        left.agent.play_action(right_action).unwrap();
+    }
+}
+
+/// Plays `game_count` games of the agent against a player choosing uniformly among the legal moves,
+/// the first half with the agent as black. The result is from the perspective of the agent.
+pub fn play_match_against_random(
+    agent: &mut Agent,
+    rng: &mut impl Rng,
+    game_count: usize,
+    mcts_count: usize,
+    mcts_batch_size: usize,
+) -> MatchResult {
+    let mut result = MatchResult::default();
+
+    for index in 0..game_count {
+        let agent_is_black = index < game_count / 2;
+        let status = loop {
+            let is_agent_turn = (agent.agent.env.turn == Turn::Black) == agent_is_black;
+            let action = if is_agent_turn {
+                agent.make_move(mcts_count, mcts_batch_size)
+            } else {
+                let legal_moves = (0..Environment::BOARD_SIZE * Environment::BOARD_SIZE)
+                    .filter(|&action| agent.agent.env.is_legal_move(action))
+                    .collect::<Vec<_>>();
+                let action = legal_moves[rng.gen_range(0..legal_moves.len())];
+                agent
+                    .agent
+                    .ensure_action_exists(action, &agent.backend)
+                    .unwrap();
+                action
+            };
+
+            let status = agent.agent.play_action(action).unwrap();
+
+            if status.is_terminal() {
+                break status;
+            }
+        };
+
+        match (status, agent_is_black) {
+            (GameStatus::BlackWin, true) | (GameStatus::WhiteWin, false) => {
+                result.wins += 1;
+            }
+            (GameStatus::BlackWin, false) | (GameStatus::WhiteWin, true) => {
+                result.losses += 1;
+            }
+            _ => {
+                result.draws += 1;
+            }
+        }
+
+        agent.reset();
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_match_result_score() {
+        assert_eq!(MatchResult::default().score(), 0f32);
+
+        let result = MatchResult {
+            wins: 3,
+            losses: 2,
+            draws: 3,
+        };
+        assert_eq!(result.game_count(), 8);
+        assert_eq!(result.score(), 0.5625);
+    }
+
+    #[test]
+    fn test_play_games_alternates_colors() {
+        let mut colors = Vec::new();
+
+        // Black wins every game, so the left agent wins exactly the games it plays as black.
+        let result = play_games(5, |left_is_black| {
+            colors.push(left_is_black);
+            1
+        });
+        assert_eq!(colors, [true, true, false, false, false]);
+        assert_eq!(
+            result,
+            MatchResult {
+                wins: 2,
+                losses: 3,
+                draws: 0,
+            }
+        );
+        assert_eq!(result.score(), 0.4);
+
+        // The left agent wins every game, whichever color it plays.
+        let result = play_games(4, |left_is_black| if left_is_black { 1 } else { -1 });
+        assert_eq!(result.wins, 4);
+        assert_eq!(result.score(), 1f32);
+
+        let result = play_games(4, |_| 0);
+        assert_eq!(result.draws, 4);
+        assert_eq!(result.score(), 0.5);
+    }
+}
@@ -1,7 +1,4 @@
-use agent::Agent;
-use environment::GameStatus;
-
-mod agent;
+use benchmark::{play_match, Agent};
 
 const LEFT_AGENT_PATH: &str = "saves/alpha-zero";
 const RIGHT_AGENT_PATH: &str = "saves/alpha-zero-other";
@@ -17,92 +14,20 @@ fn main() {
 
     println!("Playing {} games...", GAME_COUNT);
 
-    let mut left_wins = 0;
-    let mut right_wins = 0;
-    let mut draws = 0;
-
-    for _ in 0..GAME_COUNT / 2 {
-        match play_game(&mut left, &mut right) {
-            1 => {
-                left_wins += 1;
-            }
-            -1 => {
-                right_wins += 1;
-            }
-            _ => {
-                draws += 1;
-            }
-        }
-
-        left.reset();
-        right.reset();
-    }
-
-    for _ in 0..GAME_COUNT / 2 {
-        match play_game(&mut right, &mut left) {
-            1 => {
-                right_wins += 1;
-            }
-            -1 => {
-                left_wins += 1;
-            }
-            _ => {
-                draws += 1;
-            }
-        }
-
-        left.reset();
-        right.reset();
-    }
-
-    println!("Left wins: {}", left_wins);
-    println!("Right wins: {}", right_wins);
-    println!("Draws: {}", draws);
-}
-
-fn play_game(left: &mut Agent, right: &mut Agent) -> i32 {
-    loop {
-        let left_action = left.make_move(MCTS_COUNT, MCTS_BATCH_SIZE);
-        if let Some(status) = left.agent.play_action(left_action) {
-            match status {
-                GameStatus::InProgress => {}
-                GameStatus::Draw => {
-                    return 0;
-                }
-                GameStatus::BlackWin => {
-                    return 1;
-                }
-                GameStatus::WhiteWin => {
-                    return -1;
-                }
-            }
-        }
-
-        right
-            .agent
-            .ensure_action_exists(left_action, &right.agent_model, &right.session)
-            .unwrap();
-        right.agent.play_action(left_action).unwrap();
-
-        let right_action = right.make_move(MCTS_COUNT, MCTS_BATCH_SIZE);
-        if let Some(status) = right.agent.play_action(right_action) {
-            match status {
-                GameStatus::InProgress => {}
-                GameStatus::Draw => {
-                    return 0;
-                }
-                GameStatus::BlackWin => {
-                    return 1;
-                }
-                GameStatus::WhiteWin => {
-                    return -1;
-                }
-            }
-        }
-
-        left.agent
-            .ensure_action_exists(right_action, &left.agent_model, &left.session)
-            .unwrap();
-        left.agent.play_action(right_action).unwrap();
-    }
+    let result = play_match(
+        &mut left,
+        &mut right,
+        GAME_COUNT,
+        MCTS_COUNT,
+        MCTS_BATCH_SIZE,
+    );
+
+    println!("Left wins: {}", result.wins);
+    println!("Right wins: {}", result.losses);
+    println!("Draws: {}", result.draws);
+    println!("Left nodes/second: {:.0}", left.average_nodes_per_second());
+    println!(
+        "Right nodes/second: {:.0}",
+        right.average_nodes_per_second()
+    );
 }
@@ -64,20 +64,66 @@ pub struct Environment {
     pub turn: Turn,
     pub legal_move_count: u16,
     pub board: [Stone; Self::BOARD_SIZE * Self::BOARD_SIZE],
+    /// The 1-based number of the move that placed the stone at each point, or 0 if it is empty.
+    /// It is laid out like `board`, so it can be rotated and flipped along with it.
+    pub move_numbers: [u8; Self::BOARD_SIZE * Self::BOARD_SIZE],
 }
 
 impl Environment {
     pub const BOARD_SIZE: usize = 15;
     pub const SERIAL_STONE_COUNT: usize = 5;
+    /// Name of the rules implemented by `place_stone`: exactly `SERIAL_STONE_COUNT` stones in a row win,
+    /// overlines do not count and there are no restrictions for black. This is stored in model checkpoints.
+    pub const RULE_SET: &str = "standard";
 
     pub fn new() -> Self {
         Environment {
             turn: Turn::Black,
             legal_move_count: (Self::BOARD_SIZE * Self::BOARD_SIZE) as u16,
             board: [Stone::Empty; Self::BOARD_SIZE * Self::BOARD_SIZE],
+            move_numbers: [0; Self::BOARD_SIZE * Self::BOARD_SIZE],
         }
     }
 
+    /// Returns the number of stones placed so far.
+    pub fn move_count(&self) -> usize {
+        Self::BOARD_SIZE * Self::BOARD_SIZE - self.legal_move_count as usize
+    }
+
+    /// Returns whether the side to move may place a stone at the point.
+    /// Only occupied points are illegal, as [Self::RULE_SET] has no restrictions for black.
+    pub fn is_legal_move(&self, index: usize) -> bool {
+        self.board[index] == Stone::Empty
+    }
+
+    /// Formats the action in board notation; a column letter followed by a row number.
+    /// For example, `a1` is the action `0` and `h8` is the center of the board.
+    pub fn action_to_notation(action: usize) -> String {
+        let x = action % Self::BOARD_SIZE;
+        let y = action / Self::BOARD_SIZE;
+        format!("{}{}", (b'a' + x as u8) as char, y + 1)
+    }
+
+    /// Parses an action in board notation, the inverse of [Self::action_to_notation]. The column letter may be uppercase.
+    /// Returns `None` if it is not a point on the board.
+    pub fn notation_to_action(notation: &str) -> Option<usize> {
+        let mut chars = notation.chars();
+        let column = chars.next()?.to_ascii_lowercase();
+
+        if !column.is_ascii_lowercase() {
+            return None;
+        }
+
+        let x = (column as u8 - b'a') as usize;
+        let y = chars.as_str().parse::<usize>().ok()?.checked_sub(1)?;
+
+        if Self::BOARD_SIZE <= x || Self::BOARD_SIZE <= y {
+            return None;
+        }
+
+        Some(x + y * Self::BOARD_SIZE)
+    }
+
     pub fn encode_board(&self, turn: Turn, mut dst: impl AsMut<[f32]>) {
         let dst = dst.as_mut();
         dst.fill(0f32);
@@ -111,6 +157,7 @@ impl Environment {
             Turn::Black => Stone::Black,
             Turn::White => Stone::White,
         };
+        self.move_numbers[index] = self.move_count() as u8;
 
         let horizontal_count = 1
             + self.count_serial_stones(
@@ -165,6 +212,52 @@ impl Environment {
         )
     }
 
+    /// Returns the stones in a row through the stone at `index` that win the game, in ascending order,
+    /// or an empty vector if there are none. This is meant to be called with the last action of a finished game.
+    pub fn winning_line(&self, index: usize) -> Vec<usize> {
+        let stone = self.board[index];
+
+        if stone == Stone::Empty {
+            return vec![];
+        }
+
+        let x = (index % Self::BOARD_SIZE) as isize;
+        let y = (index / Self::BOARD_SIZE) as isize;
+
+        for (direction_x, direction_y) in [(1, 0), (0, 1), (1, 1), (-1, 1)] {
+            let mut line = vec![index];
+
+            for sign in [-1, 1] {
+                for distance in 1..=Self::SERIAL_STONE_COUNT as isize {
+                    let x = x + direction_x * sign * distance;
+                    let y = y + direction_y * sign * distance;
+                    if x < 0
+                        || Self::BOARD_SIZE as isize <= x
+                        || y < 0
+                        || Self::BOARD_SIZE as isize <= y
+                    {
+                        break;
+                    }
+
+                    let index = (y * Self::BOARD_SIZE as isize + x) as usize;
+
+                    if self.board[index] != stone {
+                        break;
+                    }
+
+                    line.push(index);
+                }
+            }
+
+            if line.len() == Self::SERIAL_STONE_COUNT {
+                line.sort_unstable();
+                return line;
+            }
+        }
+
+        vec![]
+    }
+
     fn count_serial_stones(&self, turn: Turn, index: usize, offset: &[(isize, isize)]) -> usize {
         let stone = match turn {
             Turn::Black => Stone::Black,
@@ -193,6 +286,31 @@ impl Environment {
     }
 }
 
+impl Display for Environment {
+    /// Draws the board with the last row at the top, labeled in board notation.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "  ")?;
+
+        for x in 0..Self::BOARD_SIZE {
+            write!(f, " {}", (b'a' + x as u8) as char)?;
+        }
+
+        writeln!(f)?;
+
+        for y in (0..Self::BOARD_SIZE).rev() {
+            write!(f, "{:>2}", y + 1)?;
+
+            for x in 0..Self::BOARD_SIZE {
+                write!(f, " {}", self.board[x + y * Self::BOARD_SIZE])?;
+            }
+
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -249,6 +367,12 @@ mod test {
         assert_eq!(env.place_stone(11), Some(GameStatus::InProgress));
         assert_eq!(env.board[11], Stone::White);
         assert_eq!(env.turn, Turn::Black);
+
+        assert_eq!(env.place_stone(11), None);
+        assert_eq!(env.move_count(), 12);
+        assert_eq!(env.move_numbers[0], 1);
+        assert_eq!(env.move_numbers[11], 12);
+        assert_eq!(env.move_numbers[12], 0);
     }
 
     #[test]
@@ -371,6 +495,56 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_winning_line() {
+        let mut env = Environment::new();
+
+        for index in 0..Environment::BOARD_SIZE * (Environment::SERIAL_STONE_COUNT - 1) {
+            env.place_stone(index);
+        }
+
+        let action = Environment::BOARD_SIZE * (Environment::SERIAL_STONE_COUNT - 1) + 4;
+        assert_eq!(env.winning_line(action), vec![]);
+        assert_eq!(env.place_stone(action), Some(GameStatus::BlackWin));
+        assert_eq!(
+            env.winning_line(action),
+            (0..Environment::SERIAL_STONE_COUNT)
+                .map(|i| i * (Environment::BOARD_SIZE + 1))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(env.winning_line(1), vec![]);
+    }
+
+    #[test]
+    fn test_action_to_notation() {
+        assert_eq!(Environment::action_to_notation(0), "a1");
+        assert_eq!(
+            Environment::action_to_notation(7 + 7 * Environment::BOARD_SIZE),
+            "h8"
+        );
+        assert_eq!(
+            Environment::action_to_notation(Environment::BOARD_SIZE * Environment::BOARD_SIZE - 1),
+            "o15"
+        );
+
+        for action in 0..Environment::BOARD_SIZE * Environment::BOARD_SIZE {
+            assert_eq!(
+                Environment::notation_to_action(&Environment::action_to_notation(action)),
+                Some(action)
+            );
+        }
+
+        assert_eq!(
+            Environment::notation_to_action("H8"),
+            Some(7 + 7 * Environment::BOARD_SIZE)
+        );
+        assert_eq!(Environment::notation_to_action("a0"), None);
+        assert_eq!(Environment::notation_to_action("a16"), None);
+        assert_eq!(Environment::notation_to_action("p1"), None);
+        assert_eq!(Environment::notation_to_action("8h"), None);
+        assert_eq!(Environment::notation_to_action(""), None);
+    }
+
     #[test]
     fn encoding_0() {
         let mut env = Environment::new();
@@ -1,10 +1,8 @@
-use alpha_zero::{ActionSamplingMode, AgentModel, MCTSExecutor};
-use tensorflow::{Scope, Session, SessionOptions};
+use alpha_zero::{ActionSamplingMode, CpuBackend, MCTSExecutor};
 
 pub struct Agent {
     pub agent: alpha_zero::Agent,
-    pub agent_model: AgentModel,
-    pub session: Session,
+    pub backend: CpuBackend,
     pub mcts_executor: MCTSExecutor,
 }
 
@@ -13,17 +11,11 @@ impl Agent {
     pub const ALPHA: f32 = 1.0;
 
     pub fn new() -> Self {
-        let mut scope = Scope::new_root_scope();
-        let agent_model = AgentModel::new(&mut scope).unwrap();
-        let session = Session::new(&SessionOptions::new(), &scope.graph()).unwrap();
-
-        agent_model.io.load(&session, "saves/alpha-zero").unwrap();
-
-        let agent = alpha_zero::Agent::new(&agent_model, &session).unwrap();
+        let backend = CpuBackend::load("saves/alpha-zero").unwrap();
+        let agent = alpha_zero::Agent::new(&backend).unwrap();
 
         Self {
-            agent_model,
-            session,
+            backend,
             agent,
             mcts_executor: MCTSExecutor::new(),
         }
@@ -38,8 +30,7 @@ impl Agent {
     //         .run(
     //             mcts_count,
     //             mcts_batch_size,
-    //             &self.agent_model,
-    //             &self.session,
+    //             &self.backend,
     //             &self.agent,
     //         )
     //         .unwrap();
@@ -53,8 +44,7 @@ impl Agent {
                 mcts_batch_size,
                 Self::EPSILON,
                 Self::ALPHA,
-                &self.agent_model,
-                &self.session,
+                &self.backend,
                 &self.agent,
             )
             .unwrap();
@@ -63,4 +53,22 @@ impl Agent {
             .unwrap()
             .0
     }
+
+    /// Keeps searching on the opponent's time until `wait` returns.
+    pub fn ponder<T>(
+        &self,
+        mcts_count: usize,
+        mcts_batch_size: usize,
+        wait: impl FnOnce() -> T,
+    ) -> T {
+        self.agent
+            .ponder(
+                &self.mcts_executor,
+                mcts_count,
+                mcts_batch_size,
+                &self.backend,
+                wait,
+            )
+            .unwrap()
+    }
 }
@@ -7,71 +7,142 @@ mod agent;
 
 use agent::Agent;
 use environment::{Environment, GameStatus, Stone, Turn};
-use std::sync::Mutex;
+use std::{
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
+    },
+    thread,
+};
+
+/// Requests sent from the UI to the engine thread.
+enum EngineRequest {
+    PlaceStone(usize),
+    Reset,
+}
+
+/// The game as seen by the UI after the engine has responded.
+struct EngineReply {
+    env: Environment,
+    env_status: GameStatus,
+}
 
 struct Application {
-    agent: Agent,
+    requests: Sender<EngineRequest>,
+    replies: Receiver<EngineReply>,
+    env: Environment,
     env_status: GameStatus,
 }
 
 impl Application {
     pub const MCTS_COUNT: usize = 600;
     pub const MCTS_BATCH_SIZE: usize = 16;
+    /// Upper bound of simulations performed while the player is thinking.
+    pub const PONDER_COUNT: usize = 100_000;
 
     pub fn new() -> Self {
-        let mut agent = Agent::new();
+        let (request_sender, request_receiver) = channel();
+        let (reply_sender, reply_receiver) = channel();
 
-        let action = agent.make_move(Self::MCTS_COUNT, Self::MCTS_BATCH_SIZE);
-        let env_status = agent.agent.play_action(action).unwrap();
+        thread::spawn(move || run_engine(request_receiver, reply_sender));
 
-        Self { agent, env_status }
+        let reply = reply_receiver.recv().unwrap();
+
+        Self {
+            requests: request_sender,
+            replies: reply_receiver,
+            env: reply.env,
+            env_status: reply.env_status,
+        }
     }
 
     fn on_click_button(&mut self, x: usize, y: usize) -> &mut Self {
-        match self.env_status {
+        let request = match self.env_status {
             GameStatus::InProgress => {
-                if self.agent.agent.env.turn != Turn::White {
+                if self.env.turn != Turn::White {
                     return self;
                 }
 
-                let index = y * Environment::BOARD_SIZE + x;
-                self.place_stone(index);
+                EngineRequest::PlaceStone(y * Environment::BOARD_SIZE + x)
             }
-            _ => {
-                // Reset game
-                self.agent = Agent::new();
+            // Reset game
+            _ => EngineRequest::Reset,
+        };
 
-                let action = self
-                    .agent
-                    .make_move(Self::MCTS_COUNT, Self::MCTS_BATCH_SIZE);
-                self.env_status = self.agent.agent.play_action(action).unwrap();
-            }
-        }
+        self.requests.send(request).unwrap();
+
+        let reply = self.replies.recv().unwrap();
+        self.env = reply.env;
+        self.env_status = reply.env_status;
 
         self
     }
+}
+
+/// Owns the agent and plays against the player.
+/// While waiting for the player's move, the agent keeps searching the tree.
+fn run_engine(requests: Receiver<EngineRequest>, replies: Sender<EngineReply>) {
+    let mut agent = Agent::new();
+    let mut env_status = play_engine_move(&mut agent);
 
-    fn place_stone(&mut self, index: usize) {
-        self.agent
-            .agent
-            .ensure_action_exists(index, &self.agent.agent_model, &self.agent.session)
-            .unwrap();
-        self.env_status = match self.agent.agent.play_action(index) {
-            Some(status) => status,
-            None => return,
+    loop {
+        let reply = EngineReply {
+            env: agent.agent.env.clone(),
+            env_status,
         };
 
-        if self.env_status.is_terminal() {
+        if replies.send(reply).is_err() {
             return;
         }
 
-        let action = self
-            .agent
-            .make_move(Self::MCTS_COUNT, Self::MCTS_BATCH_SIZE);
-        self.env_status = self.agent.agent.play_action(action).unwrap();
+        let request = if env_status.is_terminal() {
+            requests.recv()
+        } else {
+            agent.ponder(
+                Application::PONDER_COUNT,
+                Application::MCTS_BATCH_SIZE,
+                || requests.recv(),
+            )
+        };
+
+        match request {
+            Ok(EngineRequest::PlaceStone(index)) => {
+                if env_status.is_terminal() {
+                    continue;
+                }
+
+                agent
+                    .agent
+                    .ensure_action_exists(index, &agent.backend)
+                    .unwrap();
+                env_status = match agent.agent.play_action(index) {
+                    Some(status) => status,
+                    None => continue,
+                };
+
+                if env_status.is_terminal() {
+                    continue;
+                }
+
+                env_status = play_engine_move(&mut agent);
+            }
+            Ok(EngineRequest::Reset) => {
+                agent = Agent::new();
+                env_status = play_engine_move(&mut agent);
+            }
+            Err(_) => {
+                // The application has been closed.
+                return;
+            }
+        }
     }
 }
 
+fn play_engine_move(agent: &mut Agent) -> GameStatus {
+    let action = agent.make_move(Application::MCTS_COUNT, Application::MCTS_BATCH_SIZE);
+    agent.agent.play_action(action).unwrap()
+}
+
 #[derive(serde::Serialize)]
 struct ClickResponse {
     board: Vec<i32>,
@@ -95,8 +166,6 @@ fn on_click(state: tauri::State<Mutex<Application>>, x: usize, y: usize) -> Clic
 
     ClickResponse {
         board: state
-            .agent
-            .agent
             .env
             .board
             .iter()
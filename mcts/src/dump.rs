@@ -0,0 +1,276 @@
+use crate::{Node, State, MCTS};
+use std::{
+    io::{self, Write},
+    sync::atomic::Ordering,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DumpFormat {
+    /// Graphviz DOT; render with e.g. `dot -Tsvg tree.dot -o tree.svg`.
+    Dot,
+    /// Nested JSON objects, one per node.
+    Json,
+}
+
+/// Limits the part of the tree to be dumped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DumpOptions {
+    /// Nodes deeper than this are skipped. The root node has depth 0.
+    pub max_depth: usize,
+    /// Nodes visited less than this are skipped, along with their descendants.
+    /// The root node is always dumped.
+    pub min_visits: u64,
+}
+
+impl Default for DumpOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: usize::MAX,
+            min_visits: 0,
+        }
+    }
+}
+
+impl<S> MCTS<S>
+where
+    S: State,
+{
+    /// Writes the tree to `dst` for debugging.
+    /// Each node contains `n`, `w`, `p`, `v_loss` and the action leading to it,
+    /// which is formatted by `action_notation`.
+    pub fn dump(
+        &self,
+        mut dst: impl Write,
+        format: DumpFormat,
+        options: DumpOptions,
+        action_notation: impl Fn(usize) -> String,
+    ) -> io::Result<()> {
+        match format {
+            DumpFormat::Dot => {
+                writeln!(dst, "digraph mcts {{")?;
+                writeln!(dst, "    node [shape=box, fontname=monospace];")?;
+                let mut next_id = 0;
+                dump_dot(
+                    &mut dst,
+                    self.root(),
+                    0,
+                    &mut next_id,
+                    &options,
+                    &action_notation,
+                )?;
+                writeln!(dst, "}}")
+            }
+            DumpFormat::Json => {
+                dump_json(&mut dst, self.root(), 0, &options, &action_notation)?;
+                writeln!(dst)
+            }
+        }
+    }
+}
+
+fn is_dumped<S>(node: &Node<S>, depth: usize, options: &DumpOptions) -> bool
+where
+    S: State,
+{
+    depth == 0
+        || (depth <= options.max_depth && options.min_visits <= node.n.load(Ordering::Relaxed))
+}
+
+/// Writes the node and its dumped descendants, and returns the id of the node.
+fn dump_dot<S>(
+    dst: &mut impl Write,
+    node: &Node<S>,
+    depth: usize,
+    next_id: &mut usize,
+    options: &DumpOptions,
+    action_notation: &impl Fn(usize) -> String,
+) -> io::Result<usize>
+where
+    S: State,
+{
+    let id = *next_id;
+    *next_id += 1;
+
+    writeln!(
+        dst,
+        "    n{} [label=\"{}\\nn={} w={:.3}\\np={:.3} v_loss={}\"];",
+        id,
+        node.action
+            .map(action_notation)
+            .unwrap_or_else(|| "root".to_owned()),
+        node.n.load(Ordering::Relaxed),
+        node.w.load(Ordering::Relaxed),
+        node.p.load(Ordering::Relaxed),
+        node.v_loss.load(Ordering::Relaxed),
+    )?;
+
+    for child in node.children.read().iter() {
+        if !is_dumped(child, depth + 1, options) {
+            continue;
+        }
+
+        let child_id = dump_dot(dst, child, depth + 1, next_id, options, action_notation)?;
+        writeln!(dst, "    n{} -> n{};", id, child_id)?;
+    }
+
+    Ok(id)
+}
+
+fn dump_json<S>(
+    dst: &mut impl Write,
+    node: &Node<S>,
+    depth: usize,
+    options: &DumpOptions,
+    action_notation: &impl Fn(usize) -> String,
+) -> io::Result<()>
+where
+    S: State,
+{
+    write!(dst, "{{\"action\":")?;
+
+    match node.action {
+        Some(action) => write_json_str(dst, &action_notation(action))?,
+        None => write!(dst, "null")?,
+    }
+
+    write!(dst, ",\"n\":{}", node.n.load(Ordering::Relaxed))?;
+    write!(dst, ",\"w\":")?;
+    write_json_f32(dst, node.w.load(Ordering::Relaxed))?;
+    write!(dst, ",\"p\":")?;
+    write_json_f32(dst, node.p.load(Ordering::Relaxed))?;
+    write!(dst, ",\"v_loss\":{}", node.v_loss.load(Ordering::Relaxed))?;
+    write!(dst, ",\"children\":[")?;
+
+    let mut is_first = true;
+
+    for child in node.children.read().iter() {
+        if !is_dumped(child, depth + 1, options) {
+            continue;
+        }
+
+        if !is_first {
+            write!(dst, ",")?;
+        }
+
+        is_first = false;
+        dump_json(dst, child, depth + 1, options, action_notation)?;
+    }
+
+    write!(dst, "]}}")
+}
+
+fn write_json_f32(dst: &mut impl Write, value: f32) -> io::Result<()> {
+    // JSON has no representation of NaN and infinity.
+    if value.is_finite() {
+        write!(dst, "{}", value)
+    } else {
+        write!(dst, "null")
+    }
+}
+
+fn write_json_str(dst: &mut impl Write, value: &str) -> io::Result<()> {
+    write!(dst, "\"")?;
+
+    for c in value.chars() {
+        match c {
+            '"' => write!(dst, "\\\"")?,
+            '\\' => write!(dst, "\\\\")?,
+            '\n' => write!(dst, "\\n")?,
+            '\r' => write!(dst, "\\r")?,
+            '\t' => write!(dst, "\\t")?,
+            c if c.is_control() => write!(dst, "\\u{:04x}", c as u32)?,
+            c => write!(dst, "{}", c)?,
+        }
+    }
+
+    write!(dst, "\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_state::ToyState;
+
+    fn build_tree() -> MCTS<ToyState> {
+        let mcts = MCTS::new(ToyState::new(2));
+        let child = mcts
+            .expand(mcts.root(), 0, mcts.root().state.play(0))
+            .unwrap();
+        child.propagate(1.0);
+        let grandchild = mcts.expand(&child, 1, child.state.play(1)).unwrap();
+        grandchild.propagate(1.0);
+        mcts
+    }
+
+    fn dump_to_string(mcts: &MCTS<ToyState>, format: DumpFormat, options: DumpOptions) -> String {
+        let mut dst = Vec::new();
+        mcts.dump(&mut dst, format, options, |action| format!("a{}", action))
+            .unwrap();
+        String::from_utf8(dst).unwrap()
+    }
+
+    #[test]
+    fn test_dump_json() {
+        let mcts = build_tree();
+        let json = dump_to_string(&mcts, DumpFormat::Json, DumpOptions::default());
+
+        assert_eq!(
+            json,
+            "{\"action\":null,\"n\":2,\"w\":0,\"p\":1,\"v_loss\":0,\"children\":[\
+             {\"action\":\"a0\",\"n\":2,\"w\":0,\"p\":0.5,\"v_loss\":0,\"children\":[\
+             {\"action\":\"a1\",\"n\":1,\"w\":1,\"p\":0.5,\"v_loss\":0,\"children\":[]}]}]}\n"
+        );
+    }
+
+    #[test]
+    fn test_dump_json_escapes_notation() {
+        let mcts = build_tree();
+        let mut dst = Vec::new();
+        mcts.dump(
+            &mut dst,
+            DumpFormat::Json,
+            DumpOptions::default(),
+            |action| format!("\"{}\\\n", action),
+        )
+        .unwrap();
+        let json = String::from_utf8(dst).unwrap();
+
+        assert!(json.contains("{\"action\":\"\\\"0\\\\\\n\",\"n\":2"));
+    }
+
+    #[test]
+    fn test_dump_max_depth() {
+        let mcts = build_tree();
+        let json = dump_to_string(
+            &mcts,
+            DumpFormat::Json,
+            DumpOptions {
+                max_depth: 1,
+                min_visits: 0,
+            },
+        );
+
+        assert!(json.contains("\"a0\""));
+        assert!(!json.contains("\"a1\""));
+    }
+
+    #[test]
+    fn test_dump_dot() {
+        let mcts = build_tree();
+        let dot = dump_to_string(
+            &mcts,
+            DumpFormat::Dot,
+            DumpOptions {
+                max_depth: usize::MAX,
+                min_visits: 2,
+            },
+        );
+
+        assert!(dot.starts_with("digraph mcts {\n"));
+        assert!(dot.contains("n0 [label=\"root\\nn=2 w=0.000\\np=1.000 v_loss=0\"];"));
+        assert!(dot.contains("n1 [label=\"a0\\nn=2"));
+        assert!(dot.contains("n0 -> n1;"));
+        assert!(!dot.contains("a1"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
@@ -1,12 +1,20 @@
 mod bump_allocator;
+mod dump;
 mod node;
 mod state;
+mod statistics;
+#[cfg(test)]
+mod test_state;
+mod validate;
 
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 pub use bump_allocator::*;
+pub use dump::*;
 pub use node::*;
 pub use state::*;
+pub use statistics::*;
+pub use validate::*;
 
 use parking_lot::Mutex;
 
@@ -16,6 +24,7 @@ where
 {
     root: *mut Node<S>,
     allocator: Mutex<BumpAllocator<Node<S>>>,
+    node_count: AtomicUsize,
 }
 
 impl<S> MCTS<S>
@@ -28,6 +37,7 @@ where
         Self {
             root,
             allocator: Mutex::new(allocator),
+            node_count: AtomicUsize::new(1),
         }
     }
 
@@ -35,17 +45,29 @@ where
         unsafe { &*self.root }
     }
 
+    /// Returns the number of nodes in the tree, including the root node.
+    pub fn node_count(&self) -> usize {
+        self.node_count.load(Ordering::Relaxed)
+    }
+
     pub fn select_leaf(&self, selector: impl Fn(&Node<S>, &[NodePtr<S>]) -> usize) -> &Node<S> {
         let root = unsafe { &*self.root };
         root.select_leaf(selector)
     }
 
     pub fn expand(&self, node: &Node<S>, action: usize, state: S) -> Option<NodePtr<S>> {
-        node.expand(action, state, &mut self.allocator.lock())
+        let child = node.expand(action, state, &mut self.allocator.lock());
+
+        if child.is_some() {
+            self.node_count.fetch_add(1, Ordering::Relaxed);
+        }
+
+        child
     }
 
     pub fn transition(&mut self, children_index: usize) {
         let allocator = &mut self.allocator.lock();
+        let mut dealloc_count = 0;
 
         let new_root = {
             let root = unsafe { &mut *self.root };
@@ -56,7 +78,7 @@ where
                     continue;
                 }
 
-                dealloc_node(root_children[index].ptr as *mut Node<S>, allocator);
+                dealloc_count += dealloc_node(root_children[index].ptr as *mut Node<S>, allocator);
             }
 
             let new_root = unsafe { &mut *(root_children[children_index].ptr as *mut Node<S>) };
@@ -75,21 +97,26 @@ where
 
         allocator.deallocate(self.root);
         self.root = new_root;
+        self.node_count
+            .fetch_sub(dealloc_count + 1, Ordering::Relaxed);
     }
 }
 
-fn dealloc_node<S>(ptr: *mut Node<S>, allocator: &mut BumpAllocator<Node<S>>)
+/// Deallocates the node and its descendants, and returns the number of deallocated nodes.
+fn dealloc_node<S>(ptr: *mut Node<S>, allocator: &mut BumpAllocator<Node<S>>) -> usize
 where
     S: State,
 {
+    let mut count = 1;
     {
         let node = unsafe { &mut *ptr };
         let children = node.children.read();
         for child in children.iter() {
-            dealloc_node(child.ptr as *mut Node<S>, allocator);
+            count += dealloc_node(child.ptr as *mut Node<S>, allocator);
         }
     }
     allocator.deallocate(ptr);
+    count
 }
 
 unsafe impl<S> Send for MCTS<S> where S: State {}
@@ -0,0 +1,93 @@
+use crate::{Node, State, MCTS};
+use std::sync::atomic::Ordering;
+
+/// Shape of the tree, collected by walking every node. See [MCTS::statistics].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct TreeStatistics {
+    /// Number of nodes reachable from the root node, including the root node.
+    pub node_count: usize,
+    /// Number of nodes without children.
+    pub leaf_count: usize,
+    /// Number of nodes with a terminal state.
+    pub terminal_count: usize,
+    /// Depth of the deepest node, where the root node has depth 0.
+    pub max_depth: usize,
+    /// Sum of the virtual losses, which is the number of in-flight selections.
+    pub v_loss: u64,
+}
+
+impl TreeStatistics {
+    /// Mean number of children of the nodes having at least one child.
+    pub fn mean_branching_factor(&self) -> f32 {
+        let internal_count = self.node_count - self.leaf_count;
+
+        if internal_count == 0 {
+            0f32
+        } else {
+            // Every node except the root node is a child of an internal node.
+            (self.node_count - 1) as f32 / internal_count as f32
+        }
+    }
+}
+
+impl<S> MCTS<S>
+where
+    S: State,
+{
+    /// Walks the whole tree and collects its statistics.
+    pub fn statistics(&self) -> TreeStatistics {
+        let mut statistics = TreeStatistics::default();
+        let mut stack: Vec<(&Node<S>, usize)> = vec![(self.root(), 0)];
+
+        while let Some((node, depth)) = stack.pop() {
+            let children = node.children.read();
+
+            statistics.node_count += 1;
+            statistics.max_depth = usize::max(statistics.max_depth, depth);
+            statistics.v_loss += node.v_loss.load(Ordering::Relaxed) as u64;
+
+            if children.is_empty() {
+                statistics.leaf_count += 1;
+            }
+
+            if node.state.is_terminal() {
+                statistics.terminal_count += 1;
+            }
+
+            for child in children.iter() {
+                stack.push((unsafe { &*child.ptr }, depth + 1));
+            }
+        }
+
+        statistics
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_state::ToyState;
+
+    #[test]
+    fn test_statistics() {
+        let mcts = MCTS::new(ToyState::new(2));
+        let child = mcts
+            .expand(mcts.root(), 0, mcts.root().state.play(0))
+            .unwrap();
+        mcts.expand(mcts.root(), 1, mcts.root().state.play(1));
+        mcts.expand(&child, 1, child.state.play(1));
+
+        let statistics = mcts.statistics();
+        assert_eq!(
+            statistics,
+            TreeStatistics {
+                node_count: 4,
+                leaf_count: 2,
+                terminal_count: 1,
+                max_depth: 2,
+                v_loss: 0,
+            }
+        );
+        assert_eq!(statistics.mean_branching_factor(), 1.5);
+    }
+}
@@ -0,0 +1,63 @@
+//! A toy game shared by the tests: every action can be taken once, and the game ends when all actions are taken.
+
+use crate::{PolicyRef, State};
+use std::sync::atomic::AtomicU64;
+
+pub struct ToyState {
+    pub action_count: usize,
+    pub taken: Vec<usize>,
+    /// Number of evaluations propagated from the node holding this state; maintained by the tests.
+    pub evaluations: AtomicU64,
+}
+
+impl ToyState {
+    pub fn new(action_count: usize) -> Self {
+        Self {
+            action_count,
+            taken: vec![],
+            evaluations: AtomicU64::new(0),
+        }
+    }
+
+    pub fn play(&self, action: usize) -> Self {
+        let mut taken = self.taken.clone();
+        taken.push(action);
+        Self {
+            action_count: self.action_count,
+            taken,
+            evaluations: AtomicU64::new(0),
+        }
+    }
+}
+
+pub struct UniformPolicy {
+    action_count: usize,
+}
+
+impl<'s> PolicyRef<'s> for UniformPolicy {
+    fn get(&self, _action: usize) -> f32 {
+        1f32 / self.action_count as f32
+    }
+}
+
+impl State for ToyState {
+    type PolicyRef<'s> = UniformPolicy;
+
+    fn is_terminal(&self) -> bool {
+        self.taken.len() == self.action_count
+    }
+
+    fn policy<'s>(&'s self) -> Self::PolicyRef<'s> {
+        UniformPolicy {
+            action_count: self.action_count,
+        }
+    }
+
+    fn available_actions_len(&self) -> usize {
+        self.action_count - self.taken.len()
+    }
+
+    fn is_available_action(&self, action: usize) -> bool {
+        action < self.action_count && !self.taken.contains(&action)
+    }
+}
@@ -0,0 +1,331 @@
+use crate::{Node, State, MCTS};
+use std::{error::Error, fmt::Display, sync::atomic::Ordering};
+
+/// A broken invariant found by [MCTS::validate].
+/// `path` is the list of actions leading from the root node to the offending node.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum InvariantViolation {
+    /// The root node has a parent.
+    RootHasParent,
+    /// A node is visited less than its children in total.
+    /// Every visit of a child also visits the node, so `n` must be at least the sum of the children's `n`;
+    /// the difference is the number of evaluations started at the node itself.
+    VisitCount {
+        path: Vec<usize>,
+        n: u64,
+        children_n: u64,
+    },
+    /// A node still holds a virtual loss, i.e. a selection has not been expanded or propagated.
+    VirtualLoss { path: Vec<usize>, v_loss: u32 },
+    /// A child does not point back to the node holding it.
+    Parent { path: Vec<usize> },
+    /// A child has no action.
+    MissingAction { path: Vec<usize> },
+    /// Two children of a node share the same action.
+    DuplicateAction { path: Vec<usize> },
+    /// A child's action is not available in the node's state.
+    UnavailableAction { path: Vec<usize> },
+    /// A node has more children than available actions.
+    TooManyChildren {
+        path: Vec<usize>,
+        children_len: usize,
+        available_actions_len: usize,
+    },
+    /// The tracked node count differs from the number of reachable nodes.
+    NodeCount { tracked: usize, reachable: usize },
+}
+
+impl Display for InvariantViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvariantViolation::RootHasParent => write!(f, "the root node has a parent"),
+            InvariantViolation::VisitCount {
+                path,
+                n,
+                children_n,
+            } => write!(
+                f,
+                "node at {:?} has n={} but its children have n={} in total",
+                path, n, children_n
+            ),
+            InvariantViolation::VirtualLoss { path, v_loss } => {
+                write!(f, "node at {:?} has v_loss={}", path, v_loss)
+            }
+            InvariantViolation::Parent { path } => {
+                write!(f, "node at {:?} does not point to its parent", path)
+            }
+            InvariantViolation::MissingAction { path } => {
+                write!(f, "node at {:?} has no action", path)
+            }
+            InvariantViolation::DuplicateAction { path } => {
+                write!(f, "node at {:?} has a sibling with the same action", path)
+            }
+            InvariantViolation::UnavailableAction { path } => {
+                write!(f, "node at {:?} has an unavailable action", path)
+            }
+            InvariantViolation::TooManyChildren {
+                path,
+                children_len,
+                available_actions_len,
+            } => write!(
+                f,
+                "node at {:?} has {} children but only {} available actions",
+                path, children_len, available_actions_len
+            ),
+            InvariantViolation::NodeCount { tracked, reachable } => write!(
+                f,
+                "node count is {} but {} nodes are reachable",
+                tracked, reachable
+            ),
+        }
+    }
+}
+
+impl Error for InvariantViolation {}
+
+impl<S> MCTS<S>
+where
+    S: State,
+{
+    /// Checks the structural invariants of the tree, returning the first violation found.
+    /// This walks the whole tree and is meant for debugging and tests.
+    ///
+    /// It must be called while no search is running;
+    /// in-flight selections hold virtual losses and partially propagated visits.
+    pub fn validate(&self) -> Result<(), InvariantViolation> {
+        let root = self.root();
+
+        if root.parent.is_some() {
+            return Err(InvariantViolation::RootHasParent);
+        }
+
+        let mut reachable = 0;
+        let mut stack: Vec<(&Node<S>, Vec<usize>)> = vec![(root, vec![])];
+
+        while let Some((node, path)) = stack.pop() {
+            reachable += 1;
+
+            let v_loss = node.v_loss.load(Ordering::Relaxed);
+
+            if v_loss != 0 {
+                return Err(InvariantViolation::VirtualLoss { path, v_loss });
+            }
+
+            let children = node.children.read();
+            let available_actions_len = node.state.available_actions_len();
+
+            if available_actions_len < children.len() {
+                return Err(InvariantViolation::TooManyChildren {
+                    path,
+                    children_len: children.len(),
+                    available_actions_len,
+                });
+            }
+
+            let n = node.n.load(Ordering::Relaxed);
+            let children_n = children
+                .iter()
+                .map(|child| child.n.load(Ordering::Relaxed))
+                .sum::<u64>();
+
+            if n < children_n {
+                return Err(InvariantViolation::VisitCount {
+                    path,
+                    n,
+                    children_n,
+                });
+            }
+
+            for (index, child) in children.iter().enumerate() {
+                let mut child_path = path.clone();
+
+                let action = match child.action {
+                    Some(action) => action,
+                    None => return Err(InvariantViolation::MissingAction { path: child_path }),
+                };
+                child_path.push(action);
+
+                if child.parent.map(|parent| parent.ptr) != Some(node as *const Node<S>) {
+                    return Err(InvariantViolation::Parent { path: child_path });
+                }
+
+                if children[..index]
+                    .iter()
+                    .any(|sibling| sibling.action == Some(action))
+                {
+                    return Err(InvariantViolation::DuplicateAction { path: child_path });
+                }
+
+                if !node.state.is_available_action(action) {
+                    return Err(InvariantViolation::UnavailableAction { path: child_path });
+                }
+
+                stack.push((unsafe { &*child.ptr }, child_path));
+            }
+        }
+
+        let tracked = self.node_count();
+
+        if tracked != reachable {
+            return Err(InvariantViolation::NodeCount { tracked, reachable });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{test_state::ToyState, NodePtr};
+    use proptest::prelude::*;
+
+    const ACTION_COUNT: usize = 5;
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        /// Selects a leaf for each entry before evaluating any of them, as the executors do.
+        /// Each entry is a seed for the selector, a seed for the expanded action and the value to propagate.
+        Batch(Vec<(usize, usize, f32)>),
+        /// Transitions to the child at the seeded index.
+        Transition(usize),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            3 => prop::collection::vec((any::<usize>(), any::<usize>(), -1f32..=1f32), 1..8)
+                .prop_map(Op::Batch),
+            1 => any::<usize>().prop_map(Op::Transition),
+        ]
+    }
+
+    fn run_batch(mcts: &MCTS<ToyState>, selections: &[(usize, usize, f32)]) {
+        let leaves = selections
+            .iter()
+            .map(|&(seed, _, _)| {
+                NodePtr::new(mcts.select_leaf(|_, children| seed % children.len()))
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(mcts.statistics().v_loss, selections.len() as u64);
+        assert!(matches!(
+            mcts.validate(),
+            Err(InvariantViolation::VirtualLoss { .. })
+        ));
+
+        for (leaf, &(_, action_seed, value)) in leaves.iter().zip(selections) {
+            let evaluated = if leaf.state.is_terminal() {
+                Some(*leaf)
+            } else {
+                let actions = (0..ACTION_COUNT)
+                    .filter(|&action| {
+                        leaf.state.is_available_action(action)
+                            && leaf
+                                .children
+                                .read()
+                                .iter()
+                                .all(|child| child.action != Some(action))
+                    })
+                    .collect::<Vec<_>>();
+
+                // Another selection of this batch may have expanded the last action already.
+                if actions.is_empty() {
+                    None
+                } else {
+                    let action = actions[action_seed % actions.len()];
+                    mcts.expand(leaf, action, leaf.state.play(action))
+                }
+            };
+
+            leaf.v_loss.fetch_sub(1, Ordering::Relaxed);
+
+            if let Some(node) = evaluated {
+                node.state.evaluations.fetch_add(1, Ordering::Relaxed);
+                node.propagate(value);
+            }
+        }
+    }
+
+    /// Checks that `n` of every node is the sum of its children's `n` plus its own evaluations.
+    fn check_visit_counts(mcts: &MCTS<ToyState>) {
+        let mut stack = vec![mcts.root()];
+
+        while let Some(node) = stack.pop() {
+            let children = node.children.read();
+            let children_n = children
+                .iter()
+                .map(|child| child.n.load(Ordering::Relaxed))
+                .sum::<u64>();
+
+            assert_eq!(
+                node.n.load(Ordering::Relaxed),
+                children_n + node.state.evaluations.load(Ordering::Relaxed)
+            );
+
+            stack.extend(children.iter().map(|child| unsafe { &*child.ptr }));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_invariants_hold(ops in prop::collection::vec(op(), 1..32)) {
+            let mut mcts = MCTS::new(ToyState::new(ACTION_COUNT));
+
+            for op in ops {
+                match op {
+                    Op::Batch(selections) => run_batch(&mcts, &selections),
+                    Op::Transition(seed) => {
+                        let children_len = mcts.root().children.read().len();
+
+                        if children_len == 0 {
+                            continue;
+                        }
+
+                        mcts.transition(seed % children_len);
+                        // The transition discards the evaluations of the new root node itself.
+                        mcts.root().state.evaluations.store(0, Ordering::Relaxed);
+                    }
+                }
+
+                prop_assert_eq!(mcts.validate(), Ok(()));
+                prop_assert_eq!(mcts.statistics().node_count, mcts.node_count());
+                check_visit_counts(&mcts);
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate_detects_visit_count() {
+        let mcts = MCTS::new(ToyState::new(2));
+        let child = mcts
+            .expand(mcts.root(), 1, mcts.root().state.play(1))
+            .unwrap();
+        child.propagate(1.0);
+        assert_eq!(mcts.validate(), Ok(()));
+
+        mcts.root().n.store(0, Ordering::Relaxed);
+        assert_eq!(
+            mcts.validate(),
+            Err(InvariantViolation::VisitCount {
+                path: vec![],
+                n: 0,
+                children_n: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_detects_parent() {
+        let mcts = MCTS::new(ToyState::new(2));
+        let child = mcts
+            .expand(mcts.root(), 0, mcts.root().state.play(0))
+            .unwrap();
+        let grandchild = mcts.expand(&child, 1, child.state.play(1)).unwrap();
+        unsafe { (*(grandchild.ptr as *mut Node<ToyState>)).parent = None };
+
+        assert_eq!(
+            mcts.validate(),
+            Err(InvariantViolation::Parent { path: vec![0, 1] })
+        );
+    }
+}
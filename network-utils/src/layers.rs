@@ -0,0 +1,992 @@
+use crate::{Activation, Conv2DPadding, PoolPadding, WeightInitializer, BATCH_NORM_EPSILON};
+use tensorflow::{
+    ops::{
+        add, assign, bias_add, broadcast_to, constant, elu, leaky_relu, mat_mul, max, mean, mul,
+        relu, reshape, select_v2, shape, sigmoid, slice, ConcatV2, Conv2D as TFConv2D,
+        DepthwiseConv2dNative as TFDepthwiseConv2dNative, FusedBatchNormV3, MaxPool, NoOp,
+        RandomStandardNormal,
+    },
+    DataType, Operation, Output, Scope, Status, Variable,
+};
+
+#[derive(Debug, Clone)]
+pub struct Conv2D {
+    pub w: Variable,
+    pub b: Variable,
+    pub output: Operation,
+}
+
+#[derive(Debug, Clone)]
+pub struct SeparableConv2D {
+    pub depthwise_w: Variable,
+    pub pointwise_w: Variable,
+    pub b: Variable,
+    pub output: Operation,
+}
+
+#[derive(Debug, Clone)]
+pub struct Fc {
+    pub w: Variable,
+    pub b: Variable,
+    pub output: Operation,
+}
+
+#[derive(Debug, Clone)]
+pub struct Conv2DResidual {
+    pub w0: Variable,
+    pub b0: Variable,
+    pub w1: Variable,
+    pub b1: Variable,
+    /// Batch normalization layers in the order they are applied; empty if it is disabled.
+    pub batch_norms: Vec<BatchNorm>,
+    pub output: Operation,
+}
+
+#[derive(Debug, Clone)]
+pub struct Conv2DBottleneckResidual {
+    pub w0: Variable,
+    pub b0: Variable,
+    pub depthwise_w1: Variable,
+    pub pointwise_w1: Variable,
+    pub b1: Variable,
+    pub w2: Variable,
+    pub b2: Variable,
+    /// Batch normalization layers in the order they are applied; empty if it is disabled.
+    pub batch_norms: Vec<BatchNorm>,
+    pub output: Operation,
+}
+
+#[derive(Debug, Clone)]
+pub struct Conv2DSeparableResidual {
+    pub depthwise_w0: Variable,
+    pub pointwise_w0: Variable,
+    pub b0: Variable,
+    pub depthwise_w1: Variable,
+    pub pointwise_w1: Variable,
+    pub b1: Variable,
+    /// Batch normalization layers in the order they are applied; empty if it is disabled.
+    pub batch_norms: Vec<BatchNorm>,
+    pub output: Operation,
+}
+
+#[derive(Debug, Clone)]
+pub struct SeResidual {
+    pub w0: Variable,
+    pub b0: Variable,
+    pub w1: Variable,
+    pub b1: Variable,
+    /// Squeeze layer over the globally pooled activations.
+    pub se_w0: Variable,
+    pub se_b0: Variable,
+    /// Excitation layer producing the per-channel scales, which go through a sigmoid.
+    pub se_scale_w: Variable,
+    pub se_scale_b: Variable,
+    /// Excitation layer producing the per-channel biases.
+    pub se_bias_w: Variable,
+    pub se_bias_b: Variable,
+    /// Batch normalization layers in the order they are applied; empty if it is disabled.
+    pub batch_norms: Vec<BatchNorm>,
+    pub output: Operation,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchNorm {
+    pub scale: Variable,
+    pub offset: Variable,
+    /// Moving average of the mean, used for inference. It is not trainable.
+    pub mean: Variable,
+    /// Moving average of the variance, used for inference. It is not trainable.
+    pub variance: Variable,
+    pub output: Operation,
+    /// Updates the moving averages with the statistics of the current batch.
+    pub update: Operation,
+}
+
+/// Options of the batch normalization layers.
+#[derive(Debug, Clone)]
+pub struct BatchNormOptions {
+    /// Boolean scalar selecting the statistics of the batch if true, and the moving averages otherwise.
+    pub training: Operation,
+    /// Decay of the moving averages.
+    pub momentum: f32,
+}
+
+impl Activation {
+    pub fn build(&self, x: Operation, scope: &mut Scope) -> Result<Operation, Status> {
+        match self {
+            Activation::LeakyRelu => leaky_relu(x, scope),
+            Activation::Relu => relu(x, scope),
+            Activation::Elu => elu(x, scope),
+        }
+    }
+}
+
+pub fn conv2d(
+    name: impl AsRef<str>,
+    data_type: DataType,
+    x: Operation,
+    input_channels: i64,
+    output_channels: i64,
+    filter_size: &[i64; 2],
+    stride: &[i64; 2],
+    padding: Conv2DPadding,
+    weight_init: WeightInitializer,
+    scope: &mut Scope,
+) -> Result<Conv2D, Status> {
+    let name = name.as_ref();
+    let w = Variable::builder()
+        .data_type(data_type)
+        .shape(&[
+            filter_size[1],
+            filter_size[0],
+            input_channels,
+            output_channels,
+        ])
+        .initial_value(mul(
+            RandomStandardNormal::new().dtype(data_type).build(
+                constant(
+                    &[
+                        filter_size[1],
+                        filter_size[0],
+                        input_channels,
+                        output_channels,
+                    ],
+                    scope,
+                )?,
+                scope,
+            )?,
+            constant(
+                weight_init.build_constant(
+                    filter_size[1] * filter_size[0] * input_channels,
+                    filter_size[1] * filter_size[0] * output_channels,
+                ),
+                scope,
+            )?,
+            scope,
+        )?)
+        .build(&mut scope.with_op_name(&format!("{}_w", name)))?;
+    let b = Variable::builder()
+        .data_type(data_type)
+        .shape(&[output_channels])
+        .initial_value(broadcast_to(
+            constant(&[0f32], scope)?,
+            constant(&[output_channels], scope)?,
+            scope,
+        )?)
+        .build(&mut scope.with_op_name(&format!("{}_b", name)))?;
+    let conv = TFConv2D::new()
+        .data_format("NHWC")
+        .strides([1, stride[1], stride[0], 1])
+        .padding(match padding {
+            Conv2DPadding::Valid => "VALID",
+            Conv2DPadding::Same => "SAME",
+        })
+        .build(
+            x,
+            w.output().clone(),
+            &mut scope.with_op_name(&format!("{}_conv2d", name)),
+        )?;
+    let conv_biased = bias_add(
+        conv,
+        b.output().clone(),
+        &mut scope.with_op_name(&format!("{}_bias_add", name)),
+    )?;
+    Ok(Conv2D {
+        w,
+        b,
+        output: conv_biased,
+    })
+}
+
+pub fn separable_conv2d(
+    name: impl AsRef<str>,
+    data_type: DataType,
+    x: Operation,
+    input_channels: i64,
+    output_channels: i64,
+    filter_size: &[i64; 2],
+    stride: &[i64; 2],
+    padding: Conv2DPadding,
+    weight_init: WeightInitializer,
+    scope: &mut Scope,
+) -> Result<SeparableConv2D, Status> {
+    let name = name.as_ref();
+
+    let depthwise_w = Variable::builder()
+        .data_type(data_type)
+        .shape(&[filter_size[1], filter_size[0], input_channels, 1])
+        .initial_value(mul(
+            RandomStandardNormal::new().dtype(data_type).build(
+                constant(&[filter_size[1], filter_size[0], input_channels, 1], scope)?,
+                scope,
+            )?,
+            constant(
+                weight_init.build_constant(
+                    filter_size[1] * filter_size[0] * input_channels,
+                    filter_size[1] * filter_size[0] * 1,
+                ),
+                scope,
+            )?,
+            scope,
+        )?)
+        .build(&mut scope.with_op_name(&format!("{}_w", name)))?;
+    let depthwise_conv = TFDepthwiseConv2dNative::new()
+        .data_format("NHWC")
+        .strides([1, stride[1], stride[0], 1])
+        .padding(match padding {
+            Conv2DPadding::Valid => "VALID",
+            Conv2DPadding::Same => "SAME",
+        })
+        .build(
+            x,
+            depthwise_w.output().clone(),
+            &mut scope.with_op_name(&format!("{}_depthwise_conv2d", name)),
+        )?;
+
+    let pointwise_w = Variable::builder()
+        .data_type(data_type)
+        .shape(&[1, 1, input_channels, output_channels])
+        .initial_value(mul(
+            RandomStandardNormal::new().dtype(data_type).build(
+                constant(&[1, 1, input_channels, output_channels], scope)?,
+                scope,
+            )?,
+            constant(
+                weight_init.build_constant(1 * 1 * input_channels, 1 * 1 * output_channels),
+                scope,
+            )?,
+            scope,
+        )?)
+        .build(&mut scope.with_op_name(&format!("{}_w", name)))?;
+    let pointwise_conv = TFConv2D::new()
+        .data_format("NHWC")
+        .strides([1, 1, 1, 1])
+        .padding("SAME")
+        .build(
+            depthwise_conv,
+            pointwise_w.output().clone(),
+            &mut scope.with_op_name(&format!("{}_pointwise_conv2d", name)),
+        )?;
+
+    let b = Variable::builder()
+        .data_type(data_type)
+        .shape(&[output_channels])
+        .initial_value(broadcast_to(
+            constant(&[0f32], scope)?,
+            constant(&[output_channels], scope)?,
+            scope,
+        )?)
+        .build(&mut scope.with_op_name(&format!("{}_b", name)))?;
+    let conv_biased = bias_add(
+        pointwise_conv,
+        b.output().clone(),
+        &mut scope.with_op_name(&format!("{}_bias_add", name)),
+    )?;
+    Ok(SeparableConv2D {
+        depthwise_w,
+        pointwise_w,
+        b,
+        output: conv_biased,
+    })
+}
+
+pub fn max_pool(
+    name: impl AsRef<str>,
+    x: Operation,
+    filter_size: &[i64; 2],
+    stride: &[i64; 2],
+    padding: PoolPadding,
+    scope: &mut Scope,
+) -> Result<Operation, Status> {
+    let name = name.as_ref();
+    let pool = MaxPool::new()
+        .data_format("NHWC")
+        .ksize([1, filter_size[1], filter_size[0], 1])
+        .strides([1, stride[1], stride[0], 1])
+        .padding(match padding {
+            PoolPadding::Valid => "VALID",
+            PoolPadding::Same => "SAME",
+        })
+        .build(x, &mut scope.with_op_name(&format!("{}_max_pool", name)))?;
+    Ok(pool)
+}
+
+/// Pools NHWC activations over the whole board into `[N, 2 * channels]`: the averages followed by the maxima.
+pub fn global_pool(
+    name: impl AsRef<str>,
+    x: Operation,
+    scope: &mut Scope,
+) -> Result<Operation, Status> {
+    let name = name.as_ref();
+    let avg = mean(
+        x.clone(),
+        constant(&[1, 2], scope)?,
+        &mut scope.with_op_name(&format!("{}_avg_pool", name)),
+    )?;
+    let max = max(
+        x,
+        constant(&[1, 2], scope)?,
+        &mut scope.with_op_name(&format!("{}_max_pool", name)),
+    )?;
+    concat(
+        vec![avg.into(), max.into()],
+        1,
+        &mut scope.with_op_name(&format!("{}_concat", name)),
+    )
+}
+
+pub fn fc(
+    name: impl AsRef<str>,
+    data_type: DataType,
+    x: Operation,
+    inputs: i64,
+    outputs: i64,
+    weight_init: WeightInitializer,
+    scope: &mut Scope,
+) -> Result<Fc, Status> {
+    let name = name.as_ref();
+    let w = Variable::builder()
+        .data_type(data_type)
+        .shape(&[inputs, outputs])
+        .initial_value(mul(
+            RandomStandardNormal::new()
+                .dtype(data_type)
+                .build(constant(&[inputs, outputs], scope)?, scope)?,
+            constant(weight_init.build_constant(inputs, outputs), scope)?,
+            scope,
+        )?)
+        .build(&mut scope.with_op_name(&format!("{}_w", name)))?;
+    let b = Variable::builder()
+        .data_type(data_type)
+        .shape(&[outputs])
+        .initial_value(broadcast_to(
+            constant(&[0f32], scope)?,
+            constant(&[outputs], scope)?,
+            scope,
+        )?)
+        .build(&mut scope.with_op_name(&format!("{}_b", name)))?;
+    let mm = mat_mul(
+        x,
+        w.output().clone(),
+        &mut scope.with_op_name(&format!("{}_mat_mul", name)),
+    )?;
+    let mm_biased = bias_add(
+        mm,
+        b.output().clone(),
+        &mut scope.with_op_name(&format!("{}_bias_add", name)),
+    )?;
+    Ok(Fc {
+        w,
+        b,
+        output: mm_biased,
+    })
+}
+
+pub fn conv2d_residual(
+    name: impl AsRef<str>,
+    data_type: DataType,
+    x: Operation,
+    input_channels: i64,
+    output_channels: i64,
+    filter_size: &[i64; 2],
+    stride: &[i64; 2],
+    padding: Conv2DPadding,
+    weight_init: WeightInitializer,
+    activation: Activation,
+    batch_norm: Option<&BatchNormOptions>,
+    scope: &mut Scope,
+) -> Result<Conv2DResidual, Status> {
+    let mut batch_norms = Vec::new();
+
+    let conv0 = conv2d(
+        &format!("{}_conv0", name.as_ref()),
+        data_type,
+        x.clone(),
+        input_channels,
+        output_channels,
+        filter_size,
+        stride,
+        Conv2DPadding::Same,
+        WeightInitializer::He,
+        scope,
+    )?;
+    let relu = activation.build(
+        normalize(
+            &format!("{}_bn0", name.as_ref()),
+            data_type,
+            conv0.output,
+            output_channels,
+            batch_norm,
+            &mut batch_norms,
+            scope,
+        )?,
+        &mut scope.with_op_name(&format!("{}_relu", name.as_ref())),
+    )?;
+    let conv1 = conv2d(
+        &format!("{}_conv1", name.as_ref()),
+        data_type,
+        relu,
+        output_channels,
+        output_channels,
+        filter_size,
+        stride,
+        padding,
+        weight_init,
+        scope,
+    )?;
+    let add = add(
+        normalize(
+            &format!("{}_bn1", name.as_ref()),
+            data_type,
+            conv1.output,
+            output_channels,
+            batch_norm,
+            &mut batch_norms,
+            scope,
+        )?,
+        x,
+        &mut scope.with_op_name(&format!("{}_add", name.as_ref())),
+    )?;
+    Ok(Conv2DResidual {
+        w0: conv0.w,
+        b0: conv0.b,
+        w1: conv1.w,
+        b1: conv1.b,
+        batch_norms,
+        output: add,
+    })
+}
+
+pub fn conv2d_bottleneck_residual(
+    name: impl AsRef<str>,
+    data_type: DataType,
+    x: Operation,
+    channels: i64,
+    middle_channels: i64,
+    filter_size: &[i64; 2],
+    stride: &[i64; 2],
+    padding: Conv2DPadding,
+    weight_init: WeightInitializer,
+    activation: Activation,
+    batch_norm: Option<&BatchNormOptions>,
+    scope: &mut Scope,
+) -> Result<Conv2DBottleneckResidual, Status> {
+    let mut batch_norms = Vec::new();
+
+    let conv0 = conv2d(
+        &format!("{}_conv0", name.as_ref()),
+        data_type,
+        x.clone(),
+        channels,
+        middle_channels,
+        &[1, 1],
+        &[1, 1],
+        Conv2DPadding::Same,
+        WeightInitializer::He,
+        scope,
+    )?;
+    let activation0 = activation.build(
+        normalize(
+            &format!("{}_bn0", name.as_ref()),
+            data_type,
+            conv0.output,
+            middle_channels,
+            batch_norm,
+            &mut batch_norms,
+            scope,
+        )?,
+        &mut scope.with_op_name(&format!("{}_activation0", name.as_ref())),
+    )?;
+
+    let conv1 = separable_conv2d(
+        &format!("{}_conv1", name.as_ref()),
+        data_type,
+        activation0,
+        middle_channels,
+        middle_channels,
+        filter_size,
+        stride,
+        padding,
+        WeightInitializer::He,
+        scope,
+    )?;
+    let activation1 = activation.build(
+        normalize(
+            &format!("{}_bn1", name.as_ref()),
+            data_type,
+            conv1.output,
+            middle_channels,
+            batch_norm,
+            &mut batch_norms,
+            scope,
+        )?,
+        &mut scope.with_op_name(&format!("{}_activation1", name.as_ref())),
+    )?;
+
+    let conv2 = conv2d(
+        &format!("{}_conv2", name.as_ref()),
+        data_type,
+        activation1,
+        middle_channels,
+        channels,
+        &[1, 1],
+        &[1, 1],
+        Conv2DPadding::Same,
+        weight_init,
+        scope,
+    )?;
+
+    let add = add(
+        normalize(
+            &format!("{}_bn2", name.as_ref()),
+            data_type,
+            conv2.output,
+            channels,
+            batch_norm,
+            &mut batch_norms,
+            scope,
+        )?,
+        x,
+        &mut scope.with_op_name(&format!("{}_add", name.as_ref())),
+    )?;
+
+    Ok(Conv2DBottleneckResidual {
+        w0: conv0.w,
+        b0: conv0.b,
+        depthwise_w1: conv1.depthwise_w,
+        pointwise_w1: conv1.pointwise_w,
+        b1: conv1.b,
+        w2: conv2.w,
+        b2: conv2.b,
+        batch_norms,
+        output: add,
+    })
+}
+
+pub fn conv2d_separable_residual(
+    name: impl AsRef<str>,
+    data_type: DataType,
+    x: Operation,
+    channels: i64,
+    filter_size: &[i64; 2],
+    stride: &[i64; 2],
+    padding: Conv2DPadding,
+    weight_init: WeightInitializer,
+    activation: Activation,
+    batch_norm: Option<&BatchNormOptions>,
+    scope: &mut Scope,
+) -> Result<Conv2DSeparableResidual, Status> {
+    let mut batch_norms = Vec::new();
+
+    let conv0 = separable_conv2d(
+        &format!("{}_conv0", name.as_ref()),
+        data_type,
+        x.clone(),
+        channels,
+        channels,
+        filter_size,
+        stride,
+        Conv2DPadding::Same,
+        WeightInitializer::He,
+        scope,
+    )?;
+    let activation0 = activation.build(
+        normalize(
+            &format!("{}_bn0", name.as_ref()),
+            data_type,
+            conv0.output,
+            channels,
+            batch_norm,
+            &mut batch_norms,
+            scope,
+        )?,
+        &mut scope.with_op_name(&format!("{}_activation0", name.as_ref())),
+    )?;
+
+    let conv1 = separable_conv2d(
+        &format!("{}_conv1", name.as_ref()),
+        data_type,
+        activation0,
+        channels,
+        channels,
+        filter_size,
+        stride,
+        padding,
+        weight_init,
+        scope,
+    )?;
+
+    let add = add(
+        normalize(
+            &format!("{}_bn1", name.as_ref()),
+            data_type,
+            conv1.output,
+            channels,
+            batch_norm,
+            &mut batch_norms,
+            scope,
+        )?,
+        x,
+        &mut scope.with_op_name(&format!("{}_add", name.as_ref())),
+    )?;
+
+    Ok(Conv2DSeparableResidual {
+        depthwise_w0: conv0.depthwise_w,
+        pointwise_w0: conv0.pointwise_w,
+        b0: conv0.b,
+        depthwise_w1: conv1.depthwise_w,
+        pointwise_w1: conv1.pointwise_w,
+        b1: conv1.b,
+        batch_norms,
+        output: add,
+    })
+}
+
+/// A residual block of two convolutions with squeeze-and-excitation.
+/// The output of the convolutions is scaled and shifted per channel by small fully connected layers over its [global_pool],
+/// which gives every position the context of the whole board.
+pub fn se_residual(
+    name: impl AsRef<str>,
+    data_type: DataType,
+    x: Operation,
+    channels: i64,
+    se_channels: i64,
+    filter_size: &[i64; 2],
+    stride: &[i64; 2],
+    padding: Conv2DPadding,
+    weight_init: WeightInitializer,
+    activation: Activation,
+    batch_norm: Option<&BatchNormOptions>,
+    scope: &mut Scope,
+) -> Result<SeResidual, Status> {
+    let mut batch_norms = Vec::new();
+
+    let conv0 = conv2d(
+        &format!("{}_conv0", name.as_ref()),
+        data_type,
+        x.clone(),
+        channels,
+        channels,
+        filter_size,
+        stride,
+        Conv2DPadding::Same,
+        WeightInitializer::He,
+        scope,
+    )?;
+    let activation0 = activation.build(
+        normalize(
+            &format!("{}_bn0", name.as_ref()),
+            data_type,
+            conv0.output,
+            channels,
+            batch_norm,
+            &mut batch_norms,
+            scope,
+        )?,
+        &mut scope.with_op_name(&format!("{}_activation0", name.as_ref())),
+    )?;
+
+    let conv1 = conv2d(
+        &format!("{}_conv1", name.as_ref()),
+        data_type,
+        activation0,
+        channels,
+        channels,
+        filter_size,
+        stride,
+        padding,
+        weight_init,
+        scope,
+    )?;
+    let residual = normalize(
+        &format!("{}_bn1", name.as_ref()),
+        data_type,
+        conv1.output,
+        channels,
+        batch_norm,
+        &mut batch_norms,
+        scope,
+    )?;
+
+    let pool = global_pool(&format!("{}_pool", name.as_ref()), residual.clone(), scope)?;
+    let se_fc0 = fc(
+        &format!("{}_se_fc0", name.as_ref()),
+        data_type,
+        pool,
+        2 * channels,
+        se_channels,
+        WeightInitializer::He,
+        scope,
+    )?;
+    let se_activation = activation.build(
+        se_fc0.output,
+        &mut scope.with_op_name(&format!("{}_se_activation", name.as_ref())),
+    )?;
+    let se_scale = fc(
+        &format!("{}_se_scale", name.as_ref()),
+        data_type,
+        se_activation.clone(),
+        se_channels,
+        channels,
+        WeightInitializer::Xavier,
+        scope,
+    )?;
+    let se_bias = fc(
+        &format!("{}_se_bias", name.as_ref()),
+        data_type,
+        se_activation,
+        se_channels,
+        channels,
+        WeightInitializer::Xavier,
+        scope,
+    )?;
+
+    let scale = sigmoid(
+        reshape(
+            se_scale.output,
+            constant(&[-1, 1, 1, channels], scope)?,
+            scope,
+        )?,
+        &mut scope.with_op_name(&format!("{}_se_sigmoid", name.as_ref())),
+    )?;
+    let bias = reshape(
+        se_bias.output,
+        constant(&[-1, 1, 1, channels], scope)?,
+        scope,
+    )?;
+    let excitation = add(
+        mul(
+            residual,
+            scale,
+            &mut scope.with_op_name(&format!("{}_se_mul", name.as_ref())),
+        )?,
+        bias,
+        &mut scope.with_op_name(&format!("{}_se_add", name.as_ref())),
+    )?;
+
+    let add = add(
+        excitation,
+        x,
+        &mut scope.with_op_name(&format!("{}_add", name.as_ref())),
+    )?;
+
+    Ok(SeResidual {
+        w0: conv0.w,
+        b0: conv0.b,
+        w1: conv1.w,
+        b1: conv1.b,
+        se_w0: se_fc0.w,
+        se_b0: se_fc0.b,
+        se_scale_w: se_scale.w,
+        se_scale_b: se_scale.b,
+        se_bias_w: se_bias.w,
+        se_bias_b: se_bias.b,
+        batch_norms,
+        output: add,
+    })
+}
+
+/// Normalizes NHWC activations with the statistics of the batch or the moving averages, as selected by [BatchNormOptions::training].
+/// The moving averages are updated only by running [BatchNorm::update], which should be run along with the training step.
+pub fn batch_norm(
+    name: impl AsRef<str>,
+    data_type: DataType,
+    x: Operation,
+    channels: i64,
+    options: &BatchNormOptions,
+    scope: &mut Scope,
+) -> Result<BatchNorm, Status> {
+    let name = name.as_ref();
+    let scale = Variable::builder()
+        .data_type(data_type)
+        .shape(&[channels])
+        .initial_value(broadcast_to(
+            constant(&[1f32], scope)?,
+            constant(&[channels], scope)?,
+            scope,
+        )?)
+        .build(&mut scope.with_op_name(&format!("{}_scale", name)))?;
+    let offset = Variable::builder()
+        .data_type(data_type)
+        .shape(&[channels])
+        .initial_value(broadcast_to(
+            constant(&[0f32], scope)?,
+            constant(&[channels], scope)?,
+            scope,
+        )?)
+        .build(&mut scope.with_op_name(&format!("{}_offset", name)))?;
+    let mean = Variable::builder()
+        .data_type(data_type)
+        .shape(&[channels])
+        .initial_value(broadcast_to(
+            constant(&[0f32], scope)?,
+            constant(&[channels], scope)?,
+            scope,
+        )?)
+        .build(&mut scope.with_op_name(&format!("{}_mean", name)))?;
+    let variance = Variable::builder()
+        .data_type(data_type)
+        .shape(&[channels])
+        .initial_value(broadcast_to(
+            constant(&[1f32], scope)?,
+            constant(&[channels], scope)?,
+            scope,
+        )?)
+        .build(&mut scope.with_op_name(&format!("{}_variance", name)))?;
+
+    // Only the normalization selected by `training` should do any work, so it is given the whole batch
+    // and the other one an empty slice of it. `Switch` and `Merge` would skip the other one entirely,
+    // but the C API has no gradients for them.
+    let batch_size = slice(
+        shape(x.clone(), scope)?,
+        constant(&[0], scope)?,
+        constant(&[1], scope)?,
+        scope,
+    )?;
+    let training_size = select_v2(
+        options.training.clone(),
+        batch_size,
+        constant(&[0], scope)?,
+        scope,
+    )?;
+    let x_training = slice(
+        x.clone(),
+        constant(&[0, 0, 0, 0], scope)?,
+        concat(
+            vec![
+                training_size.clone().into(),
+                constant(&[-1, -1, -1], scope)?.into(),
+            ],
+            0,
+            scope,
+        )?,
+        scope,
+    )?;
+    let x_inference = slice(
+        x,
+        concat(
+            vec![training_size.into(), constant(&[0, 0, 0], scope)?.into()],
+            0,
+            scope,
+        )?,
+        constant(&[-1, -1, -1, -1], scope)?,
+        scope,
+    )?;
+
+    let bn_training = FusedBatchNormV3::new()
+        .data_format("NHWC")
+        .is_training(true)
+        .epsilon(BATCH_NORM_EPSILON)
+        .build(
+            x_training,
+            scale.output().clone(),
+            offset.output().clone(),
+            mean.output().clone(),
+            variance.output().clone(),
+            &mut scope.with_op_name(&format!("{}_bn_training", name)),
+        )?;
+    let bn_inference = FusedBatchNormV3::new()
+        .data_format("NHWC")
+        .is_training(false)
+        .epsilon(BATCH_NORM_EPSILON)
+        .build(
+            x_inference,
+            scale.output().clone(),
+            offset.output().clone(),
+            mean.output().clone(),
+            variance.output().clone(),
+            &mut scope.with_op_name(&format!("{}_bn_inference", name)),
+        )?;
+    let output = concat(
+        vec![bn_training.output(0), bn_inference.output(0)],
+        0,
+        &mut scope.with_op_name(&format!("{}_output", name)),
+    )?;
+
+    let momentum = constant(options.momentum, scope)?;
+    let rest = constant(1f32 - options.momentum, scope)?;
+    let update_mean = assign(
+        mean.output().clone(),
+        add(
+            mul(mean.output().clone(), momentum.clone(), scope)?,
+            mul(bn_training.output(1), rest.clone(), scope)?,
+            scope,
+        )?,
+        &mut scope.with_op_name(&format!("{}_update_mean", name)),
+    )?;
+    let update_variance = assign(
+        variance.output().clone(),
+        add(
+            mul(variance.output().clone(), momentum, scope)?,
+            mul(bn_training.output(2), rest, scope)?,
+            scope,
+        )?,
+        &mut scope.with_op_name(&format!("{}_update_variance", name)),
+    )?;
+    let update = NoOp::new()
+        .add_control_input(update_mean)
+        .add_control_input(update_variance)
+        .build(&mut scope.with_op_name(&format!("{}_update", name)))?;
+
+    Ok(BatchNorm {
+        scale,
+        offset,
+        mean,
+        variance,
+        output,
+        update,
+    })
+}
+
+pub fn batch_norm_fc(
+    name: impl AsRef<str>,
+    data_type: DataType,
+    x: Operation,
+    channels: i64,
+    options: &BatchNormOptions,
+    scope: &mut Scope,
+) -> Result<BatchNorm, Status> {
+    let name = name.as_ref();
+    let before_reshape = reshape(
+        x,
+        constant(&[-1, 1, 1, channels], scope)?,
+        &mut scope.with_op_name(&format!("{}_input_reshape", name)),
+    )?;
+    let mut bn = batch_norm(name, data_type, before_reshape, channels, options, scope)?;
+    let output = reshape(
+        bn.output,
+        constant(&[-1, channels], scope)?,
+        &mut scope.with_op_name(&format!("{}_output_reshape", name)),
+    )?;
+    bn.output = output;
+    Ok(bn)
+}
+
+/// Applies [batch_norm] if `options` is set, collecting the layer into `batch_norms`; returns `x` otherwise.
+pub fn normalize(
+    name: impl AsRef<str>,
+    data_type: DataType,
+    x: Operation,
+    channels: i64,
+    options: Option<&BatchNormOptions>,
+    batch_norms: &mut Vec<BatchNorm>,
+    scope: &mut Scope,
+) -> Result<Operation, Status> {
+    match options {
+        Some(options) => {
+            let bn = batch_norm(name, data_type, x, channels, options, scope)?;
+            let output = bn.output.clone();
+            batch_norms.push(bn);
+            Ok(output)
+        }
+        None => Ok(x),
+    }
+}
+
+/// Concatenates the given tensors along `axis`.
+fn concat(values: Vec<Output>, axis: i32, scope: &mut Scope) -> Result<Operation, Status> {
+    let axis = constant(axis, scope)?;
+    Ok(ConcatV2::new()
+        .build_instance(values, axis.into(), scope)?
+        .into())
+}
@@ -0,0 +1,291 @@
+use crate::{
+    config::{Config, ConfigError},
+    game_file::{read_game, write_game, GameFileError},
+    trainer::TrainerError,
+};
+use alpha_zero::{Checkpoint, CheckpointStore, CpuBackend, InferenceError, ModelIOError};
+use benchmark::{play_match, play_match_against_random, Agent, MatchResult};
+use environment::{Environment, GameStatus, Turn};
+use rand::{rngs::StdRng, SeedableRng};
+use std::{
+    fs::create_dir_all,
+    io::{stdin, stdout, Write},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CommandError {
+    #[error("inference error: {0}")]
+    Inference(#[from] InferenceError),
+    #[error("{0}")]
+    Trainer(#[from] TrainerError),
+    #[error("{0}")]
+    Config(#[from] ConfigError),
+    #[error("model io error: {0}")]
+    ModelIO(#[from] ModelIOError),
+    #[error("game file error: {0}")]
+    GameFile(#[from] GameFileError),
+    #[error("io error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("failed to format the config: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("config file {0:?} already exists; pass --force to overwrite it")]
+    ConfigExists(PathBuf),
+}
+
+/// Format of the weights written by [export].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// ONNX model of the policy and value network, see [CpuBackend::export_onnx].
+    Onnx,
+    /// Checkpoint without the optimizer state, which is all that inference needs.
+    Checkpoint,
+}
+
+/// The analysis marks a move whose value is lower than the one of the best move by more than this.
+const MISTAKE_THRESHOLD: f32 = 0.2;
+
+/// Plays `game_count` games of the model against the opponent model, or against random moves if there is none,
+/// and prints the result from the perspective of the model.
+pub fn eval(
+    config: &Config,
+    model: &Path,
+    opponent: Option<&Path>,
+    game_count: usize,
+    playouts: usize,
+) -> Result<(), CommandError> {
+    let mut agent = Agent::load(model)?;
+    agent.opening_move_count = config.evaluation.gating.opening_move_count;
+
+    let result = match opponent {
+        Some(opponent) => {
+            let mut opponent_agent = Agent::load(opponent)?;
+            opponent_agent.opening_move_count = config.evaluation.gating.opening_move_count;
+
+            println!("Playing {} games against {:?}...", game_count, opponent);
+
+            let result = play_match(
+                &mut agent,
+                &mut opponent_agent,
+                game_count,
+                playouts,
+                config.evaluation.evaluate_batch_size,
+            );
+
+            println!(
+                "Opponent nodes/second: {:.0}",
+                opponent_agent.average_nodes_per_second()
+            );
+
+            result
+        }
+        None => {
+            let mut rng = match config.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            };
+
+            println!("Playing {} games against random moves...", game_count);
+
+            play_match_against_random(
+                &mut agent,
+                &mut rng,
+                game_count,
+                playouts,
+                config.evaluation.evaluate_batch_size,
+            )
+        }
+    };
+
+    print_match_result(&result);
+    println!(
+        "Model nodes/second: {:.0}",
+        agent.average_nodes_per_second()
+    );
+
+    Ok(())
+}
+
+fn print_match_result(result: &MatchResult) {
+    println!("Wins: {}", result.wins);
+    println!("Losses: {}", result.losses);
+    println!("Draws: {}", result.draws);
+    println!("Score: {:.3}", result.score());
+}
+
+/// Plays a game against the model in the terminal, with the player's moves read from the standard input in board notation.
+/// The moves are written to `save_path` when the game ends or the player quits.
+pub fn play(
+    config: &Config,
+    model: &Path,
+    player_turn: Turn,
+    playouts: usize,
+    save_path: Option<&Path>,
+) -> Result<(), CommandError> {
+    let mut engine = Agent::load(model)?;
+    let mut actions = Vec::new();
+    let mut lines = stdin().lines();
+
+    println!(
+        "You play {}. Enter moves such as h8, or quit to stop.",
+        player_turn
+    );
+
+    let status = loop {
+        let action = if engine.agent.env.turn == player_turn {
+            println!();
+            print!("{}{} to move: ", engine.agent.env, player_turn);
+            stdout().flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => break None,
+            };
+            let line = line.trim();
+
+            if line == "quit" {
+                break None;
+            }
+
+            match Environment::notation_to_action(line) {
+                Some(action) if engine.agent.env.is_legal_move(action) => {
+                    engine.agent.ensure_action_exists(action, &engine.backend)?;
+                    action
+                }
+                _ => {
+                    println!("{:?} is not a legal move.", line);
+                    continue;
+                }
+            }
+        } else {
+            let action = engine.make_move(playouts, config.evaluation.evaluate_batch_size);
+            println!(
+                "Engine plays {}. [{}]",
+                Environment::action_to_notation(action),
+                engine.agent.search_info()
+            );
+            action
+        };
+
+        actions.push(action);
+        let status = engine.agent.play_action(action).unwrap();
+
+        if status.is_terminal() {
+            println!();
+            print!("{}", engine.agent.env);
+            break Some(status);
+        }
+    };
+
+    match status {
+        Some(GameStatus::BlackWin) => println!("{} wins.", Turn::Black),
+        Some(GameStatus::WhiteWin) => println!("{} wins.", Turn::White),
+        Some(_) => println!("Draw."),
+        None => println!("Game stopped."),
+    }
+
+    if let Some(save_path) = save_path {
+        write_game(save_path, &actions)?;
+        println!("Game saved to {:?}.", save_path);
+    }
+
+    Ok(())
+}
+
+/// Searches each position of a game file with the model, and prints the value of the move played next to the best move.
+/// The values are from the perspective of the player to move, and moves worse than the best one by [MISTAKE_THRESHOLD] are marked with `?`.
+pub fn analyze(
+    config: &Config,
+    model: &Path,
+    game_path: &Path,
+    playouts: usize,
+) -> Result<(), CommandError> {
+    let actions = read_game(game_path)?;
+    let mut engine = Agent::load(model)?;
+
+    for (index, &action) in actions.iter().enumerate() {
+        engine.mcts_executor.run(
+            playouts,
+            config.evaluation.evaluate_batch_size,
+            Agent::EPSILON,
+            Agent::ALPHA,
+            &engine.backend,
+            &engine.agent,
+        )?;
+
+        let info = engine.agent.search_info();
+        let best = info.best_child().unwrap();
+        let played = info
+            .children
+            .iter()
+            .find(|child| child.action == action && child.n != 0);
+
+        let played_summary = match played {
+            Some(played) => format!(
+                "q {:>6.3} visits {:>5.1}%",
+                played.q,
+                played.n as f32 / info.root_n as f32 * 100f32
+            ),
+            None => format!("q {:>6} visits {:>5.1}%", "-", 0f32),
+        };
+        let is_mistake = match played {
+            Some(played) => MISTAKE_THRESHOLD < best.q - played.q,
+            None => true,
+        };
+
+        println!(
+            "{:>3}. {} {:<3} {} | best {:<3} q {:>6.3} | {}{}",
+            index + 1,
+            engine.agent.env.turn,
+            Environment::action_to_notation(action),
+            played_summary,
+            Environment::action_to_notation(best.action),
+            best.q,
+            info,
+            if is_mistake { " ?" } else { "" }
+        );
+
+        engine.agent.ensure_action_exists(action, &engine.backend)?;
+        engine.agent.play_action(action).unwrap();
+    }
+
+    Ok(())
+}
+
+/// Writes the weights of the model, a checkpoint file or the best checkpoint of a store, in another format.
+pub fn export(model: &Path, format: ExportFormat, output: &Path) -> Result<(), CommandError> {
+    match format {
+        ExportFormat::Onnx => CpuBackend::load(model)?.export_onnx(output)?,
+        ExportFormat::Checkpoint => {
+            let mut checkpoint = Checkpoint::read(CheckpointStore::resolve(model)?)?;
+            checkpoint.optimizer_variables.clear();
+            checkpoint.write(output)?;
+        }
+    }
+
+    println!("Exported {:?} to {:?}.", model, output);
+
+    Ok(())
+}
+
+/// Writes the default config with the overrides applied to the config file of the given name.
+pub fn config_init(name: &str, force: bool, overrides: &[String]) -> Result<(), CommandError> {
+    let path = Config::path(name);
+
+    if path.exists() && !force {
+        return Err(CommandError::ConfigExists(path));
+    }
+
+    create_dir_all(path.parent().unwrap())?;
+    Config::default().with_overrides(overrides)?.save(&path)?;
+    println!("Wrote the config to {:?}.", path);
+
+    Ok(())
+}
+
+/// Prints the config in effect, with the defaults of the missing fields and the overrides applied.
+pub fn config_show(config: &Config) -> Result<(), CommandError> {
+    print!("{}", toml::to_string(config)?);
+    Ok(())
+}
@@ -1,20 +1,93 @@
+use alpha_zero::{
+    BlockType, LearningRateSchedule, NetworkConfig, OptimizerConfig, RetentionPolicy,
+};
+use environment::Environment;
 use serde::{Deserialize, Serialize};
-use std::{default::Default, fs, path::Path};
-use toml;
+use std::{
+    default::Default,
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+use toml::{Table, Value};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct Config {
-    pub parameters: Parameters,
-    // TODO: implement enviroment or make it separate from the config file.
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to access the config file {path:?}: {source}")]
+    IO {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("config file {0:?} does not exist; create it with `omok-ai config init`")]
+    NotFound(PathBuf),
+    #[error("failed to parse the config file {path:?}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("invalid config: {0}")]
+    Deserialize(toml::de::Error),
+    #[error("failed to format the config: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("invalid override {0:?}; expected KEY=VALUE, such as selfplay.episode_count=100")]
+    InvalidOverride(String),
+    #[error("environment variable {0} is not valid UTF-8")]
+    InvalidEnvironment(String),
+    #[error("invalid {key}: {message}")]
+    Invalid { key: String, message: String },
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct Parameters {
-    // Network Parameters
+/// Configuration of the trainer and the other commands.
+///
+/// It is layered from the defaults, the config file, the environment variables named like `OMOK_AI__SELFPLAY__EPISODE_COUNT`
+/// and the `--set selfplay.episode_count=100` overrides, in this order. Missing fields take their defaults,
+/// unknown ones are errors, and the values are checked by [Config::validate].
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Name of the run, which names its directories in `saves` and `replays`.
     pub model_name: String,
+    /// Seed of the self-play, the evaluation and the replay sampling. Seeded from entropy if not set.
+    /// Note that the initial weights of a new model are not seeded; load a saved model to reproduce a run.
+    pub seed: Option<u64>,
+    /// Runs the search and the TensorFlow session on a single thread, so that a seeded run is reproducible.
+    pub deterministic: bool,
+    pub rules: RulesConfig,
+    pub selfplay: SelfPlayConfig,
+    pub training: TrainingConfig,
+    /// Architecture of the network. It must match the one stored in the saved model, if any.
+    pub network: NetworkConfig,
+    pub evaluation: EvaluationConfig,
+    pub tensorboard: TensorBoardConfig,
+}
 
-    // Self Play Parameters
-    pub replay_memory_size: usize,
+/// Rules of the game. The [Environment] is built for a single rule set, so these only document it
+/// and make a config written for other rules fail instead of training with the wrong ones.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RulesConfig {
+    pub board_size: usize,
+    /// Number of stones in a row that win the game.
+    pub serial_stone_count: usize,
+    pub rule_set: String,
+}
+
+impl Default for RulesConfig {
+    fn default() -> Self {
+        Self {
+            board_size: Environment::BOARD_SIZE,
+            serial_stone_count: Environment::SERIAL_STONE_COUNT,
+            rule_set: Environment::RULE_SET.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SelfPlayConfig {
+    /// Number of games played in each iteration.
     pub episode_count: usize,
     pub evaluate_count: usize,
     pub evaluate_batch_size: usize,
@@ -22,51 +95,319 @@ pub struct Parameters {
     pub alpha: f32,
     pub temperature: f32,
     pub temperature_threshold: usize,
+}
 
-    // Training Parameters
+impl Default for SelfPlayConfig {
+    fn default() -> Self {
+        Self {
+            episode_count: 50,
+            evaluate_count: 600,
+            evaluate_batch_size: 16,
+            epsilon: 0.25,
+            alpha: 0.03,
+            temperature: 1.0,
+            temperature_threshold: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrainingConfig {
+    /// Number of iterations of the training, including those of the runs it resumes.
+    pub iteration_count: usize,
+    /// Size of the sliding window of self-play positions used for training. The rotations and flips of the positions
+    /// are sampled on the fly, so they do not count. The games are kept on disk under `replays/{model_name}`,
+    /// so the window survives restarts.
+    pub replay_memory_size: usize,
     pub parameter_update_count: usize,
     pub parameter_update_batch_size: usize,
+    /// Number of losses kept for the plot.
+    pub max_losses: usize,
+    /// Optimizer, learning rate schedule and regularization of the training.
+    pub optimizer: OptimizerConfig,
+    /// Which of the numbered checkpoints in `saves/{model_name}` to keep.
+    pub checkpoints: RetentionPolicy,
+}
+
+impl Default for TrainingConfig {
+    fn default() -> Self {
+        Self {
+            iteration_count: 10_000,
+            replay_memory_size: 600_000,
+            parameter_update_count: 600,
+            parameter_update_batch_size: 128,
+            max_losses: 1024 * 1024,
+            optimizer: OptimizerConfig::default(),
+            checkpoints: RetentionPolicy::default(),
+        }
+    }
+}
 
-    // Test Play Parameters
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EvaluationConfig {
+    /// Number of searches per move of the games against the random move player, and of the `eval`, `play` and `analyze` commands.
     pub test_evaluate_count: usize,
+    pub evaluate_batch_size: usize,
+    /// Evaluation of each newly trained model against the best one before it replaces it.
+    pub gating: GatingConfig,
+}
 
-    // Plot Parameters
-    pub max_losses: usize,
+impl Default for EvaluationConfig {
+    fn default() -> Self {
+        Self {
+            test_evaluate_count: 800,
+            evaluate_batch_size: 16,
+            gating: GatingConfig::default(),
+        }
+    }
 }
 
-impl Config {
-    pub fn new(name: &str) -> Self {
-        let path_base = Path::new("config");
-        let path_file = path_base.join(Path::new(name).with_extension("toml"));
+/// The newly trained model plays `game_count` games against the best one, half of them as black,
+/// and replaces it only if its score is at least `score_threshold`.
+/// The best model plays the self-play games, while the training continues from the newly trained one either way.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GatingConfig {
+    /// Every trained model replaces the best one if it is 0.
+    pub game_count: usize,
+    /// Ratio of the games to win, counting draws as half a win.
+    pub score_threshold: f32,
+    pub evaluate_count: usize,
+    /// Number of opening moves sampled from the search policy, so that the games differ.
+    pub opening_move_count: usize,
+}
 
-        if !path_base.exists() {
-            fs::create_dir_all(path_base).unwrap();
+impl Default for GatingConfig {
+    fn default() -> Self {
+        Self {
+            game_count: 40,
+            score_threshold: 0.55,
+            evaluate_count: 400,
+            opening_move_count: 4,
         }
+    }
+}
+
+/// TensorBoard event files written by the trainer into `{log_dir}/{model_name}`, which can be viewed with `tensorboard --logdir {log_dir}`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TensorBoardConfig {
+    pub enabled: bool,
+    pub log_dir: String,
+    /// Number of iterations between the histograms of the weights. They are not written if it is 0.
+    pub histogram_interval: usize,
+}
 
-        if !path_file.exists() {
-            Self::save(path_file.to_str().unwrap().to_string()).unwrap();
+impl Default for TensorBoardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            log_dir: "runs".to_owned(),
+            histogram_interval: 1,
         }
+    }
+}
+
+/// Keys of the config files written before the sections were split, when every value was in `[parameters]`, and the keys they have moved to.
+const LEGACY_KEYS: &[(&str, &[&str])] = &[
+    ("parameters.model_name", &["model_name"]),
+    ("parameters.episode_count", &["selfplay.episode_count"]),
+    ("parameters.evaluate_count", &["selfplay.evaluate_count"]),
+    (
+        "parameters.evaluate_batch_size",
+        &[
+            "selfplay.evaluate_batch_size",
+            "evaluation.evaluate_batch_size",
+        ],
+    ),
+    ("parameters.epsilon", &["selfplay.epsilon"]),
+    ("parameters.alpha", &["selfplay.alpha"]),
+    ("parameters.temperature", &["selfplay.temperature"]),
+    (
+        "parameters.temperature_threshold",
+        &["selfplay.temperature_threshold"],
+    ),
+    (
+        "parameters.replay_memory_size",
+        &["training.replay_memory_size"],
+    ),
+    (
+        "parameters.parameter_update_count",
+        &["training.parameter_update_count"],
+    ),
+    (
+        "parameters.parameter_update_batch_size",
+        &["training.parameter_update_batch_size"],
+    ),
+    ("parameters.max_losses", &["training.max_losses"]),
+    (
+        "parameters.test_evaluate_count",
+        &["evaluation.test_evaluate_count"],
+    ),
+];
 
-        match Self::load(path_file.to_str().unwrap().to_string()) {
-            Ok(config) => config,
-            Err(_) => {
-                println!("failed to load configuration, using default config");
-                Self::default()
+impl Config {
+    /// Prefix of the environment variables overriding the config. The rest of the name is the key,
+    /// with `__` separating the sections, such as `OMOK_AI__SELFPLAY__EPISODE_COUNT`.
+    pub const ENV_PREFIX: &str = "OMOK_AI__";
+
+    /// Returns the path of the config file of the given name.
+    pub fn path(name: &str) -> PathBuf {
+        Path::new("config").join(Path::new(name).with_extension("toml"))
+    }
+
+    /// Loads the config file of the given name and applies the overrides, see [Config].
+    /// A missing file is an error if `required` is set, and is read as an empty one otherwise. Nothing is written.
+    pub fn load(name: &str, required: bool, overrides: &[String]) -> Result<Self, ConfigError> {
+        let path = Self::path(name);
+
+        let table = if path.exists() {
+            let contents = fs::read_to_string(&path).map_err(|source| ConfigError::IO {
+                path: path.clone(),
+                source,
+            })?;
+            contents
+                .parse::<Table>()
+                .map_err(|source| ConfigError::Parse { path, source })?
+        } else if required {
+            return Err(ConfigError::NotFound(path));
+        } else {
+            Table::new()
+        };
+
+        Self::from_table(table, env::vars_os(), overrides)
+    }
+
+    /// Applies the overrides of the environment and `overrides` to this config, like [Config::load] does to the config file.
+    pub fn with_overrides(&self, overrides: &[String]) -> Result<Self, ConfigError> {
+        Self::from_table(Table::try_from(self)?, env::vars_os(), overrides)
+    }
+
+    /// Builds the config from `table` and the overrides, reading the environment variables from `vars`.
+    fn from_table(
+        mut table: Table,
+        vars: impl IntoIterator<Item = (OsString, OsString)>,
+        overrides: &[String],
+    ) -> Result<Self, ConfigError> {
+        migrate_legacy_keys(&mut table)?;
+
+        for (name, value) in vars {
+            // The other variables are not ours to check, so only the overrides have to be valid UTF-8.
+            let lossy_name = name.to_string_lossy();
+            if let Some(key) = lossy_name.strip_prefix(Self::ENV_PREFIX) {
+                let (Some(_), Some(value)) = (name.to_str(), value.to_str()) else {
+                    return Err(ConfigError::InvalidEnvironment(lossy_name.into_owned()));
+                };
+                let key = key.split("__").collect::<Vec<_>>().join(".");
+                set_override(&mut table, &key.to_lowercase(), value)?;
             }
         }
-    }
 
-    pub fn load(path: String) -> Result<Self, std::io::Error> {
-        let path = Path::new(&path);
-        let contents = fs::read_to_string(path)?;
-        let config = toml::from_str(&contents).unwrap();
+        for assignment in overrides {
+            let (key, value) = assignment
+                .split_once('=')
+                .ok_or_else(|| ConfigError::InvalidOverride(assignment.clone()))?;
+            set_override(&mut table, key.trim(), value.trim())?;
+        }
+
+        let config = Value::Table(table)
+            .try_into::<Self>()
+            .map_err(ConfigError::Deserialize)?;
+        config.validate()?;
+
         Ok(config)
     }
 
-    pub fn save(path: String) -> Result<(), std::io::Error> {
-        let path = Path::new(&path);
-        let contents = toml::to_string(&Config::default()).unwrap();
-        fs::write(path, contents)?;
+    /// Writes the config to `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ConfigError> {
+        let path = path.as_ref();
+        fs::write(path, toml::to_string(self)?).map_err(|source| ConfigError::IO {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Checks the values which are well-formed but cannot be used, such as empty batches.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check(
+            !self.model_name.is_empty()
+                && !self.model_name.contains(['/', '\\'])
+                && self.model_name != "."
+                && self.model_name != "..",
+            "model_name",
+            "must be a directory name",
+        )?;
+
+        check(
+            self.rules.board_size == Environment::BOARD_SIZE,
+            "rules.board_size",
+            &format!("must be {}", Environment::BOARD_SIZE),
+        )?;
+        check(
+            self.rules.serial_stone_count == Environment::SERIAL_STONE_COUNT,
+            "rules.serial_stone_count",
+            &format!("must be {}", Environment::SERIAL_STONE_COUNT),
+        )?;
+        check(
+            self.rules.rule_set == Environment::RULE_SET,
+            "rules.rule_set",
+            &format!("must be {:?}", Environment::RULE_SET),
+        )?;
+
+        let selfplay = &self.selfplay;
+        check_positive(selfplay.episode_count, "selfplay.episode_count")?;
+        check_positive(selfplay.evaluate_count, "selfplay.evaluate_count")?;
+        check_positive(selfplay.evaluate_batch_size, "selfplay.evaluate_batch_size")?;
+        check(
+            (0f32..=1f32).contains(&selfplay.epsilon),
+            "selfplay.epsilon",
+            "must be between 0 and 1",
+        )?;
+        check(0f32 < selfplay.alpha, "selfplay.alpha", "must be positive")?;
+        check(
+            0f32 < selfplay.temperature,
+            "selfplay.temperature",
+            "must be positive",
+        )?;
+
+        let training = &self.training;
+        check_positive(training.replay_memory_size, "training.replay_memory_size")?;
+        check_positive(
+            training.parameter_update_batch_size,
+            "training.parameter_update_batch_size",
+        )?;
+        check_positive(training.max_losses, "training.max_losses")?;
+        check_optimizer(&training.optimizer)?;
+
+        check_network(&self.network)?;
+
+        let evaluation = &self.evaluation;
+        check_positive(
+            evaluation.test_evaluate_count,
+            "evaluation.test_evaluate_count",
+        )?;
+        check_positive(
+            evaluation.evaluate_batch_size,
+            "evaluation.evaluate_batch_size",
+        )?;
+        check_positive(
+            evaluation.gating.evaluate_count,
+            "evaluation.gating.evaluate_count",
+        )?;
+        check(
+            (0f32..=1f32).contains(&evaluation.gating.score_threshold),
+            "evaluation.gating.score_threshold",
+            "must be between 0 and 1",
+        )?;
+
+        check(
+            !self.tensorboard.enabled || !self.tensorboard.log_dir.is_empty(),
+            "tensorboard.log_dir",
+            "must not be empty",
+        )?;
+
         Ok(())
     }
 }
@@ -74,36 +415,342 @@ impl Config {
 impl Default for Config {
     fn default() -> Self {
         Self {
-            parameters: Parameters::default(),
+            model_name: "alpha-zero".to_owned(),
+            seed: None,
+            deterministic: false,
+            rules: RulesConfig::default(),
+            selfplay: SelfPlayConfig::default(),
+            training: TrainingConfig::default(),
+            network: NetworkConfig::default(),
+            evaluation: EvaluationConfig::default(),
+            tensorboard: TensorBoardConfig::default(),
         }
     }
 }
 
-impl Default for Parameters {
-    fn default() -> Parameters {
-        Parameters {
-            // Network Parameters
-            model_name: "alpha-zero".to_owned(),
+fn check_optimizer(optimizer: &OptimizerConfig) -> Result<(), ConfigError> {
+    check(
+        0f32 < optimizer.learning_rate,
+        "training.optimizer.learning_rate",
+        "must be positive",
+    )?;
 
-            // Self Play Parameters
-            replay_memory_size: 600_000,
-            episode_count: 50,
-            evaluate_count: 600,
-            evaluate_batch_size: 16,
-            epsilon: 0.25,
-            alpha: 0.03,
-            temperature: 1.0,
-            temperature_threshold: 30,
+    for (value, key) in [
+        (
+            optimizer.l2_regularization,
+            "training.optimizer.l2_regularization",
+        ),
+        (
+            optimizer.reply_policy_loss_weight,
+            "training.optimizer.reply_policy_loss_weight",
+        ),
+        (
+            optimizer.ownership_loss_weight,
+            "training.optimizer.ownership_loss_weight",
+        ),
+    ] {
+        check(0f32 <= value, key, "must not be negative")?;
+    }
 
-            // Training Parameters
-            parameter_update_count: 600,
-            parameter_update_batch_size: 128,
+    match optimizer.schedule {
+        LearningRateSchedule::Constant => Ok(()),
+        LearningRateSchedule::Step { interval, .. } => check(
+            interval != 0,
+            "training.optimizer.schedule.interval",
+            "must be positive",
+        ),
+        LearningRateSchedule::Cosine { decay_steps, .. } => check(
+            decay_steps != 0,
+            "training.optimizer.schedule.decay_steps",
+            "must be positive",
+        ),
+    }
+}
 
-            // Test Play Parameters
-            test_evaluate_count: 800,
+fn check_network(network: &NetworkConfig) -> Result<(), ConfigError> {
+    check(
+        0 <= network.block_count,
+        "network.block_count",
+        "must not be negative",
+    )?;
+    check(
+        0 < network.filter_size && network.filter_size % 2 == 1,
+        "network.filter_size",
+        "must be a positive odd number",
+    )?;
+    check(
+        0 <= network.v_fc_size,
+        "network.v_fc_size",
+        "must not be negative",
+    )?;
 
-            // Plot Parameters
-            max_losses: 1024 * 1024,
+    for (value, key) in [
+        (network.channels, "network.channels"),
+        (network.v_conv_channels, "network.v_conv_channels"),
+        (network.p_conv_channels, "network.p_conv_channels"),
+    ] {
+        check(0 < value, key, "must be positive")?;
+    }
+
+    match network.block_type {
+        BlockType::Bottleneck => check(
+            0 < network.bottleneck_channels,
+            "network.bottleneck_channels",
+            "must be positive for bottleneck blocks",
+        ),
+        BlockType::SqueezeExcitation => check(
+            0 < network.se_channels,
+            "network.se_channels",
+            "must be positive for squeeze-and-excitation blocks",
+        ),
+        BlockType::Residual | BlockType::Separable => Ok(()),
+    }
+}
+
+fn check(condition: bool, key: &str, message: &str) -> Result<(), ConfigError> {
+    if condition {
+        Ok(())
+    } else {
+        Err(ConfigError::Invalid {
+            key: key.to_owned(),
+            message: message.to_owned(),
+        })
+    }
+}
+
+fn check_positive(value: usize, key: &str) -> Result<(), ConfigError> {
+    check(value != 0, key, "must be positive")
+}
+
+/// Returns the keys that a key of a config written before the sections were split has moved to.
+fn migrate_key(key: &str) -> Vec<String> {
+    match LEGACY_KEYS
+        .iter()
+        .find(|&&(legacy_key, _)| legacy_key == key)
+    {
+        Some(&(_, keys)) => keys.iter().map(|&key| key.to_owned()).collect(),
+        None => vec![key.to_owned()],
+    }
+}
+
+/// Moves the values of the legacy keys of a config file to their keys, unless the file sets them too.
+fn migrate_legacy_keys(table: &mut Table) -> Result<(), ConfigError> {
+    for &(legacy_key, _) in LEGACY_KEYS {
+        let value = match remove_value(table, legacy_key) {
+            Some(value) => value,
+            None => continue,
+        };
+
+        for key in migrate_key(legacy_key) {
+            if get_value(table, &key).is_none() {
+                set_value(table, &key, value.clone())?;
+            }
+        }
+    }
+
+    if let Some(Value::Table(parameters)) = table.get("parameters") {
+        if let Some(key) = parameters.keys().next() {
+            return Err(ConfigError::Invalid {
+                key: format!("parameters.{}", key),
+                message: "unknown key".to_owned(),
+            });
+        }
+
+        table.remove("parameters");
+    }
+
+    Ok(())
+}
+
+/// Sets the value of a key given by an override, which is parsed as a TOML value, or as a string if it is not one.
+fn set_override(table: &mut Table, key: &str, value: &str) -> Result<(), ConfigError> {
+    let value = match format!("value = {}", value).parse::<Table>() {
+        Ok(mut parsed) => parsed.remove("value").unwrap(),
+        Err(_) => Value::String(value.to_owned()),
+    };
+
+    for key in migrate_key(key) {
+        set_value(table, &key, value.clone())?;
+    }
+
+    Ok(())
+}
+
+fn get_value<'a>(table: &'a Table, key: &str) -> Option<&'a Value> {
+    let (parent, name) = match key.rsplit_once('.') {
+        Some((parent, name)) => (get_value(table, parent)?.as_table()?, name),
+        None => (table, key),
+    };
+
+    parent.get(name)
+}
+
+fn remove_value(table: &mut Table, key: &str) -> Option<Value> {
+    match key.split_once('.') {
+        Some((name, rest)) => remove_value(table.get_mut(name)?.as_table_mut()?, rest),
+        None => table.remove(key),
+    }
+}
+
+/// Sets the value of a dotted key, creating the tables on the way.
+fn set_value(table: &mut Table, key: &str, value: Value) -> Result<(), ConfigError> {
+    match key.split_once('.') {
+        Some((name, rest)) => {
+            let child = table
+                .entry(name)
+                .or_insert_with(|| Value::Table(Table::new()));
+
+            match child.as_table_mut() {
+                Some(child) => set_value(child, rest, value),
+                None => Err(ConfigError::Invalid {
+                    key: key.to_owned(),
+                    message: format!("{} is not a section", name),
+                }),
+            }
+        }
+        None if key.is_empty() => Err(ConfigError::InvalidOverride(key.to_owned())),
+        None => {
+            table.insert(key.to_owned(), value);
+            Ok(())
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(contents: &str, overrides: &[&str]) -> Result<Config, ConfigError> {
+        parse_with_vars(contents, Vec::new(), overrides)
+    }
+
+    fn parse_with_vars(
+        contents: &str,
+        vars: Vec<(OsString, OsString)>,
+        overrides: &[&str],
+    ) -> Result<Config, ConfigError> {
+        Config::from_table(
+            contents.parse().unwrap(),
+            vars,
+            &overrides
+                .iter()
+                .map(|&assignment| assignment.to_owned())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn test_config_layers() {
+        let config = parse(
+            "model_name = \"test\"\n[selfplay]\nepisode_count = 7\n[training.optimizer]\nlearning_rate = 0.5\n",
+            &["selfplay.alpha=0.5", "training.checkpoints.keep_last = 2"],
+        )
+        .unwrap();
+        assert_eq!(config.model_name, "test");
+        assert_eq!(config.selfplay.episode_count, 7);
+        assert_eq!(config.selfplay.alpha, 0.5);
+        assert_eq!(config.selfplay.epsilon, SelfPlayConfig::default().epsilon);
+        assert_eq!(config.training.optimizer.learning_rate, 0.5);
+        assert_eq!(config.training.checkpoints.keep_last, 2);
+
+        let config = parse("", &["model_name=other"]).unwrap();
+        assert_eq!(config.model_name, "other");
+        assert_eq!(config.with_overrides(&[]).unwrap(), config);
+    }
+
+    #[test]
+    fn test_config_environment() {
+        let vars = vec![
+            ("OMOK_AI__SELFPLAY__EPISODE_COUNT".into(), "9".into()),
+            ("OMOK_AI__MODEL_NAME".into(), "env".into()),
+            ("PATH".into(), "/bin".into()),
+        ];
+        let config = parse_with_vars("[selfplay]\nepisode_count = 7\n", vars.clone(), &[]).unwrap();
+        assert_eq!(config.selfplay.episode_count, 9);
+        assert_eq!(config.model_name, "env");
+
+        // The command line overrides the environment.
+        let config = parse_with_vars("", vars, &["selfplay.episode_count=3"]).unwrap();
+        assert_eq!(config.selfplay.episode_count, 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_config_non_utf8_environment() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let invalid = || OsString::from_vec(vec![0x66, 0x6f, 0x80]);
+
+        // Variables without the prefix are skipped whatever they contain.
+        let config = parse_with_vars(
+            "",
+            vec![(invalid(), invalid()), ("OTHER".into(), invalid())],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(config, parse("", &[]).unwrap());
+
+        assert!(matches!(
+            parse_with_vars("", vec![("OMOK_AI__MODEL_NAME".into(), invalid())], &[]),
+            Err(ConfigError::InvalidEnvironment(name)) if name == "OMOK_AI__MODEL_NAME"
+        ));
+        let mut name = b"OMOK_AI__".to_vec();
+        name.push(0x80);
+        assert!(matches!(
+            parse_with_vars("", vec![(OsString::from_vec(name), "1".into())], &[]),
+            Err(ConfigError::InvalidEnvironment(name)) if name.starts_with(Config::ENV_PREFIX)
+        ));
+    }
+
+    #[test]
+    fn test_config_legacy_keys() {
+        let config = parse(
+            "[parameters]\nmodel_name = \"legacy\"\nepisode_count = 3\nevaluate_batch_size = 4\n",
+            &["parameters.max_losses=10"],
+        )
+        .unwrap();
+        assert_eq!(config.model_name, "legacy");
+        assert_eq!(config.selfplay.episode_count, 3);
+        assert_eq!(config.selfplay.evaluate_batch_size, 4);
+        assert_eq!(config.evaluation.evaluate_batch_size, 4);
+        assert_eq!(config.training.max_losses, 10);
+
+        assert!(matches!(
+            parse("[parameters]\nepisode_cont = 3\n", &[]),
+            Err(ConfigError::Invalid { key, .. }) if key == "parameters.episode_cont"
+        ));
+        // Only the keys of the `[parameters]` section have moved.
+        assert!(matches!(
+            parse("[parameters]\nseed = 3\n", &[]),
+            Err(ConfigError::Invalid { key, .. }) if key == "parameters.seed"
+        ));
+    }
+
+    #[test]
+    fn test_config_errors() {
+        assert!(matches!(
+            parse("[selfplay]\nepisode_cont = 3\n", &[]),
+            Err(ConfigError::Deserialize(_))
+        ));
+        assert!(matches!(
+            parse("[selfplay]\nepisode_count = \"many\"\n", &[]),
+            Err(ConfigError::Deserialize(_))
+        ));
+        assert!(matches!(
+            parse("", &["selfplay.episode_count"]),
+            Err(ConfigError::InvalidOverride(_))
+        ));
+        assert!(matches!(
+            parse("model_name = \"a\"", &["model_name.x=1"]),
+            Err(ConfigError::Invalid { .. })
+        ));
+        assert!(matches!(
+            parse("", &["selfplay.epsilon=2"]),
+            Err(ConfigError::Invalid { key, .. }) if key == "selfplay.epsilon"
+        ));
+        assert!(matches!(
+            parse("[rules]\nboard_size = 19\n", &[]),
+            Err(ConfigError::Invalid { key, .. }) if key == "rules.board_size"
+        ));
+    }
+}
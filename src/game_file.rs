@@ -0,0 +1,110 @@
+use environment::{Environment, GameStatus};
+use std::{
+    fs::{read_to_string, write},
+    path::Path,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum GameFileError {
+    #[error("io error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("move {index} {notation:?} is not a point on the board")]
+    InvalidNotation { index: usize, notation: String },
+    #[error("move {index} {notation} is played on an occupied point")]
+    IllegalMove { index: usize, notation: String },
+    #[error("move {index} is played after the end of the game")]
+    MoveAfterEnd { index: usize },
+}
+
+/// Parses a game written as its moves in board notation, such as `h8 i9 h9`, starting with black.
+/// The moves are separated by whitespace, and the rest of a line after `#` is a comment.
+/// Moves are numbered from 1 in the errors.
+pub fn parse_game(contents: &str) -> Result<Vec<usize>, GameFileError> {
+    let mut env = Environment::new();
+    let mut status = GameStatus::InProgress;
+    let mut actions = Vec::new();
+
+    let notations = contents
+        .lines()
+        .flat_map(|line| line.split('#').next().unwrap().split_whitespace());
+
+    for (index, notation) in notations.enumerate() {
+        let index = index + 1;
+
+        if status.is_terminal() {
+            return Err(GameFileError::MoveAfterEnd { index });
+        }
+
+        let action = Environment::notation_to_action(notation).ok_or_else(|| {
+            GameFileError::InvalidNotation {
+                index,
+                notation: notation.to_owned(),
+            }
+        })?;
+
+        status = env
+            .place_stone(action)
+            .ok_or_else(|| GameFileError::IllegalMove {
+                index,
+                notation: notation.to_owned(),
+            })?;
+        actions.push(action);
+    }
+
+    Ok(actions)
+}
+
+/// Formats the actions in the format read by [parse_game], one move of each player per line.
+pub fn format_game(actions: &[usize]) -> String {
+    let mut contents = String::new();
+
+    for moves in actions.chunks(2) {
+        let notations = moves
+            .iter()
+            .map(|&action| Environment::action_to_notation(action))
+            .collect::<Vec<_>>();
+        contents.push_str(&notations.join(" "));
+        contents.push('\n');
+    }
+
+    contents
+}
+
+pub fn read_game(path: impl AsRef<Path>) -> Result<Vec<usize>, GameFileError> {
+    parse_game(&read_to_string(path)?)
+}
+
+pub fn write_game(path: impl AsRef<Path>, actions: &[usize]) -> Result<(), GameFileError> {
+    write(path, format_game(actions))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_game() {
+        let actions = parse_game("# opening\nh8 i9\nH9 # black\n\nh10").unwrap();
+        assert_eq!(
+            actions,
+            ["h8", "i9", "h9", "h10"]
+                .map(|notation| Environment::notation_to_action(notation).unwrap())
+        );
+        assert_eq!(parse_game(&format_game(&actions)).unwrap(), actions);
+
+        assert!(matches!(
+            parse_game("h8 z9"),
+            Err(GameFileError::InvalidNotation { index: 2, .. })
+        ));
+        assert!(matches!(
+            parse_game("h8 i9 h8"),
+            Err(GameFileError::IllegalMove { index: 3, .. })
+        ));
+        assert!(matches!(
+            parse_game("a1 a2 b1 b2 c1 c2 d1 d2 e1 e2"),
+            Err(GameFileError::MoveAfterEnd { index: 10 })
+        ));
+    }
+}
@@ -1,11 +1,41 @@
+mod commands;
+mod config;
+mod game_file;
+mod metrics;
 mod plot;
+mod replay_buffer;
+mod tensorboard;
 mod trainer;
+mod trainer_state;
 mod utils;
-mod config;
 
-use tensorflow::Status;
+use clap::{parser::ValueSource, value_parser, Arg, ArgAction, ArgMatches, Command};
+use commands::{CommandError, ExportFormat};
+use config::Config;
+use environment::Turn;
+use std::{
+    path::{Path, PathBuf},
+    process::{self, exit},
+};
 use trainer::Trainer;
-use clap::{Arg,Command};
+
+fn model_arg() -> Arg {
+    Arg::new("model")
+        .help(
+            "Checkpoint file or checkpoint directory of the model; defaults to saves/{model_name}",
+        )
+        .long("model")
+        .value_parser(value_parser!(PathBuf))
+}
+
+fn playouts_arg() -> Arg {
+    Arg::new("playouts")
+        .help(
+            "Number of searches per move; defaults to evaluation.test_evaluate_count of the config",
+        )
+        .long("playouts")
+        .value_parser(value_parser!(usize))
+}
 
 fn cli() -> Command {
     Command::new("omok-ai")
@@ -14,16 +44,243 @@ fn cli() -> Command {
                 .help("Name of the config file")
                 .short('c')
                 .long("config")
+                .global(true)
                 .default_value("default"),
         )
+        .arg(
+            Arg::new("set")
+                .help("Overrides a config value, such as selfplay.episode_count=100. \
+                    Environment variables such as OMOK_AI__SELFPLAY__EPISODE_COUNT=100 override it as well, before these")
+                .long("set")
+                .value_name("KEY=VALUE")
+                .global(true)
+                .action(ArgAction::Append),
+        )
+        .subcommand(
+            Command::new("train")
+                .about("Alternates self-play and training; this is the default")
+                .arg(
+                    Arg::new("no-selfplay")
+                        .help("Train only on the games written by selfplay processes")
+                        .long("no-selfplay")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("resume")
+                        .help("Continue the previous run from its saved trainer state and config")
+                        .long("resume")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("iterations")
+                        .help("Total number of iterations to train, overriding training.iteration_count of the config")
+                        .long("iterations")
+                        .value_parser(value_parser!(usize)),
+                ),
+        )
+        .subcommand(
+            Command::new("selfplay")
+                .about("Plays games with the best saved model for a trainer running with --no-selfplay")
+                .arg(
+                    Arg::new("worker-id")
+                        .help("Distinguishes the games of this process from those of the other selfplay processes \
+                            when the config sets a seed; defaults to the process id")
+                        .long("worker-id")
+                        .value_parser(value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            Command::new("eval")
+                .about("Plays the model against another model, or against random moves")
+                .arg(model_arg())
+                .arg(
+                    Arg::new("opponent")
+                        .help("Checkpoint file or checkpoint directory of the opponent; plays random moves if not given")
+                        .long("opponent")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("games")
+                        .help("Number of games, half of them as black")
+                        .long("games")
+                        .value_parser(value_parser!(usize))
+                        .default_value("100"),
+                )
+                .arg(playouts_arg()),
+        )
+        .subcommand(
+            Command::new("play")
+                .about("Plays a game against the model in the terminal")
+                .arg(model_arg())
+                .arg(
+                    Arg::new("color")
+                        .help("Color of your stones")
+                        .long("color")
+                        .value_parser(["black", "white"])
+                        .default_value("black"),
+                )
+                .arg(
+                    Arg::new("save")
+                        .help("Writes the moves of the game to this file, which can be analyzed")
+                        .long("save")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(playouts_arg()),
+        )
+        .subcommand(
+            Command::new("analyze")
+                .about("Searches each position of a game file and compares the moves played with the best ones")
+                .arg(
+                    Arg::new("game")
+                        .help("Game file with the moves in board notation, such as h8 i9 h9")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(model_arg())
+                .arg(playouts_arg()),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Writes the weights of the model in another format")
+                .arg(model_arg())
+                .arg(
+                    Arg::new("format")
+                        .help("onnx for the ONNX model, checkpoint for a checkpoint without the optimizer state")
+                        .long("format")
+                        .value_parser(["onnx", "checkpoint"])
+                        .default_value("onnx"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .help("Path of the exported file")
+                        .short('o')
+                        .long("output")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Manages the config file")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("init")
+                        .about("Writes the default config file, with the overrides applied")
+                        .arg(
+                            Arg::new("force")
+                                .help("Overwrites the config file if it exists")
+                                .long("force")
+                                .action(ArgAction::SetTrue),
+                        ),
+                )
+                .subcommand(
+                    Command::new("show")
+                        .about("Prints the config in effect, including the defaults"),
+                ),
+        )
 }
 
-fn main() -> Result<(), Status> {
+fn model_path(args: &ArgMatches, config: &Config) -> PathBuf {
+    match args.get_one::<PathBuf>("model") {
+        Some(model) => model.clone(),
+        None => Path::new("saves").join(&config.model_name),
+    }
+}
+
+fn playouts(args: &ArgMatches, config: &Config) -> usize {
+    args.get_one::<usize>("playouts")
+        .copied()
+        .unwrap_or(config.evaluation.test_evaluate_count)
+}
+
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("error: {}", error);
+        exit(1);
+    }
+}
+
+fn run() -> Result<(), CommandError> {
     let args = cli().get_matches();
     let config_name = args.get_one::<String>("config").unwrap();
-        
-    let mut train = Trainer::new(config_name)?;
+    let overrides = args
+        .get_many::<String>("set")
+        .map(|overrides| overrides.cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    // Only the default config file may be missing.
+    let is_config_required = args.value_source("config") != Some(ValueSource::DefaultValue);
+    let load_config = || Config::load(config_name, is_config_required, &overrides);
+
+    match args.subcommand() {
+        Some(("selfplay", args)) => {
+            let worker_id = args
+                .get_one::<u64>("worker-id")
+                .copied()
+                .unwrap_or_else(|| process::id() as u64);
+            Trainer::new_self_play_worker(load_config()?, worker_id)?.self_play_worker()?
+        }
+        Some(("train", args)) => {
+            let mut train = Trainer::new(load_config()?, args.get_flag("resume"), &overrides)?;
+
+            if let Some(&iteration_count) = args.get_one::<usize>("iterations") {
+                train.config.training.iteration_count = iteration_count;
+            }
+
+            train.train(!args.get_flag("no-selfplay"))?
+        }
+        Some(("eval", args)) => {
+            let config = load_config()?;
+            commands::eval(
+                &config,
+                &model_path(args, &config),
+                args.get_one::<PathBuf>("opponent").map(PathBuf::as_path),
+                *args.get_one::<usize>("games").unwrap(),
+                playouts(args, &config),
+            )?
+        }
+        Some(("play", args)) => {
+            let config = load_config()?;
+            let player_turn = match args.get_one::<String>("color").unwrap().as_str() {
+                "white" => Turn::White,
+                _ => Turn::Black,
+            };
+            commands::play(
+                &config,
+                &model_path(args, &config),
+                player_turn,
+                playouts(args, &config),
+                args.get_one::<PathBuf>("save").map(PathBuf::as_path),
+            )?
+        }
+        Some(("analyze", args)) => {
+            let config = load_config()?;
+            commands::analyze(
+                &config,
+                &model_path(args, &config),
+                args.get_one::<PathBuf>("game").unwrap(),
+                playouts(args, &config),
+            )?
+        }
+        Some(("export", args)) => {
+            let config = load_config()?;
+            let format = match args.get_one::<String>("format").unwrap().as_str() {
+                "checkpoint" => ExportFormat::Checkpoint,
+                _ => ExportFormat::Onnx,
+            };
+            commands::export(
+                &model_path(args, &config),
+                format,
+                args.get_one::<PathBuf>("output").unwrap(),
+            )?
+        }
+        Some(("config", args)) => match args.subcommand() {
+            Some(("init", args)) => {
+                commands::config_init(config_name, args.get_flag("force"), &overrides)?
+            }
+            _ => commands::config_show(&load_config()?)?,
+        },
+        _ => Trainer::new(load_config()?, false, &overrides)?.train(true)?,
+    }
 
-    train.train(10_000)?;
     Ok(())
 }
@@ -0,0 +1,257 @@
+use crate::replay_buffer::GameRecord;
+use environment::GameStatus;
+use serde::Serialize;
+use std::{
+    fs::{create_dir_all, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum MetricsError {
+    #[error("io error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Metrics of a training iteration. The fields which are not measured in an iteration are empty.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct IterationMetrics {
+    pub iteration: u64,
+    pub training_step: u64,
+    /// Seconds since the Unix epoch at the end of the iteration.
+    pub timestamp: u64,
+    /// Mean losses of the last 100 parameter updates, as plotted.
+    pub value_loss: f32,
+    pub policy_loss: f32,
+    pub loss: f32,
+    pub learning_rate: f32,
+    /// Mean entropy in nats of the policy of the network, over a batch sampled from the replay buffer after the training.
+    pub policy_entropy: f32,
+    /// Ratio of the decisive positions of the same batch whose value has the sign of the outcome.
+    /// It is empty if the batch has no decisive position.
+    pub value_accuracy: Option<f32>,
+    /// Number of games of the iteration, played by the trainer or received from the self-play workers.
+    pub game_count: usize,
+    /// Mean number of moves of the games of the iteration.
+    pub average_game_length: f32,
+    pub black_win_ratio: f32,
+    pub white_win_ratio: f32,
+    pub draw_ratio: f32,
+    /// Games per second of the self-play, or of waiting for the self-play workers.
+    pub games_per_second: f32,
+    /// Nodes expanded per second by the self-play search. It is not measured without self-play.
+    pub nodes_per_second: Option<f32>,
+    /// Score against the random move player, counting draws as half a win, in the iterations which play it.
+    pub random_score: Option<f32>,
+    /// Score of the new checkpoint against the best one, in the iterations which gate it.
+    pub gating_score: Option<f32>,
+    pub replay_game_count: usize,
+    pub replay_position_count: usize,
+}
+
+impl IterationMetrics {
+    pub const CSV_HEADER: &str = "iteration,training_step,timestamp,value_loss,policy_loss,loss,learning_rate,\
+        policy_entropy,value_accuracy,game_count,average_game_length,black_win_ratio,white_win_ratio,draw_ratio,\
+        games_per_second,nodes_per_second,random_score,gating_score,replay_game_count,replay_position_count";
+
+    /// Records the games of the iteration, which took `seconds` to play or to receive.
+    pub fn record_games<'a>(
+        &mut self,
+        games: impl IntoIterator<Item = &'a GameRecord>,
+        seconds: f32,
+    ) {
+        let mut black_win_count = 0usize;
+        let mut white_win_count = 0usize;
+        let mut move_count = 0usize;
+
+        self.game_count = 0;
+
+        for game in games {
+            match game.status {
+                GameStatus::BlackWin => black_win_count += 1,
+                GameStatus::WhiteWin => white_win_count += 1,
+                _ => {}
+            }
+
+            move_count += game.actions.len();
+            self.game_count += 1;
+        }
+
+        if self.game_count == 0 {
+            return;
+        }
+
+        let game_count = self.game_count as f32;
+        self.average_game_length = move_count as f32 / game_count;
+        self.black_win_ratio = black_win_count as f32 / game_count;
+        self.white_win_ratio = white_win_count as f32 / game_count;
+        self.draw_ratio = (self.game_count - black_win_count - white_win_count) as f32 / game_count;
+        self.games_per_second = game_count / seconds;
+    }
+
+    /// Returns the measured metrics as TensorBoard scalars, tagged by group.
+    pub fn scalars(&self) -> Vec<(&'static str, f32)> {
+        let mut scalars = vec![
+            ("loss/value", self.value_loss),
+            ("loss/policy", self.policy_loss),
+            ("loss/total", self.loss),
+            ("training/learning_rate", self.learning_rate),
+            ("training/policy_entropy", self.policy_entropy),
+            ("selfplay/game_count", self.game_count as f32),
+            ("selfplay/average_game_length", self.average_game_length),
+            ("selfplay/black_win_ratio", self.black_win_ratio),
+            ("selfplay/white_win_ratio", self.white_win_ratio),
+            ("selfplay/draw_ratio", self.draw_ratio),
+            ("selfplay/games_per_second", self.games_per_second),
+            ("replay/game_count", self.replay_game_count as f32),
+            ("replay/position_count", self.replay_position_count as f32),
+        ];
+
+        for (tag, value) in [
+            ("training/value_accuracy", self.value_accuracy),
+            ("selfplay/nodes_per_second", self.nodes_per_second),
+            ("evaluation/random_score", self.random_score),
+            ("evaluation/gating_score", self.gating_score),
+        ] {
+            if let Some(value) = value {
+                scalars.push((tag, value));
+            }
+        }
+
+        scalars
+    }
+
+    /// Formats the metrics as a row under [IterationMetrics::CSV_HEADER], with the empty fields left blank.
+    pub fn to_csv_row(&self) -> String {
+        fn optional(value: Option<f32>) -> String {
+            value.map_or_else(String::new, |value| value.to_string())
+        }
+
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.iteration,
+            self.training_step,
+            self.timestamp,
+            self.value_loss,
+            self.policy_loss,
+            self.loss,
+            self.learning_rate,
+            self.policy_entropy,
+            optional(self.value_accuracy),
+            self.game_count,
+            self.average_game_length,
+            self.black_win_ratio,
+            self.white_win_ratio,
+            self.draw_ratio,
+            self.games_per_second,
+            optional(self.nodes_per_second),
+            optional(self.random_score),
+            optional(self.gating_score),
+            self.replay_game_count,
+            self.replay_position_count,
+        )
+    }
+}
+
+/// Appends the metrics of each iteration to `metrics.jsonl` and `metrics.csv` in a directory, for external tools.
+pub struct MetricsLog {
+    pub path: PathBuf,
+}
+
+impl MetricsLog {
+    pub const JSONL_FILE_NAME: &str = "metrics.jsonl";
+    pub const CSV_FILE_NAME: &str = "metrics.csv";
+
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn append(&self, metrics: &IterationMetrics) -> Result<(), MetricsError> {
+        create_dir_all(&self.path)?;
+
+        let mut jsonl = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path.join(Self::JSONL_FILE_NAME))?;
+        writeln!(jsonl, "{}", serde_json::to_string(metrics)?)?;
+
+        let csv_path = self.path.join(Self::CSV_FILE_NAME);
+        let is_new_csv = !csv_path.exists();
+        let mut csv = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(csv_path)?;
+
+        if is_new_csv {
+            writeln!(csv, "{}", IterationMetrics::CSV_HEADER)?;
+        }
+
+        writeln!(csv, "{}", metrics.to_csv_row())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::read_to_string;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_metrics_log() {
+        let mut black_win = GameRecord::new();
+        black_win.actions = vec![0, 1, 2, 3, 4];
+        black_win.status = GameStatus::BlackWin;
+        let mut draw = GameRecord::new();
+        draw.actions = vec![0, 1, 2];
+        draw.status = GameStatus::Draw;
+
+        let mut metrics = IterationMetrics {
+            iteration: 3,
+            gating_score: Some(0.5),
+            ..Default::default()
+        };
+        metrics.record_games(&[black_win, draw], 4f32);
+        assert_eq!(metrics.game_count, 2);
+        assert_eq!(metrics.average_game_length, 4f32);
+        assert_eq!(metrics.black_win_ratio, 0.5);
+        assert_eq!(metrics.white_win_ratio, 0f32);
+        assert_eq!(metrics.draw_ratio, 0.5);
+        assert_eq!(metrics.games_per_second, 0.5);
+
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+        let log = MetricsLog::new(path);
+        log.append(&metrics).unwrap();
+        log.append(&metrics).unwrap();
+
+        let jsonl = read_to_string(path.join(MetricsLog::JSONL_FILE_NAME)).unwrap();
+        let csv = read_to_string(path.join(MetricsLog::CSV_FILE_NAME)).unwrap();
+
+        let lines = jsonl.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let value = serde_json::from_str::<serde_json::Value>(lines[0]).unwrap();
+        assert_eq!(value["iteration"], 3);
+        assert_eq!(value["gating_score"], 0.5);
+        assert!(value["random_score"].is_null());
+
+        let rows = csv.lines().collect::<Vec<_>>();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], IterationMetrics::CSV_HEADER);
+        assert_eq!(rows[0].split(',').count(), value.as_object().unwrap().len());
+        assert_eq!(rows[1].split(',').count(), rows[0].split(',').count());
+        assert!(rows[1].starts_with("3,"));
+
+        let scalars = metrics.scalars();
+        assert!(scalars.contains(&("evaluation/gating_score", 0.5)));
+        assert!(!scalars
+            .iter()
+            .any(|(tag, _)| *tag == "evaluation/random_score"));
+    }
+}
@@ -1,14 +1,11 @@
 use bincode::serialize_into;
+use image::{codecs::png::PngEncoder, ColorType, ImageEncoder, ImageError};
 use plotters::{
+    coord::Shift,
     prelude::*,
     style::{AsRelative, WHITE},
 };
-use std::{
-    collections::VecDeque, 
-    fs::File, 
-    path::Path, 
-    fs::create_dir_all,
-};
+use std::{collections::VecDeque, fs::create_dir_all, fs::File, path::Path};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -17,6 +14,8 @@ pub enum PlotError {
     IO(#[from] std::io::Error),
     #[error("Bincode error: {0}")]
     Bincode(#[from] bincode::Error),
+    #[error("Image error: {0}")]
+    Image(#[from] ImageError),
 }
 
 pub struct Plotter {
@@ -25,6 +24,8 @@ pub struct Plotter {
 }
 
 impl Plotter {
+    /// Width and height of the drawn plots.
+    pub const PLOT_SIZE: (u32, u32) = (1024, 768);
 
     pub fn new(max_losses: usize) -> Self {
         Self {
@@ -59,14 +60,34 @@ impl Plotter {
     }
 
     pub fn draw_plot(&mut self, path: impl AsRef<Path>) {
-        draw_loss_plot(self.losses.make_contiguous(), path);
+        let root = SVGBackend::new(path.as_ref(), Self::PLOT_SIZE).into_drawing_area();
+        draw_loss_plot(self.losses.make_contiguous(), root);
+    }
+
+    /// Draws the same plot as [Plotter::draw_plot] and returns it encoded in PNG, of [Plotter::PLOT_SIZE] pixels.
+    pub fn draw_png(&mut self) -> Result<Vec<u8>, PlotError> {
+        let (width, height) = Self::PLOT_SIZE;
+        let mut pixels = vec![0u8; width as usize * height as usize * 3];
+        {
+            let root = BitMapBackend::with_buffer(&mut pixels, Self::PLOT_SIZE).into_drawing_area();
+            draw_loss_plot(self.losses.make_contiguous(), root);
+        }
+
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png).write_image(&pixels, width, height, ColorType::Rgb8)?;
+        Ok(png)
     }
 }
 
-fn draw_loss_plot(losses: &[(f32, f32, f32)], path: impl AsRef<Path>) {
-    let root = SVGBackend::new(path.as_ref(), (1024, 768)).into_drawing_area();
+fn draw_loss_plot<DB: DrawingBackend>(losses: &[(f32, f32, f32)], root: DrawingArea<DB, Shift>) {
     root.fill(&WHITE).unwrap();
 
+    // The chart needs a loss to scale to, so nothing is drawn before the first epoch.
+    if losses.is_empty() {
+        root.present().unwrap();
+        return;
+    }
+
     let len = losses.len();
     let max_loss = *losses
         .iter()
@@ -142,3 +163,23 @@ fn draw_loss_plot(losses: &[(f32, f32, f32)], path: impl AsRef<Path>) {
 
     root.present().unwrap();
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    #[test]
+    fn test_draw_png() {
+        let mut plotter = Plotter::new(2);
+        let empty = plotter.draw_png().unwrap();
+        assert!(empty.starts_with(PNG_SIGNATURE));
+
+        plotter.add_loss((0.5, 1.0, 1.5));
+        plotter.add_loss((0.25, 0.5, 0.75));
+        let png = plotter.draw_png().unwrap();
+        assert!(png.starts_with(PNG_SIGNATURE));
+        assert_ne!(png, empty);
+    }
+}
@@ -0,0 +1,497 @@
+use crate::{
+    trainer::Transition,
+    utils::{flip_horizontal, flip_vertical, rotate_180, rotate_270, rotate_90},
+};
+use bincode::{deserialize, serialize_into};
+use environment::{Environment, GameStatus, Stone, Turn};
+use rand::{seq::index, Rng};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashSet, VecDeque},
+    fs::{create_dir_all, hard_link, read, read_dir, remove_file, File},
+    io::{BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    process,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ReplayBufferError {
+    #[error("io error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("bincode error: {0}")]
+    Bincode(#[from] bincode::Error),
+    #[error("unsupported replay chunk format version {found}; the latest supported version is {supported}")]
+    UnsupportedFormatVersion { found: u32, supported: u32 },
+    #[error("invalid replay chunk {0}")]
+    InvalidChunk(PathBuf),
+}
+
+/// A finished self-play game. Its positions and their targets are reconstructed from the actions when sampled.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GameRecord {
+    /// Actions played from the empty board, in order.
+    pub actions: Vec<u16>,
+    /// Search policy of the position before each action, as the actions with a nonzero probability.
+    pub policies: Vec<Vec<(u16, f32)>>,
+    /// Status after the last action.
+    pub status: GameStatus,
+}
+
+impl GameRecord {
+    pub fn new() -> Self {
+        Self {
+            actions: Vec::with_capacity(64),
+            policies: Vec::with_capacity(64),
+            status: GameStatus::InProgress,
+        }
+    }
+
+    /// Records an action and the search policy of the position it has been played from.
+    pub fn push(
+        &mut self,
+        action: usize,
+        policy: &[f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE],
+    ) {
+        self.actions.push(action as u16);
+        self.policies.push(sparse_policy(policy));
+    }
+
+    /// Number of positions of the game, including the final one.
+    pub fn position_count(&self) -> usize {
+        self.actions.len() + 1
+    }
+
+    /// Reconstructs the position before the action at `index` and its targets.
+    /// The final position, at `index == actions.len()`, is in the perspective of the loser and has a uniform policy.
+    pub fn transition(&self, index: usize) -> Transition {
+        let mut env = Environment::new();
+        let mut position = None;
+
+        for (action_index, &action) in self.actions.iter().enumerate() {
+            if action_index == index {
+                position = Some(env.clone());
+            }
+
+            env.place_stone(action as usize);
+        }
+
+        let final_env = env;
+        let env = position.unwrap_or_else(|| final_env.clone());
+
+        let mut transition = Transition {
+            policy: [0f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE],
+            reply_policy: [0f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE],
+            ownership: [0f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE],
+            z: 0f32,
+            env,
+        };
+
+        match self.policies.get(index) {
+            Some(policy) => {
+                for &(action, probability) in policy {
+                    transition.policy[action as usize] = probability;
+                }
+            }
+            None => {
+                transition.policy = [1f32
+                    / (Environment::BOARD_SIZE * Environment::BOARD_SIZE) as f32;
+                    Environment::BOARD_SIZE * Environment::BOARD_SIZE];
+            }
+        }
+
+        if let Some(&reply) = self.actions.get(index + 1) {
+            transition.reply_policy[reply as usize] = 1f32;
+        }
+
+        let winner = match self.status {
+            GameStatus::BlackWin => Stone::Black,
+            GameStatus::WhiteWin => Stone::White,
+            GameStatus::InProgress | GameStatus::Draw => return transition,
+        };
+        let stone = match transition.env.turn {
+            Turn::Black => Stone::Black,
+            Turn::White => Stone::White,
+        };
+        let owner = if stone == winner { 1f32 } else { -1f32 };
+
+        transition.z = owner;
+
+        if let Some(&last_action) = self.actions.last() {
+            for index in final_env.winning_line(last_action as usize) {
+                transition.ownership[index] = owner;
+            }
+        }
+
+        transition
+    }
+}
+
+/// Keeps the actions of `policy` with a nonzero probability.
+fn sparse_policy(policy: &[f32]) -> Vec<(u16, f32)> {
+    policy
+        .iter()
+        .enumerate()
+        .filter(|(_, &probability)| probability != 0f32)
+        .map(|(action, &probability)| (action as u16, probability))
+        .collect()
+}
+
+/// Self-play games used for training, within a sliding window over the most recent positions.
+///
+/// Every call to [ReplayBuffer::append] writes its games to a new chunk file in the buffer directory, which is never modified afterwards.
+/// Opening the buffer reads the most recent chunks back, so the window survives restarts of the training.
+/// Several processes can append to the same directory, and [ReplayBuffer::refresh] reads the chunks written by the others.
+pub struct ReplayBuffer {
+    pub path: PathBuf,
+    /// Number of positions in the window. The oldest games are dropped while the others still fill it.
+    /// If it is 0, the buffer only writes chunks, as the self-play workers do.
+    pub capacity: usize,
+    pub games: VecDeque<GameRecord>,
+    /// Number of positions of `games`.
+    pub position_count: usize,
+    /// Chunks which have been read or written by this buffer, or were outside of the window when it has been opened.
+    known_chunk_indices: HashSet<u64>,
+    /// Newest chunk whose games have been added to the window.
+    pub newest_chunk_index: Option<u64>,
+}
+
+impl ReplayBuffer {
+    pub const CHUNK_FORMAT_VERSION: u32 = 1;
+    pub const CHUNK_EXTENSION: &str = "chunk";
+
+    /// Opens the buffer stored in `path`, creating the directory if needed, and loads the most recent chunks filling the window.
+    /// If `last_chunk_index` is given, the window ends at that chunk, as it was when a resumed training stopped;
+    /// the later chunks are read by [ReplayBuffer::refresh].
+    pub fn open(
+        path: impl AsRef<Path>,
+        capacity: usize,
+        last_chunk_index: Option<u64>,
+    ) -> Result<Self, ReplayBufferError> {
+        let path = path.as_ref().to_path_buf();
+        create_dir_all(&path)?;
+
+        let last_chunk_index = last_chunk_index.unwrap_or(u64::MAX);
+        let chunk_indices = Self::chunk_indices(&path)?
+            .into_iter()
+            .filter(|&index| index <= last_chunk_index)
+            .collect::<Vec<_>>();
+        let mut chunks = Vec::new();
+        let mut position_count = 0;
+
+        for &index in chunk_indices.iter().rev() {
+            if capacity <= position_count {
+                break;
+            }
+
+            let games = Self::read_chunk(&Self::chunk_path(&path, index))?;
+            position_count += games.iter().map(GameRecord::position_count).sum::<usize>();
+            chunks.push(games);
+        }
+
+        let newest_chunk_index = chunk_indices.last().copied();
+
+        let mut this = Self {
+            path,
+            capacity,
+            games: VecDeque::new(),
+            position_count: 0,
+            known_chunk_indices: chunk_indices.into_iter().collect(),
+            newest_chunk_index,
+        };
+
+        for games in chunks.into_iter().rev() {
+            this.push_games(games);
+        }
+
+        Ok(this)
+    }
+
+    /// Writes the finished games as a new chunk and adds them to the window.
+    pub fn append(&mut self, games: Vec<GameRecord>) -> Result<(), ReplayBufferError> {
+        let chunk = ReplayChunk {
+            format_version: Self::CHUNK_FORMAT_VERSION,
+            games,
+        };
+
+        // Write to a temporary file first, so that an interrupted write never leaves a truncated chunk behind.
+        let temporary_path = self.path.join(format!("{}.tmp", process::id()));
+        let mut writer = BufWriter::new(File::create(&temporary_path)?);
+        serialize_into(&mut writer, &chunk)?;
+        writer.flush()?;
+        drop(writer);
+
+        // Link the chunk rather than renaming it, since linking fails instead of replacing
+        // a chunk which another process has written in the meantime.
+        let mut index = Self::chunk_indices(&self.path)?
+            .last()
+            .map_or(0, |&index| index + 1);
+
+        loop {
+            match hard_link(&temporary_path, Self::chunk_path(&self.path, index)) {
+                Ok(()) => break,
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => index += 1,
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        remove_file(&temporary_path)?;
+        self.known_chunk_indices.insert(index);
+        self.newest_chunk_index = self.newest_chunk_index.max(Some(index));
+        self.push_games(chunk.games);
+
+        Ok(())
+    }
+
+    /// Adds the chunks written by other processes since the buffer has been opened or refreshed, returning the number of their games.
+    pub fn refresh(&mut self) -> Result<usize, ReplayBufferError> {
+        let mut game_count = 0;
+
+        for index in Self::chunk_indices(&self.path)? {
+            if self.known_chunk_indices.insert(index) {
+                let games = Self::read_chunk(&Self::chunk_path(&self.path, index))?;
+                game_count += games.len();
+                self.newest_chunk_index = self.newest_chunk_index.max(Some(index));
+                self.push_games(games);
+            }
+        }
+
+        Ok(game_count)
+    }
+
+    /// Samples `count` distinct positions uniformly from the window, each under a random symmetry of the board:
+    /// itself, 3 rotations or 2 flips.
+    pub fn sample(&self, rng: &mut impl Rng, count: usize) -> Vec<Transition> {
+        let mut indices =
+            index::sample(rng, self.position_count, count.min(self.position_count)).into_vec();
+        indices.sort_unstable();
+
+        let mut transitions = Vec::with_capacity(indices.len());
+        let mut games = self.games.iter();
+        let mut game = games.next();
+        let mut first_index = 0;
+
+        for index in indices {
+            while let Some(record) = game {
+                if index < first_index + record.position_count() {
+                    break;
+                }
+
+                first_index += record.position_count();
+                game = games.next();
+            }
+
+            let transition = game.unwrap().transition(index - first_index);
+
+            transitions.push(match rng.gen_range(0..6) {
+                0 => transition,
+                1 => transition.transformed(|src, dst, size| rotate_90(src, dst, size)),
+                2 => transition.transformed(|src, dst, size| rotate_180(src, dst, size)),
+                3 => transition.transformed(|src, dst, size| rotate_270(src, dst, size)),
+                4 => transition.transformed(|src, dst, size| flip_horizontal(src, dst, size)),
+                _ => transition.transformed(|src, dst, size| flip_vertical(src, dst, size)),
+            });
+        }
+
+        transitions
+    }
+
+    /// Adds the games to the window, dropping the oldest ones that are no longer needed to fill it.
+    fn push_games(&mut self, games: Vec<GameRecord>) {
+        for game in games {
+            self.position_count += game.position_count();
+            self.games.push_back(game);
+        }
+
+        while let Some(game) = self.games.front() {
+            if self.position_count - game.position_count() < self.capacity {
+                break;
+            }
+
+            self.position_count -= game.position_count();
+            self.games.pop_front();
+        }
+    }
+
+    fn chunk_path(path: &Path, index: u64) -> PathBuf {
+        path.join(format!("{:08}", index))
+            .with_extension(Self::CHUNK_EXTENSION)
+    }
+
+    /// Returns the indices of the chunks in `path`, in the order they have been written.
+    fn chunk_indices(path: &Path) -> Result<Vec<u64>, ReplayBufferError> {
+        let mut indices = Vec::new();
+
+        for entry in read_dir(path)? {
+            let entry_path = entry?.path();
+
+            if entry_path
+                .extension()
+                .and_then(|extension| extension.to_str())
+                != Some(Self::CHUNK_EXTENSION)
+            {
+                continue;
+            }
+
+            let index = entry_path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+                .ok_or_else(|| ReplayBufferError::InvalidChunk(entry_path.clone()))?;
+            indices.push(index);
+        }
+
+        indices.sort_unstable();
+
+        Ok(indices)
+    }
+
+    fn read_chunk(path: &Path) -> Result<Vec<GameRecord>, ReplayBufferError> {
+        let bytes = read(path)?;
+        let format_version: u32 = deserialize(&bytes)?;
+
+        if format_version != Self::CHUNK_FORMAT_VERSION {
+            return Err(ReplayBufferError::UnsupportedFormatVersion {
+                found: format_version,
+                supported: Self::CHUNK_FORMAT_VERSION,
+            });
+        }
+
+        let chunk: ReplayChunk = deserialize(&bytes)?;
+        Ok(chunk.games)
+    }
+}
+
+/// The games written by one call to [ReplayBuffer::append].
+#[derive(Serialize, Deserialize)]
+struct ReplayChunk {
+    format_version: u32,
+    games: Vec<GameRecord>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use tempfile::tempdir;
+
+    /// Plays a game in which black wins along the first row, with most of the policy on the played action.
+    fn play_game() -> GameRecord {
+        let mut env = Environment::new();
+        let mut game = GameRecord::new();
+
+        for action in [0, 15, 1, 16, 2, 17, 3, 18, 4] {
+            let mut policy = [0f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE];
+            policy[action] = 0.75;
+            policy[224] = 0.25;
+            game.push(action, &policy);
+            game.status = env.place_stone(action).unwrap();
+        }
+
+        game
+    }
+
+    #[test]
+    fn test_game_record_transition() {
+        let game = play_game();
+        assert_eq!(game.status, GameStatus::BlackWin);
+        assert_eq!(game.position_count(), 10);
+
+        // Black to move, before its second stone.
+        let transition = game.transition(2);
+        assert_eq!(transition.env.turn, Turn::Black);
+        assert_eq!(transition.env.move_count(), 2);
+        assert_eq!(transition.env.board[0], Stone::Black);
+        assert_eq!(transition.env.board[15], Stone::White);
+        assert_eq!(transition.policy[1], 0.75);
+        assert_eq!(transition.policy[224], 0.25);
+        assert_eq!(transition.reply_policy[16], 1f32);
+        assert_eq!(transition.z, 1f32);
+        assert_eq!(transition.ownership[0..5], [1f32; 5]);
+
+        // The final position is in the perspective of the loser.
+        let transition = game.transition(9);
+        assert_eq!(transition.env.turn, Turn::White);
+        assert_eq!(transition.env.move_count(), 9);
+        assert!(transition.reply_policy.iter().all(|&p| p == 0f32));
+        assert_eq!(transition.z, -1f32);
+        assert_eq!(transition.ownership[0..5], [-1f32; 5]);
+    }
+
+    #[test]
+    fn test_replay_buffer_persistence() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("replays");
+        let game_size = play_game().position_count();
+
+        {
+            let mut buffer = ReplayBuffer::open(&path, game_size * 2, None).unwrap();
+            assert!(buffer.games.is_empty());
+
+            buffer.append(vec![play_game()]).unwrap();
+            buffer.append(vec![play_game(), play_game()]).unwrap();
+            assert_eq!(buffer.games.len(), 2);
+            assert_eq!(buffer.position_count, game_size * 2);
+        }
+
+        // Only the chunks within the window are loaded, and new chunks follow the existing ones.
+        let mut buffer = ReplayBuffer::open(&path, game_size, None).unwrap();
+        assert_eq!(buffer.games, [play_game()]);
+        assert_eq!(buffer.known_chunk_indices.len(), 2);
+        assert_eq!(buffer.newest_chunk_index, Some(1));
+
+        let transitions = buffer.sample(&mut StdRng::seed_from_u64(0), 4);
+        assert_eq!(transitions.len(), 4);
+        assert!(transitions
+            .iter()
+            .all(|transition| (transition.policy.iter().sum::<f32>() - 1f32).abs() < 1e-4));
+
+        let mut other_buffer = ReplayBuffer::open(&path, game_size, None).unwrap();
+        assert_eq!(other_buffer.games, [play_game()]);
+        other_buffer.append(vec![play_game()]).unwrap();
+
+        // Chunks written by other buffers are added on refresh, and appending never replaces them.
+        assert_eq!(buffer.refresh().unwrap(), 1);
+        other_buffer.append(vec![play_game(), play_game()]).unwrap();
+        buffer.append(vec![play_game()]).unwrap();
+        assert_eq!(buffer.refresh().unwrap(), 2);
+        assert_eq!(buffer.refresh().unwrap(), 0);
+        assert_eq!(buffer.newest_chunk_index, Some(4));
+        assert_eq!(
+            ReplayBuffer::chunk_indices(&path).unwrap(),
+            vec![0, 1, 2, 3, 4]
+        );
+
+        // A resumed buffer holds the window it had, and reads the later chunks on refresh.
+        let mut resumed_buffer = ReplayBuffer::open(&path, game_size * 2, Some(1)).unwrap();
+        assert_eq!(resumed_buffer.games.len(), 2);
+        assert_eq!(resumed_buffer.newest_chunk_index, Some(1));
+        assert_eq!(resumed_buffer.refresh().unwrap(), 4);
+    }
+
+    #[test]
+    fn test_resume_after_interrupted_iteration() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("replays");
+        let game_size = play_game().position_count();
+
+        // The state is saved after the first iteration, and the second one is interrupted after writing its games.
+        let mut buffer = ReplayBuffer::open(&path, game_size * 10, None).unwrap();
+        buffer.append(vec![play_game()]).unwrap();
+        let replay_chunk = buffer.newest_chunk_index;
+        buffer.append(vec![play_game(), play_game()]).unwrap();
+        drop(buffer);
+
+        // The resumed trainer refreshes before playing, so the interrupted games are not skipped behind the new chunk.
+        let mut buffer = ReplayBuffer::open(&path, game_size * 10, replay_chunk).unwrap();
+        assert_eq!(buffer.games.len(), 1);
+        assert_eq!(buffer.refresh().unwrap(), 2);
+        buffer.append(vec![play_game()]).unwrap();
+        assert_eq!(buffer.games.len(), 4);
+        assert_eq!(buffer.newest_chunk_index, Some(2));
+
+        // The next resume from this state finds the same window.
+        let buffer = ReplayBuffer::open(&path, game_size * 10, Some(2)).unwrap();
+        assert_eq!(buffer.games.len(), 4);
+    }
+}
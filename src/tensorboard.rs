@@ -0,0 +1,349 @@
+use std::{
+    fs::{create_dir_all, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum TensorBoardError {
+    #[error("io error: {0}")]
+    IO(#[from] std::io::Error),
+}
+
+/// Writes the summaries of a run into a TensorBoard event file, in the TFRecord format.
+///
+/// The `Event` and `Summary` protocol buffers are encoded by hand, as only a few of their fields are written.
+pub struct EventWriter {
+    pub path: PathBuf,
+    file: BufWriter<File>,
+}
+
+impl EventWriter {
+    /// Number of buckets of the histograms, which span the range of the values evenly.
+    pub const HISTOGRAM_BUCKET_COUNT: usize = 30;
+
+    /// Creates a new event file in the directory `path`. Each run writes its own file, which TensorBoard merges.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, TensorBoardError> {
+        create_dir_all(&path)?;
+
+        let path = path.as_ref().join(format!(
+            "events.out.tfevents.{}.omok-ai.{}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            process::id()
+        ));
+        let mut this = Self {
+            file: BufWriter::new(File::create(&path)?),
+            path,
+        };
+
+        let mut event = event_header(0);
+        write_bytes_field(&mut event, 3, b"brain.Event:2");
+        this.write_record(&event)?;
+        this.flush()?;
+
+        Ok(this)
+    }
+
+    pub fn add_scalar(&mut self, tag: &str, value: f32, step: u64) -> Result<(), TensorBoardError> {
+        let mut summary_value = Vec::new();
+        write_bytes_field(&mut summary_value, 1, tag.as_bytes());
+        write_key(&mut summary_value, 2, WIRE_TYPE_FIXED32);
+        summary_value.extend(value.to_le_bytes());
+
+        self.write_summary(&summary_value, step)
+    }
+
+    /// Adds a histogram of the values. Nothing is written if there are none.
+    pub fn add_histogram(
+        &mut self,
+        tag: &str,
+        values: &[f32],
+        step: u64,
+    ) -> Result<(), TensorBoardError> {
+        if values.is_empty() {
+            return Ok(());
+        }
+
+        let min = values.iter().copied().fold(f32::INFINITY, f32::min) as f64;
+        let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max) as f64;
+        let bucket_count = if min < max {
+            Self::HISTOGRAM_BUCKET_COUNT
+        } else {
+            1
+        };
+        let bucket_width = (max - min) / bucket_count as f64;
+
+        // The limits are the right edges of the buckets; the last one is the maximum itself.
+        let bucket_limits = (1..=bucket_count)
+            .map(|index| {
+                if index == bucket_count {
+                    max
+                } else {
+                    min + bucket_width * index as f64
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut buckets = vec![0f64; bucket_count];
+
+        for &value in values {
+            let index = if bucket_width == 0f64 {
+                0
+            } else {
+                ((value as f64 - min) / bucket_width) as usize
+            };
+            buckets[index.min(bucket_count - 1)] += 1f64;
+        }
+
+        let mut histogram = Vec::new();
+        write_double_field(&mut histogram, 1, min);
+        write_double_field(&mut histogram, 2, max);
+        write_double_field(&mut histogram, 3, values.len() as f64);
+        write_double_field(
+            &mut histogram,
+            4,
+            values.iter().map(|&value| value as f64).sum(),
+        );
+        write_double_field(
+            &mut histogram,
+            5,
+            values
+                .iter()
+                .map(|&value| value as f64 * value as f64)
+                .sum(),
+        );
+        write_packed_doubles_field(&mut histogram, 6, &bucket_limits);
+        write_packed_doubles_field(&mut histogram, 7, &buckets);
+
+        let mut summary_value = Vec::new();
+        write_bytes_field(&mut summary_value, 1, tag.as_bytes());
+        write_bytes_field(&mut summary_value, 5, &histogram);
+
+        self.write_summary(&summary_value, step)
+    }
+
+    /// Adds an image encoded in PNG, of the given size in pixels.
+    pub fn add_image(
+        &mut self,
+        tag: &str,
+        png: &[u8],
+        width: u32,
+        height: u32,
+        step: u64,
+    ) -> Result<(), TensorBoardError> {
+        let mut image = Vec::new();
+        write_varint_field(&mut image, 1, height as u64);
+        write_varint_field(&mut image, 2, width as u64);
+        // RGB.
+        write_varint_field(&mut image, 3, 3);
+        write_bytes_field(&mut image, 4, png);
+
+        let mut summary_value = Vec::new();
+        write_bytes_field(&mut summary_value, 1, tag.as_bytes());
+        write_bytes_field(&mut summary_value, 4, &image);
+
+        self.write_summary(&summary_value, step)
+    }
+
+    pub fn flush(&mut self) -> Result<(), TensorBoardError> {
+        self.file.flush()?;
+        Ok(())
+    }
+
+    fn write_summary(&mut self, summary_value: &[u8], step: u64) -> Result<(), TensorBoardError> {
+        let mut summary = Vec::new();
+        write_bytes_field(&mut summary, 1, summary_value);
+
+        let mut event = event_header(step);
+        write_bytes_field(&mut event, 5, &summary);
+        self.write_record(&event)
+    }
+
+    /// Writes a TFRecord: the length, its masked CRC, the data and its masked CRC.
+    fn write_record(&mut self, data: &[u8]) -> Result<(), TensorBoardError> {
+        let length = (data.len() as u64).to_le_bytes();
+        self.file.write_all(&length)?;
+        self.file.write_all(&masked_crc32c(&length).to_le_bytes())?;
+        self.file.write_all(data)?;
+        self.file.write_all(&masked_crc32c(data).to_le_bytes())?;
+        Ok(())
+    }
+}
+
+const WIRE_TYPE_VARINT: u8 = 0;
+const WIRE_TYPE_FIXED64: u8 = 1;
+const WIRE_TYPE_LENGTH_DELIMITED: u8 = 2;
+const WIRE_TYPE_FIXED32: u8 = 5;
+
+/// Encodes the `wall_time` and `step` fields of an `Event`.
+fn event_header(step: u64) -> Vec<u8> {
+    let mut event = Vec::new();
+    write_double_field(
+        &mut event,
+        1,
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64(),
+    );
+    write_varint_field(&mut event, 2, step);
+    event
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while 0x80 <= value {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    buffer.push(value as u8);
+}
+
+fn write_key(buffer: &mut Vec<u8>, field: u32, wire_type: u8) {
+    write_varint(buffer, (field as u64) << 3 | wire_type as u64);
+}
+
+fn write_varint_field(buffer: &mut Vec<u8>, field: u32, value: u64) {
+    write_key(buffer, field, WIRE_TYPE_VARINT);
+    write_varint(buffer, value);
+}
+
+fn write_double_field(buffer: &mut Vec<u8>, field: u32, value: f64) {
+    write_key(buffer, field, WIRE_TYPE_FIXED64);
+    buffer.extend(value.to_le_bytes());
+}
+
+fn write_bytes_field(buffer: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_key(buffer, field, WIRE_TYPE_LENGTH_DELIMITED);
+    write_varint(buffer, bytes.len() as u64);
+    buffer.extend(bytes);
+}
+
+fn write_packed_doubles_field(buffer: &mut Vec<u8>, field: u32, values: &[f64]) {
+    write_key(buffer, field, WIRE_TYPE_LENGTH_DELIMITED);
+    write_varint(buffer, (values.len() * 8) as u64);
+
+    for value in values {
+        buffer.extend(value.to_le_bytes());
+    }
+}
+
+/// Table of the reflected CRC-32C (Castagnoli) polynomial, which TFRecord uses.
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                crc >> 1 ^ 0x82f63b78
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[index] = crc;
+        index += 1;
+    }
+
+    table
+};
+
+fn crc32c(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        CRC32C_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ crc >> 8
+    })
+}
+
+fn masked_crc32c(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    crc.rotate_right(15).wrapping_add(0xa282ead8)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{read, read_dir};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_crc32c() {
+        assert_eq!(crc32c(b"123456789"), 0xe3069283);
+        assert_eq!(crc32c(&[0u8; 32]), 0x8a9136aa);
+    }
+
+    #[test]
+    fn test_event_writer() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+        let mut writer = EventWriter::create(path).unwrap();
+        writer.add_scalar("loss/total", 0.5, 3).unwrap();
+        writer
+            .add_histogram("weights/w", &[-1f32, 0f32, 1f32, 1f32], 3)
+            .unwrap();
+        writer.add_histogram("weights/empty", &[], 3).unwrap();
+        writer.add_image("loss_plot", &[1, 2, 3], 2, 1, 3).unwrap();
+        writer.flush().unwrap();
+
+        assert_eq!(read_dir(path).unwrap().count(), 1);
+        assert!(writer
+            .path
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("events.out.tfevents."));
+
+        let bytes = read(&writer.path).unwrap();
+
+        let mut records = Vec::new();
+        let mut rest = &bytes[..];
+
+        while !rest.is_empty() {
+            let length = u64::from_le_bytes(rest[..8].try_into().unwrap()) as usize;
+            assert_eq!(
+                u32::from_le_bytes(rest[8..12].try_into().unwrap()),
+                masked_crc32c(&rest[..8])
+            );
+
+            let data = &rest[12..12 + length];
+            assert_eq!(
+                u32::from_le_bytes(rest[12 + length..16 + length].try_into().unwrap()),
+                masked_crc32c(data)
+            );
+
+            records.push(data);
+            rest = &rest[16 + length..];
+        }
+
+        // The file version, the scalar, the histogram and the image.
+        assert_eq!(records.len(), 4);
+
+        let contains =
+            |record: &[u8], bytes: &[u8]| record.windows(bytes.len()).any(|window| window == bytes);
+        assert!(contains(records[0], b"brain.Event:2"));
+        assert!(contains(records[1], b"loss/total"));
+        assert!(contains(records[1], &0.5f32.to_le_bytes()));
+        assert!(contains(records[2], b"weights/w"));
+        // The last bucket holds both ones.
+        assert!(contains(records[2], &2f64.to_le_bytes()));
+        assert!(contains(records[3], &[0x22, 3, 1, 2, 3]));
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut buffer = Vec::new();
+        write_varint(&mut buffer, 1);
+        write_varint(&mut buffer, 300);
+        assert_eq!(buffer, [0x01, 0xac, 0x02]);
+    }
+}
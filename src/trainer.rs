@@ -1,43 +1,224 @@
 use crate::{
-    config::Config,
-    plot::Plotter,
-    utils::{flip_horizontal, flip_vertical, rotate_180, rotate_270, rotate_90},
+    config::{Config, ConfigError},
+    metrics::{IterationMetrics, MetricsError, MetricsLog},
+    plot::{PlotError, Plotter},
+    replay_buffer::{GameRecord, ReplayBuffer, ReplayBufferError},
+    tensorboard::{EventWriter, TensorBoardError},
+    trainer_state::{TrainerState, TrainerStateError},
 };
 use alpha_zero::{
-    encode_nn_input, encode_nn_targets, ActionSamplingMode, Agent, AgentModel, EnvTurnMode,
-    ParallelMCTSExecutor,
+    encode_nn_board_targets, encode_nn_input, encode_nn_targets, ActionSamplingMode, Agent,
+    AgentModel, CheckpointStore, EnvTurnMode, InferenceError, ModelIOError, ParallelMCTSExecutor,
+    TensorflowBackend,
 };
+use benchmark::{play_match, MatchResult};
 use environment::{Environment, GameStatus, Stone};
-use rand::{seq::IteratorRandom, thread_rng, Rng};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::{
     collections::VecDeque,
-    fs::{create_dir_all, remove_dir_all, remove_file},
+    fs::{create_dir_all, OpenOptions},
     io::Write,
     path::Path,
+    thread::sleep,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use tensorflow::{Scope, Session, SessionOptions, SessionRunArgs, Status};
+use tensorflow::{Scope, Session, SessionOptions, SessionRunArgs, Status, Tensor};
+use thiserror::Error;
 
 pub struct Transition {
     pub env: Environment,
     pub policy: [f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE],
+    /// One-hot target of the reply policy head: the opponent's answer to the move played here.
+    /// It is all zeros if the game ended before the reply.
+    pub reply_policy: [f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE],
+    /// Target of the ownership head: the stones of the winning line, which are 1 if they belong to the player
+    /// to move and -1 otherwise. It is all zeros for a draw.
+    pub ownership: [f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE],
     pub z: f32,
 }
 
+impl Transition {
+    /// Returns the transition with the board and all targets transformed by `transform`,
+    /// which is one of the symmetries in [crate::utils].
+    pub fn transformed(&self, transform: impl Fn(&[usize], &mut [usize], usize)) -> Self {
+        let mut identity = [0; Environment::BOARD_SIZE * Environment::BOARD_SIZE];
+
+        for (index, source) in identity.iter_mut().enumerate() {
+            *source = index;
+        }
+
+        let mut sources = [0; Environment::BOARD_SIZE * Environment::BOARD_SIZE];
+        transform(&identity, &mut sources, Environment::BOARD_SIZE);
+
+        let mut transition = Self {
+            env: self.env.clone(),
+            policy: self.policy,
+            reply_policy: self.reply_policy,
+            ownership: self.ownership,
+            z: self.z,
+        };
+
+        for (index, &source) in sources.iter().enumerate() {
+            transition.env.board[index] = self.env.board[source];
+            transition.env.move_numbers[index] = self.env.move_numbers[source];
+            transition.policy[index] = self.policy[source];
+            transition.reply_policy[index] = self.reply_policy[source];
+            transition.ownership[index] = self.ownership[source];
+        }
+
+        transition
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum TrainerError {
+    #[error("tensorflow error: {0}")]
+    Tensorflow(#[from] Status),
+    #[error("inference error: {0}")]
+    Inference(#[from] InferenceError),
+    #[error("{0}")]
+    Config(#[from] ConfigError),
+    #[error("trainer state error: {0}")]
+    TrainerState(#[from] TrainerStateError),
+    #[error("model io error: {0}")]
+    ModelIO(#[from] ModelIOError),
+    #[error("replay buffer error: {0}")]
+    ReplayBuffer(#[from] ReplayBufferError),
+    #[error("metrics error: {0}")]
+    Metrics(#[from] MetricsError),
+    #[error("tensorboard error: {0}")]
+    TensorBoard(#[from] TensorBoardError),
+    #[error("plot error: {0}")]
+    Plot(#[from] PlotError),
+    #[error("io error: {0}")]
+    IO(#[from] std::io::Error),
+}
+
 pub struct Trainer {
     pub session: Session,
+    /// Session of the same graph holding the best saved model, which plays the self-play games while `session` is trained.
+    pub self_play_session: Session,
+    /// Checkpoint loaded into `self_play_session`.
+    pub self_play_checkpoint: Option<u64>,
     pub agent_model: AgentModel,
     pub plotter: Plotter,
-    pub replay_memory: VecDeque<Transition>,
+    pub replay_buffer: ReplayBuffer,
+    /// Numbered checkpoints in `saves/{model_name}`. The self-play workers, the benchmark and the GUI use the best one.
+    pub checkpoints: CheckpointStore,
     pub config: Config,
+    pub rng: StdRng,
+    /// Seed of the run, from which the generator is reseeded at the start of each iteration.
+    pub seed: u64,
+    /// Number of iterations finished over all resumed runs.
+    pub iteration: u64,
+    /// Number of parameter updates performed on the model, stored in the checkpoints.
+    pub training_step: u64,
+    /// Metrics of each iteration, written to `plots` next to the loss plot.
+    pub metrics_log: MetricsLog,
+    /// Event file of the run, if TensorBoard is enabled. It is created when the training starts.
+    pub tensorboard: Option<EventWriter>,
 }
 
 impl Trainer {
-    pub fn new(config_name: &str) -> Result<Self, Status> {
-        let config = Config::new(config_name);
+    /// Interval at which the trainer checks for new games when it does not play them itself.
+    pub const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+    /// Creates the trainer with the given config, and loads the latest checkpoint.
+    /// If `resume` is set, it restores the [TrainerState] of the previous run instead, including its config
+    /// with `overrides` applied like [Config::load] does.
+    pub fn new(
+        mut config: Config,
+        resume: bool,
+        overrides: &[String],
+    ) -> Result<Self, TrainerError> {
+        let checkpoints = CheckpointStore::open(Path::new("saves").join(&config.model_name))?;
+        let state = if resume {
+            TrainerState::load(&checkpoints.path)?
+        } else {
+            None
+        };
+
+        match &state {
+            Some(state) => {
+                let resumed_config = state.config.with_overrides(overrides)?;
+
+                if resumed_config != config {
+                    println!("The config file has changed since the run started; resuming with the config of the run.");
+                }
+
+                config = resumed_config;
+            }
+            None if resume => println!("No trainer state to resume from; starting a new run."),
+            None => {}
+        }
+
+        let seed = match &state {
+            Some(state) => state.seed,
+            None => config.seed.unwrap_or_else(|| StdRng::from_entropy().gen()),
+        };
+        let replay_buffer = ReplayBuffer::open(
+            Path::new("replays").join(&config.model_name),
+            config.training.replay_memory_size,
+            state.as_ref().and_then(|state| state.replay_chunk),
+        )?;
+        let mut this = Self::with_parts(config, checkpoints, replay_buffer, seed)?;
+
+        match state {
+            Some(state) => {
+                // The checkpoint of the state, rather than the latest one, which may have been saved by an unfinished iteration.
+                if let Some(checkpoint_iteration) = state.checkpoint {
+                    this.load_checkpoint(checkpoint_iteration)?;
+                }
+
+                this.iteration = state.iteration;
+                this.training_step = state.training_step;
 
+                println!(
+                    "Resuming the training. [iter={}, step={}, checkpoint={:?}]",
+                    this.iteration, this.training_step, state.checkpoint
+                );
+            }
+            // Load the parameters if it exists.
+            None => this.load()?,
+        }
+
+        Ok(this)
+    }
+
+    /// Creates the trainer of a [Trainer::self_play_worker] process. The worker only appends games to the replay buffer,
+    /// so it does not read the window. `worker_id` is mixed into the seed by [worker_seed],
+    /// so that the workers of a seeded run play different games from each other and from the trainer.
+    pub fn new_self_play_worker(config: Config, worker_id: u64) -> Result<Self, TrainerError> {
+        let checkpoints = CheckpointStore::open(Path::new("saves").join(&config.model_name))?;
+        let seed = worker_seed(
+            config.seed.unwrap_or_else(|| StdRng::from_entropy().gen()),
+            worker_id,
+        );
+        let replay_buffer =
+            ReplayBuffer::open(Path::new("replays").join(&config.model_name), 0, None)?;
+
+        Self::with_parts(config, checkpoints, replay_buffer, seed)
+    }
+
+    /// Builds the model and its session, with freshly initialized variables.
+    fn with_parts(
+        config: Config,
+        checkpoints: CheckpointStore,
+        replay_buffer: ReplayBuffer,
+        seed: u64,
+    ) -> Result<Self, TrainerError> {
         let mut scope = Scope::new_root_scope();
-        let agent = AgentModel::new(&mut scope)?;
-        let session = Session::new(&SessionOptions::new(), &scope.graph())?;
+        let agent = AgentModel::new(config.network, config.training.optimizer, &mut scope)?;
+
+        let mut session_options = SessionOptions::new();
+
+        if config.deterministic {
+            // Serialized ConfigProto of `intra_op_parallelism_threads: 1, inter_op_parallelism_threads: 1`.
+            session_options.set_config(&[0x10, 0x01, 0x28, 0x01])?;
+        }
+
+        let session = Session::new(&session_options, &scope.graph())?;
+        let self_play_session = Session::new(&session_options, &scope.graph())?;
 
         let mut init_run_args = SessionRunArgs::new();
 
@@ -46,273 +227,159 @@ impl Trainer {
         }
 
         session.run(&mut init_run_args)?;
+        self_play_session.run(&mut init_run_args)?;
 
-        let mut plotter = Plotter::new(config.parameters.max_losses);
+        let mut plotter = Plotter::new(config.training.max_losses);
         if Path::new("plots").join("losses").exists() {
-            plotter.load("plots/losses").unwrap();
+            plotter.load("plots/losses")?;
         }
 
-        let this = Self {
+        Ok(Self {
             session,
+            self_play_session,
+            self_play_checkpoint: None,
             agent_model: agent,
             plotter,
-            replay_memory: VecDeque::with_capacity(config.parameters.replay_memory_size),
-            config: config.clone(),
-        };
-
-        // Load the parameters if it exists.
-        this.load(config.parameters.model_name);
-
-        Ok(this)
+            replay_buffer,
+            checkpoints,
+            config,
+            rng: StdRng::seed_from_u64(seed),
+            seed,
+            iteration: 0,
+            training_step: 0,
+            metrics_log: MetricsLog::new("plots"),
+            tensorboard: None,
+        })
     }
 
-    pub fn train(&mut self, iteration_count: usize) -> Result<(), Status> {
-        let parallel_mcts_executor = ParallelMCTSExecutor::new();
-        let mut rng = thread_rng();
+    /// Runs the iterations until `iteration_count` of them are finished, each of which plays `episode_count` games and then updates the parameters.
+    /// If `self_play` is not set, the iterations wait for as many new games from [Trainer::self_play_worker] processes instead.
+    /// The [TrainerState] is saved after each iteration.
+    pub fn train(&mut self, self_play: bool) -> Result<(), TrainerError> {
+        let parallel_mcts_executor = if self.config.deterministic {
+            ParallelMCTSExecutor::with_num_threads(1)
+        } else {
+            ParallelMCTSExecutor::new()
+        };
         let mut recent_losses = VecDeque::with_capacity(100);
 
-        for iteration in 0..iteration_count {
-            println!("========================================");
-            println!("[iter={}] Entering self-play phase.", iteration + 1);
+        if self.config.tensorboard.enabled && self.tensorboard.is_none() {
+            let writer = EventWriter::create(
+                Path::new(&self.config.tensorboard.log_dir).join(&self.config.model_name),
+            )?;
+            println!("Writing TensorBoard events to {:?}.", writer.path);
+            self.tensorboard = Some(writer);
+        }
 
-            // Empty the replay memory.
-            self.replay_memory.clear();
+        if self_play {
+            // The chunks written after the resumed state by an unfinished iteration join the window, as the new chunks follow them.
+            // Without self-play, the waiting loop reads them as the games of the first iteration instead.
+            self.replay_buffer.refresh()?;
+        }
 
-            let mut finished_episode_count = 0usize;
-            let mut agents = Vec::with_capacity(self.config.parameters.episode_count);
-            let mut turn_counts = vec![0; self.config.parameters.episode_count];
-            let mut transitions = Vec::with_capacity(self.config.parameters.episode_count);
-            let mut transition_indices = Vec::from_iter(0..self.config.parameters.episode_count);
+        if self.checkpoints.best()?.is_none() {
+            // The games are played by the best model, which is the initial one until a trained one is promoted.
+            let checkpoint_iteration = match self.checkpoints.latest()? {
+                Some(checkpoint_iteration) => checkpoint_iteration,
+                None => {
+                    self.save(0)?;
+                    0
+                }
+            };
+            self.checkpoints.set_best(checkpoint_iteration)?;
+        }
 
-            for _ in 0..self.config.parameters.episode_count {
-                agents.push(Agent::new(&self.agent_model, &self.session)?);
-                transitions.push(Vec::with_capacity(64));
-            }
+        for iteration in self.iteration as usize..self.config.training.iteration_count {
+            self.rng = StdRng::seed_from_u64(self.seed.wrapping_add(iteration as u64));
 
-            while !agents.is_empty() {
-                parallel_mcts_executor.execute(
-                    self.config.parameters.evaluate_count,
-                    self.config.parameters.evaluate_batch_size,
-                    self.config.parameters.epsilon,
-                    self.config.parameters.alpha,
-                    &self.agent_model,
-                    &self.session,
-                    &agents,
-                )?;
+            println!("========================================");
 
-                let mut index = 0;
-
-                while index < agents.len() {
-                    let agent = &mut agents[index];
-                    let turn_count = &mut turn_counts[index];
-                    let transitions = &mut transitions[transition_indices[index]];
-
-                    let (action, policy) = agent
-                        .sample_action(
-                            if *turn_count < self.config.parameters.temperature_threshold {
-                                ActionSamplingMode::Boltzmann(self.config.parameters.temperature)
-                            } else {
-                                ActionSamplingMode::Best
-                            },
-                        )
-                        .unwrap();
-
-                    *turn_count += 1;
-
-                    // Clone the environment to store.
-                    // This is required because we have to store the environment
-                    // before the action is applied.
-                    let env_before_action = agent.env.clone();
-
-                    // Play the action.
-                    let (z, is_terminal) = match agent.play_action(action).unwrap() {
-                        GameStatus::InProgress => (0f32, false),
-                        GameStatus::Draw => (0f32, true),
-                        GameStatus::BlackWin => (1f32, true),
-                        GameStatus::WhiteWin => (1f32, true),
-                    };
-
-                    transitions.push(Transition {
-                        env: env_before_action,
-                        policy,
-                        z,
-                    });
-
-                    if is_terminal {
-                        // We have to put the last transition into the replay memory, in perspective of loser.
-                        transitions.push(Transition {
-                            env: agent.env.clone(),
-                            policy: [1f32
-                                / (Environment::BOARD_SIZE * Environment::BOARD_SIZE) as f32;
-                                Environment::BOARD_SIZE * Environment::BOARD_SIZE],
-                            z: -z,
-                        });
-
-                        finished_episode_count += 1;
-
-                        agents.swap_remove(index);
-                        turn_counts.swap_remove(index);
-                        transition_indices.swap_remove(index);
-
-                        print!(
-                            "\r[iter={}] Self-playing... [episode={}/{}]",
-                            iteration + 1,
-                            finished_episode_count,
-                            self.config.parameters.episode_count
-                        );
-                        std::io::stdout().flush().unwrap();
-
-                        continue;
-                    }
+            let mut metrics = IterationMetrics {
+                iteration: iteration as u64 + 1,
+                ..Default::default()
+            };
+            let games_start = Instant::now();
 
-                    index += 1;
-                }
-            }
+            if self_play {
+                self.load_best_model(iteration)?;
 
-            // Update z in the game history, so that the agent can learn from it.
-            for mut transitions in transitions.into_iter() {
-                let mut z = transitions.last().unwrap().z;
+                let (games, node_count) = self.self_play(iteration, &parallel_mcts_executor)?;
+                let seconds = games_start.elapsed().as_secs_f32();
+                metrics.record_games(&games, seconds);
+                metrics.nodes_per_second = Some(node_count as f32 / seconds);
+                self.replay_buffer.append(games)?;
+            } else {
+                println!("[iter={}] Waiting for self-play games.", iteration + 1);
 
-                for transition in transitions.iter_mut().rev() {
-                    transition.z = z;
-                    z = -z;
-                }
+                let mut game_count = 0;
 
-                // Augment the replay memory, by rotating and flipping the board.
-                // We can generate extra 5 boards from one board.
-                // 3 from rotation, and 2 from flipping.
-                let mut augmented_replay_memory = Vec::with_capacity(transitions.len() * 5);
-
-                for transition in transitions.iter() {
-                    augmented_replay_memory.push(Transition {
-                        env: {
-                            let mut env = transition.env.clone();
-                            rotate_90(
-                                &transition.env.board,
-                                &mut env.board,
-                                Environment::BOARD_SIZE,
-                            );
-                            env
-                        },
-                        policy: {
-                            let mut policy =
-                                [0f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE];
-                            rotate_90(&transition.policy, &mut policy, Environment::BOARD_SIZE);
-                            policy
-                        },
-                        z: transition.z,
-                    });
-                    augmented_replay_memory.push(Transition {
-                        env: {
-                            let mut env = transition.env.clone();
-                            rotate_180(
-                                &transition.env.board,
-                                &mut env.board,
-                                Environment::BOARD_SIZE,
-                            );
-                            env
-                        },
-                        policy: {
-                            let mut policy =
-                                [0f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE];
-                            rotate_180(&transition.policy, &mut policy, Environment::BOARD_SIZE);
-                            policy
-                        },
-                        z: transition.z,
-                    });
-                    augmented_replay_memory.push(Transition {
-                        env: {
-                            let mut env = transition.env.clone();
-                            rotate_270(
-                                &transition.env.board,
-                                &mut env.board,
-                                Environment::BOARD_SIZE,
-                            );
-                            env
-                        },
-                        policy: {
-                            let mut policy =
-                                [0f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE];
-                            rotate_270(&transition.policy, &mut policy, Environment::BOARD_SIZE);
-                            policy
-                        },
-                        z: transition.z,
-                    });
-                    augmented_replay_memory.push(Transition {
-                        env: {
-                            let mut env = transition.env.clone();
-                            flip_horizontal(
-                                &transition.env.board,
-                                &mut env.board,
-                                Environment::BOARD_SIZE,
-                            );
-                            env
-                        },
-                        policy: {
-                            let mut policy =
-                                [0f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE];
-                            flip_horizontal(
-                                &transition.policy,
-                                &mut policy,
-                                Environment::BOARD_SIZE,
-                            );
-                            policy
-                        },
-                        z: transition.z,
-                    });
-                    augmented_replay_memory.push(Transition {
-                        env: {
-                            let mut env = transition.env.clone();
-                            flip_vertical(
-                                &transition.env.board,
-                                &mut env.board,
-                                Environment::BOARD_SIZE,
-                            );
-                            env
-                        },
-                        policy: {
-                            let mut policy =
-                                [0f32; Environment::BOARD_SIZE * Environment::BOARD_SIZE];
-                            flip_vertical(&transition.policy, &mut policy, Environment::BOARD_SIZE);
-                            policy
-                        },
-                        z: transition.z,
-                    });
+                loop {
+                    game_count += self.replay_buffer.refresh()?;
+
+                    if self.config.selfplay.episode_count <= game_count {
+                        break;
+                    }
+
+                    sleep(Self::POLL_INTERVAL);
                 }
 
-                self.replay_memory.extend(transitions);
-                self.replay_memory.extend(augmented_replay_memory);
-            }
+                // The received games are the newest ones of the window, unless they do not fit in it.
+                metrics.record_games(
+                    self.replay_buffer.games.iter().rev().take(game_count),
+                    games_start.elapsed().as_secs_f32(),
+                );
 
-            while self.config.parameters.replay_memory_size < self.replay_memory.len() {
-                self.replay_memory.pop_front();
+                print!("[iter={}] Received {} games.", iteration + 1, game_count);
             }
 
             println!();
             println!("[iter={}] Entering training phase.", iteration + 1);
 
-            for _ in 0..self.config.parameters.parameter_update_count {
-                let transitions = self
-                    .replay_memory
-                    .iter()
-                    .choose_multiple(&mut rng, self.config.parameters.parameter_update_batch_size);
+            for _ in 0..self.config.training.parameter_update_count {
+                let transitions = self.replay_buffer.sample(
+                    &mut self.rng,
+                    self.config.training.parameter_update_batch_size,
+                );
 
                 debug_assert!(!transitions.is_empty());
 
                 let input = encode_nn_input(
+                    &self.config.network.features,
                     transitions.len(),
                     EnvTurnMode::Player,
-                    transitions.iter().map(|&transition| &transition.env),
+                    transitions.iter().map(|transition| &transition.env),
                 );
                 let (policy_target, value_target) = encode_nn_targets(
                     transitions.len(),
-                    transitions.iter().map(|&transition| &transition.policy),
-                    transitions.iter().map(|&transition| transition.z),
+                    transitions.iter().map(|transition| &transition.policy),
+                    transitions.iter().map(|transition| transition.z),
+                );
+                let reply_policy_target = encode_nn_board_targets(
+                    transitions.len(),
+                    transitions
+                        .iter()
+                        .map(|transition| &transition.reply_policy),
+                );
+                let ownership_target = encode_nn_board_targets(
+                    transitions.len(),
+                    transitions.iter().map(|transition| &transition.ownership),
                 );
 
-                let (policy_loss, value_loss, loss) =
-                    self.agent_model
-                        .train(&self.session, input, policy_target, value_target)?;
+                let board_dims = [
+                    transitions.len() as u64,
+                    Environment::BOARD_SIZE as u64,
+                    Environment::BOARD_SIZE as u64,
+                ];
+                let (policy_loss, value_loss, loss) = self.agent_model.train(
+                    &self.session,
+                    self.training_step,
+                    self.input_tensor(transitions.len(), &input)?,
+                    Tensor::new(&board_dims).with_values(&policy_target)?,
+                    Tensor::new(&[transitions.len() as u64, 1]).with_values(&value_target)?,
+                    Tensor::new(&board_dims).with_values(&reply_policy_target)?,
+                    Tensor::new(&board_dims).with_values(&ownership_target)?,
+                )?;
+                self.training_step += 1;
 
                 recent_losses.push_back((value_loss, policy_loss, loss));
 
@@ -328,19 +395,49 @@ impl Trainer {
             );
 
             println!(
-                "[iter={}] Loss: {} [v_loss={:.4}, p_loss={:.4}]",
+                "[iter={}] Loss: {} [v_loss={:.4}, p_loss={:.4}, lr={:.6}]",
                 iteration + 1,
                 loss,
                 v_loss,
                 p_loss,
+                self.config
+                    .training
+                    .optimizer
+                    .learning_rate(self.training_step),
             );
 
+            let (policy_entropy, value_accuracy) = self.evaluate_sample()?;
+            metrics.value_loss = v_loss;
+            metrics.policy_loss = p_loss;
+            metrics.loss = loss;
+            metrics.learning_rate = self
+                .config
+                .training
+                .optimizer
+                .learning_rate(self.training_step);
+            metrics.policy_entropy = policy_entropy;
+            metrics.value_accuracy = value_accuracy;
+
             self.plotter.add_loss((v_loss, p_loss, loss));
-            self.plotter.save("losses").unwrap();
+            self.plotter.save("losses")?;
             self.plotter.draw_plot("plots/loss.svg");
 
-            self.save(self.config.parameters.model_name.clone());
-            println!("[iter={}] Model saved.", iteration + 1);
+            // The checkpoints are numbered across runs, following the latest one.
+            let checkpoint_iteration = self
+                .checkpoints
+                .latest()?
+                .map_or(1, |checkpoint_iteration| checkpoint_iteration + 1);
+            self.save(checkpoint_iteration)?;
+            println!(
+                "[iter={}] Model saved. [checkpoint={}]",
+                iteration + 1,
+                checkpoint_iteration
+            );
+
+            metrics.gating_score = self
+                .gate(iteration, checkpoint_iteration)?
+                .map(|result| result.score());
+            self.checkpoints.retain(&self.config.training.checkpoints)?;
 
             if iteration % 10 == 0 {
                 println!(
@@ -359,35 +456,354 @@ impl Trainer {
                     white_win,
                     draw
                 );
+
+                // The trained model plays black.
+                metrics.random_score = Some(
+                    MatchResult {
+                        wins: black_win as usize,
+                        losses: white_win as usize,
+                        draws: draw as usize,
+                    }
+                    .score(),
+                );
             }
+
+            metrics.training_step = self.training_step;
+            metrics.timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            metrics.replay_game_count = self.replay_buffer.games.len();
+            metrics.replay_position_count = self.replay_buffer.position_count;
+            self.metrics_log.append(&metrics)?;
+            self.write_summaries(iteration, &metrics)?;
+
+            self.iteration = iteration as u64 + 1;
+            self.save_state()?;
         }
 
         Ok(())
     }
 
+    /// Plays games with the best saved model and appends them to the replay buffer, until the process is stopped.
+    /// Several of these processes can feed one running [Trainer::train] without self-play, through the `saves` and `replays` directories.
+    /// It should be created by [Trainer::new_self_play_worker], and it waits until the trainer has saved a best model.
+    pub fn self_play_worker(&mut self) -> Result<(), TrainerError> {
+        let parallel_mcts_executor = if self.config.deterministic {
+            ParallelMCTSExecutor::with_num_threads(1)
+        } else {
+            ParallelMCTSExecutor::new()
+        };
+        let mut is_waiting = false;
+        let mut iteration = 0;
+
+        loop {
+            // Games of the randomly initialized model would only fill the replay buffer with noise.
+            if !self.load_best_model(iteration)? {
+                if !is_waiting {
+                    println!("Waiting for the trainer to save a model.");
+                    is_waiting = true;
+                }
+
+                sleep(Self::POLL_INTERVAL);
+                continue;
+            }
+
+            self.rng = StdRng::seed_from_u64(self.seed.wrapping_add(iteration as u64));
+
+            let (games, _) = self.self_play(iteration, &parallel_mcts_executor)?;
+            self.replay_buffer.append(games)?;
+
+            println!();
+            println!("[iter={}] Games saved.", iteration + 1);
+
+            iteration += 1;
+        }
+    }
+
+    /// Loads the best saved model into the self-play session if it has changed, and returns whether any model has been loaded.
+    fn load_best_model(&mut self, iteration: usize) -> Result<bool, TrainerError> {
+        let best = self.checkpoints.best()?;
+
+        if let Some(checkpoint_iteration) =
+            best.filter(|&best| Some(best) != self.self_play_checkpoint)
+        {
+            match self.agent_model.io.load(
+                &self.self_play_session,
+                self.checkpoints.checkpoint_path(checkpoint_iteration),
+            ) {
+                Ok(header) => {
+                    self.self_play_checkpoint = best;
+                    println!(
+                        "[iter={}] Loaded the best model for self-play. [checkpoint={}, step={}]",
+                        iteration + 1,
+                        checkpoint_iteration,
+                        header.training_step
+                    );
+                }
+                // The retention policy may have deleted the checkpoint in the meantime; the new best one is loaded in the next iteration.
+                Err(error) => {
+                    println!(
+                        "[iter={}] Failed to load the best model: {}",
+                        iteration + 1,
+                        error
+                    )
+                }
+            }
+        }
+
+        Ok(self.self_play_checkpoint.is_some())
+    }
+
+    /// Plays `episode_count` games with the best model, returning them and the number of nodes expanded by the search.
+    fn self_play(
+        &mut self,
+        iteration: usize,
+        parallel_mcts_executor: &ParallelMCTSExecutor,
+    ) -> Result<(Vec<GameRecord>, usize), InferenceError> {
+        println!("[iter={}] Entering self-play phase.", iteration + 1);
+
+        let mut finished_episode_count = 0usize;
+        let mut agents = Vec::with_capacity(self.config.selfplay.episode_count);
+        let mut turn_counts = vec![0; self.config.selfplay.episode_count];
+        let mut games = Vec::with_capacity(self.config.selfplay.episode_count);
+        let mut game_indices = Vec::from_iter(0..self.config.selfplay.episode_count);
+        let mut node_count = 0;
+        let backend = TensorflowBackend::new(&self.agent_model, &self.self_play_session);
+
+        for _ in 0..self.config.selfplay.episode_count {
+            agents.push(Agent::with_seed(&backend, self.rng.gen())?);
+            games.push(GameRecord::new());
+        }
+
+        while !agents.is_empty() {
+            let node_count_before = agents
+                .iter()
+                .map(|agent| agent.mcts.node_count())
+                .sum::<usize>();
+
+            parallel_mcts_executor.execute(
+                self.config.selfplay.evaluate_count,
+                self.config.selfplay.evaluate_batch_size,
+                self.config.selfplay.epsilon,
+                self.config.selfplay.alpha,
+                &backend,
+                &agents,
+            )?;
+
+            node_count += agents
+                .iter()
+                .map(|agent| agent.mcts.node_count())
+                .sum::<usize>()
+                .saturating_sub(node_count_before);
+
+            let mut index = 0;
+
+            while index < agents.len() {
+                let agent = &mut agents[index];
+                let turn_count = &mut turn_counts[index];
+                let game = &mut games[game_indices[index]];
+
+                let (action, policy) = agent
+                    .sample_action(
+                        if *turn_count < self.config.selfplay.temperature_threshold {
+                            ActionSamplingMode::Boltzmann(self.config.selfplay.temperature)
+                        } else {
+                            ActionSamplingMode::Best
+                        },
+                    )
+                    .unwrap();
+
+                *turn_count += 1;
+
+                // Play the action.
+                let status = agent.play_action(action).unwrap();
+                game.push(action, &policy);
+
+                if status.is_terminal() {
+                    game.status = status;
+
+                    finished_episode_count += 1;
+
+                    agents.swap_remove(index);
+                    turn_counts.swap_remove(index);
+                    game_indices.swap_remove(index);
+
+                    print!(
+                        "\r[iter={}] Self-playing... [episode={}/{}]",
+                        iteration + 1,
+                        finished_episode_count,
+                        self.config.selfplay.episode_count
+                    );
+                    std::io::stdout().flush().unwrap();
+
+                    continue;
+                }
+
+                index += 1;
+            }
+        }
+
+        Ok((games, node_count))
+    }
+
+    /// Plays the checkpoint of the given iteration against the best one, and makes it the best one if it scores high enough.
+    /// If there is no best checkpoint yet, or gating is disabled, it becomes the best one without playing, and no result is returned.
+    fn gate(
+        &mut self,
+        iteration: usize,
+        checkpoint_iteration: u64,
+    ) -> Result<Option<MatchResult>, TrainerError> {
+        let best_checkpoint_iteration = match self.checkpoints.best()? {
+            Some(best) if self.config.evaluation.gating.game_count != 0 => best,
+            _ => {
+                self.checkpoints.set_best(checkpoint_iteration)?;
+                return Ok(None);
+            }
+        };
+
+        println!("[iter={}] Playing against the best model.", iteration + 1);
+
+        let mut candidate =
+            benchmark::Agent::load(self.checkpoints.checkpoint_path(checkpoint_iteration))?;
+        let mut best =
+            benchmark::Agent::load(self.checkpoints.checkpoint_path(best_checkpoint_iteration))?;
+        candidate.opening_move_count = self.config.evaluation.gating.opening_move_count;
+        best.opening_move_count = self.config.evaluation.gating.opening_move_count;
+
+        let result = play_match(
+            &mut candidate,
+            &mut best,
+            self.config.evaluation.gating.game_count,
+            self.config.evaluation.gating.evaluate_count,
+            self.config.evaluation.evaluate_batch_size,
+        );
+        let promoted = self.config.evaluation.gating.score_threshold <= result.score();
+
+        println!(
+            "[iter={}] Win: {}, Lose: {}, Draw: {}, Score: {:.3} [{}]",
+            iteration + 1,
+            result.wins,
+            result.losses,
+            result.draws,
+            result.score(),
+            if promoted { "promoted" } else { "rejected" }
+        );
+
+        let log_path = Path::new("plots").join("gating.csv");
+        let is_new_log = !log_path.exists();
+        create_dir_all("plots")?;
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)?;
+
+        if is_new_log {
+            writeln!(
+                log,
+                "checkpoint,best_checkpoint,training_step,wins,losses,draws,score,promoted"
+            )?;
+        }
+
+        writeln!(
+            log,
+            "{},{},{},{},{},{},{},{}",
+            checkpoint_iteration,
+            best_checkpoint_iteration,
+            self.training_step,
+            result.wins,
+            result.losses,
+            result.draws,
+            result.score(),
+            promoted
+        )?;
+
+        if promoted {
+            self.checkpoints.set_best(checkpoint_iteration)?;
+        }
+
+        Ok(Some(result))
+    }
+
+    /// Evaluates the model on a batch sampled from the replay buffer, returning the mean entropy of its policy
+    /// and the ratio of the decisive positions whose value has the sign of the outcome, if there are any.
+    fn evaluate_sample(&mut self) -> Result<(f32, Option<f32>), Status> {
+        let transitions = self.replay_buffer.sample(
+            &mut self.rng,
+            self.config.training.parameter_update_batch_size,
+        );
+        let input = encode_nn_input(
+            &self.config.network.features,
+            transitions.len(),
+            EnvTurnMode::Player,
+            transitions.iter().map(|transition| &transition.env),
+        );
+        let (p, v) = self
+            .agent_model
+            .evaluate_pv(&self.session, self.input_tensor(transitions.len(), &input)?)?;
+
+        let entropy = p
+            .iter()
+            .filter(|&&p| f32::EPSILON < p)
+            .map(|&p| -p * p.ln())
+            .sum::<f32>()
+            / transitions.len() as f32;
+
+        let mut decisive_count = 0usize;
+        let mut correct_count = 0usize;
+
+        for (transition, &v) in transitions.iter().zip(v.iter()) {
+            if transition.z != 0f32 {
+                decisive_count += 1;
+
+                if 0f32 < transition.z * v {
+                    correct_count += 1;
+                }
+            }
+        }
+
+        let accuracy = if decisive_count == 0 {
+            None
+        } else {
+            Some(correct_count as f32 / decisive_count as f32)
+        };
+
+        Ok((entropy, accuracy))
+    }
+
+    /// Converts the output of [encode_nn_input] to the input of the model.
+    fn input_tensor(&self, batch_size: usize, input: &[f32]) -> Result<Tensor<f32>, Status> {
+        Tensor::new(&[
+            batch_size as u64,
+            Environment::BOARD_SIZE as u64,
+            Environment::BOARD_SIZE as u64,
+            self.config.network.features.channels() as u64,
+        ])
+        .with_values(input)
+    }
+
     fn play_against_random_player(
-        &self,
+        &mut self,
         episode_count: usize,
         parallel_mcts_executor: &ParallelMCTSExecutor,
-    ) -> Result<(u32, u32, u32), Status> {
-        let mut rng = thread_rng();
+    ) -> Result<(u32, u32, u32), InferenceError> {
         let mut black_win = 0u32;
         let mut white_win = 0u32;
         let mut draw = 0u32;
         let mut agents = Vec::with_capacity(episode_count);
+        let backend = TensorflowBackend::new(&self.agent_model, &self.session);
 
         for _ in 0..episode_count {
-            agents.push(Agent::new(&self.agent_model, &self.session)?);
+            agents.push(Agent::with_seed(&backend, self.rng.gen())?);
         }
 
         while !agents.is_empty() {
             parallel_mcts_executor.execute(
-                self.config.parameters.test_evaluate_count,
-                self.config.parameters.evaluate_batch_size,
-                self.config.parameters.epsilon,
-                self.config.parameters.alpha,
-                &self.agent_model,
-                &self.session,
+                self.config.evaluation.test_evaluate_count,
+                self.config.evaluation.evaluate_batch_size,
+                self.config.selfplay.epsilon,
+                self.config.selfplay.alpha,
+                &backend,
                 &agents,
             )?;
 
@@ -420,9 +836,9 @@ impl Trainer {
                 let legal_moves = (0..Environment::BOARD_SIZE * Environment::BOARD_SIZE)
                     .filter(|&action| agent.env.board[action] == Stone::Empty)
                     .collect::<Vec<_>>();
-                let random_action = legal_moves[rng.gen_range(0..legal_moves.len())];
+                let random_action = legal_moves[self.rng.gen_range(0..legal_moves.len())];
 
-                agent.ensure_action_exists(random_action, &self.agent_model, &self.session)?;
+                agent.ensure_action_exists(random_action, &backend)?;
 
                 let is_terminal = match agent.play_action(random_action).unwrap() {
                     GameStatus::InProgress => false,
@@ -452,36 +868,120 @@ impl Trainer {
         Ok((black_win, white_win, draw))
     }
 
-    pub fn save(&self, name: impl AsRef<Path>) {
-        let path_base = Path::new("saves");
-        let path_model = path_base.join(name);
-        if path_base.exists() {
-            if path_model.exists() {
-                if path_model.is_dir() {
-                    remove_dir_all(&path_model).unwrap();
-                } else {
-                    remove_file(&path_model).unwrap();
-                }
-            }
-        } else {
-            if !path_base.exists() {
-                create_dir_all(path_base).unwrap();
+    /// Writes the metrics, the histograms of the weights every `histogram_interval` iterations and the loss plot
+    /// to the TensorBoard event file, at the current training step. Nothing is written if TensorBoard is disabled.
+    fn write_summaries(
+        &mut self,
+        iteration: usize,
+        metrics: &IterationMetrics,
+    ) -> Result<(), TrainerError> {
+        let writer = match &mut self.tensorboard {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+        let step = self.training_step;
+
+        for (tag, value) in metrics.scalars() {
+            writer.add_scalar(tag, value, step)?;
+        }
+
+        // There are no histograms if the interval is 0.
+        if (iteration + 1).checked_rem(self.config.tensorboard.histogram_interval) == Some(0) {
+            let variables = self.agent_model.io.read_variables(&self.session)?;
+
+            for variable in variables {
+                writer.add_histogram(
+                    &format!("weights/{}", variable.name),
+                    &variable.values,
+                    step,
+                )?;
             }
         }
 
-        self.agent_model
-            .io
-            .save(&self.session, &path_model)
-            .unwrap();
+        let png = self.plotter.draw_png()?;
+        let (width, height) = Plotter::PLOT_SIZE;
+        writer.add_image("loss_plot", &png, width, height, step)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Saves the model as the checkpoint of the given iteration, which becomes the latest one.
+    pub fn save(&self, checkpoint_iteration: u64) -> Result<(), TrainerError> {
+        self.agent_model.io.save(
+            &self.session,
+            self.checkpoints.checkpoint_path(checkpoint_iteration),
+            self.training_step,
+            true,
+        )?;
+        self.checkpoints.set_latest(checkpoint_iteration)?;
+
+        Ok(())
+    }
+
+    /// Loads the latest checkpoint, if any.
+    pub fn load(&mut self) -> Result<(), TrainerError> {
+        if let Some(checkpoint_iteration) = self.checkpoints.latest()? {
+            self.load_checkpoint(checkpoint_iteration)?;
+        }
+
+        Ok(())
     }
 
-    pub fn load(&self, name: impl AsRef<Path>) {
-        let path = Path::new("saves").join(name);
+    fn load_checkpoint(&mut self, checkpoint_iteration: u64) -> Result<(), TrainerError> {
+        let header = self.agent_model.io.load(
+            &self.session,
+            self.checkpoints.checkpoint_path(checkpoint_iteration),
+        )?;
+        self.training_step = header.training_step;
+
+        Ok(())
+    }
 
-        if !path.exists() {
-            return;
+    /// Saves the state of the run next to its checkpoints, so that `train --resume` continues after the last finished iteration.
+    fn save_state(&self) -> Result<(), TrainerError> {
+        TrainerState {
+            iteration: self.iteration,
+            training_step: self.training_step,
+            seed: self.seed,
+            checkpoint: self.checkpoints.latest()?,
+            replay_chunk: self.replay_buffer.newest_chunk_index,
+            config: self.config.clone(),
         }
+        .save(&self.checkpoints.path)?;
+
+        Ok(())
+    }
+}
+
+/// Derives the seed of a self-play worker from the seed of the run.
+/// The ids are spread by the golden ratio increment of SplitMix64, so that the generators of the workers
+/// and of the trainer, which are reseeded with the iteration added, do not overlap.
+pub fn worker_seed(seed: u64, worker_id: u64) -> u64 {
+    seed.wrapping_add(
+        worker_id
+            .wrapping_add(1)
+            .wrapping_mul(0x9E37_79B9_7F4A_7C15),
+    )
+}
 
-        self.agent_model.io.load(&self.session, &path).unwrap();
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_worker_seed() {
+        let mut seeds = (0..100)
+            .map(|worker_id| worker_seed(42, worker_id))
+            .collect::<Vec<_>>();
+        seeds.push(42);
+
+        // No two of the trainer and the workers are within a long run of iterations of each other.
+        for (index, &seed) in seeds.iter().enumerate() {
+            for &other in &seeds[index + 1..] {
+                assert!(1 << 20 < seed.wrapping_sub(other));
+                assert!(1 << 20 < other.wrapping_sub(seed));
+            }
+        }
     }
 }
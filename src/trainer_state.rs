@@ -0,0 +1,92 @@
+use crate::config::Config;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{read_to_string, rename, write},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum TrainerStateError {
+    #[error("io error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("failed to parse the trainer state: {0}")]
+    Deserialize(#[from] toml::de::Error),
+    #[error("failed to write the trainer state: {0}")]
+    Serialize(#[from] toml::ser::Error),
+}
+
+/// Everything besides the weights that a training run needs to continue where it stopped, written after each iteration.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TrainerState {
+    /// Number of iterations finished over all runs.
+    pub iteration: u64,
+    /// Number of parameter updates performed on the model.
+    pub training_step: u64,
+    /// Seed of the run. Each iteration reseeds the generator from it and the iteration number.
+    pub seed: u64,
+    /// Latest checkpoint at the end of the iteration.
+    pub checkpoint: Option<u64>,
+    /// Newest replay chunk in the window at the end of the iteration. The later chunks are treated as new games.
+    pub replay_chunk: Option<u64>,
+    /// Configuration of the run, which a resumed run uses instead of the config file.
+    pub config: Config,
+}
+
+impl TrainerState {
+    pub const FILE_NAME: &str = "trainer_state.toml";
+
+    /// Reads the state stored in the directory `path`, if any.
+    pub fn load(path: impl AsRef<Path>) -> Result<Option<Self>, TrainerStateError> {
+        let path = path.as_ref().join(Self::FILE_NAME);
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        Ok(Some(toml::from_str(&read_to_string(path)?)?))
+    }
+
+    /// Writes the state into the directory `path`, replacing the previous one at once.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), TrainerStateError> {
+        let path = path.as_ref().join(Self::FILE_NAME);
+        let temporary_path = PathBuf::from(format!("{}.tmp", path.display()));
+        write(&temporary_path, toml::to_string(self)?)?;
+        rename(&temporary_path, &path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_trainer_state_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path();
+        assert!(TrainerState::load(path).unwrap().is_none());
+
+        let mut config = Config::default();
+        config.selfplay.episode_count = 7;
+        let state = TrainerState {
+            iteration: 12,
+            training_step: 7200,
+            seed: 42,
+            checkpoint: Some(12),
+            replay_chunk: None,
+            config,
+        };
+        state.save(path).unwrap();
+
+        let loaded = TrainerState::load(path).unwrap().unwrap();
+
+        assert_eq!(loaded.iteration, 12);
+        assert_eq!(loaded.training_step, 7200);
+        assert_eq!(loaded.seed, 42);
+        assert_eq!(loaded.checkpoint, Some(12));
+        assert_eq!(loaded.replay_chunk, None);
+        assert_eq!(loaded.config.selfplay.episode_count, 7);
+    }
+}